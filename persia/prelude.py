@@ -44,6 +44,7 @@ from persia_core.utils import (
     PersiaBatchDataReceiver,
 )
 from persia_core.nats import initialize_dataflow  # noqa
+from persia_core.holder import EmbeddingHolder  # noqa
 
 from persia_core.backward import Backward  # noqa
 from persia_core.forward import Forward, Tensor, PersiaTrainingBatch  # noqa
@@ -0,0 +1,271 @@
+#![feature(custom_test_frameworks)]
+#![test_runner(criterion::runner)]
+
+use criterion::*;
+use criterion_macro::criterion;
+
+use persia_embedding_config::InitializationMethod;
+use persia_embedding_holder::{
+    arena::ArenaEntryFactory,
+    emb_entry::HashMapEmbeddingEntry,
+    entry_pool::{EntryPool, PooledEntry},
+    eviction_map::EvictionMap,
+    map_backend::{DefaultMapBackend, MapBackend, StdMapBackend},
+    PersiaEmbeddingHolder,
+};
+use persia_speedy::Writable;
+
+#[cfg(feature = "dashmap")]
+use persia_embedding_holder::map_backend::DashMapBackend;
+
+const BATCH_SIZE: u64 = 10_000;
+const DIM: usize = 32;
+
+#[criterion]
+fn bench_batched_lookup(c: &mut Criterion) {
+    let initialization = InitializationMethod::default();
+    let signs: Vec<u64> = (0..BATCH_SIZE).collect();
+    let mut group = c.benchmark_group("batched_lookup");
+    group.throughput(Throughput::Elements(BATCH_SIZE));
+    group.bench_function("single_key_loop", |b| {
+        b.iter_batched(
+            || PersiaEmbeddingHolder::new(1_000_000, 100),
+            |holder| {
+                signs.iter().for_each(|sign| {
+                    let mut shard = holder.shard(sign).write();
+                    if shard.get(sign).is_none() {
+                        let entry =
+                            HashMapEmbeddingEntry::new(&initialization, DIM, DIM, *sign, *sign);
+                        let _ = shard.insert(*sign, entry);
+                    }
+                });
+                holder
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("get_or_init_many", |b| {
+        b.iter_batched(
+            || PersiaEmbeddingHolder::new(1_000_000, 100),
+            |holder| {
+                black_box(holder.get_or_init_many(&signs, &initialization, DIM, DIM, 0));
+                holder
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+#[criterion]
+fn bench_cold_start(c: &mut Criterion) {
+    const NUM_SIGNS: u64 = 1_000_000;
+    const CAPACITY: usize = 100_000_000;
+    let initialization = InitializationMethod::default();
+    let signs: Vec<u64> = (0..NUM_SIGNS).collect();
+    let mut group = c.benchmark_group("cold_start");
+    group.throughput(Throughput::Elements(NUM_SIGNS));
+    group.sample_size(10);
+    group.bench_function("grow_on_demand", |b| {
+        b.iter_batched(
+            || PersiaEmbeddingHolder::with_capacity(CAPACITY, 100, 0),
+            |holder| {
+                black_box(holder.get_or_init_many(&signs, &initialization, DIM, DIM, 0));
+                holder
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("pre_sized", |b| {
+        b.iter_batched(
+            || PersiaEmbeddingHolder::with_capacity(CAPACITY, 100, NUM_SIGNS as usize),
+            |holder| {
+                black_box(holder.get_or_init_many(&signs, &initialization, DIM, DIM, 0));
+                holder
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+#[criterion]
+fn bench_concurrent_get_or_init(c: &mut Criterion) {
+    const NUM_THREADS: u64 = 8;
+    let initialization = InitializationMethod::default();
+    let mut group = c.benchmark_group("concurrent_get_or_init");
+    group.throughput(Throughput::Elements(BATCH_SIZE * NUM_THREADS));
+    for num_internal_shards in [1, 4, 16, 64] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_internal_shards),
+            &num_internal_shards,
+            |b, num_internal_shards| {
+                let holder = PersiaEmbeddingHolder::new(1_000_000, *num_internal_shards);
+                b.iter(|| {
+                    let handles: Vec<_> = (0..NUM_THREADS)
+                        .map(|thread_idx| {
+                            let holder = holder.clone();
+                            let initialization = initialization.clone();
+                            std::thread::spawn(move || {
+                                (0..BATCH_SIZE).for_each(|x| {
+                                    let sign = x * NUM_THREADS + thread_idx;
+                                    black_box(holder.get_or_init(
+                                        sign,
+                                        &initialization,
+                                        DIM,
+                                        DIM,
+                                        0,
+                                    ));
+                                })
+                            })
+                        })
+                        .collect();
+                    handles.into_iter().for_each(|x| x.join().unwrap());
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+#[criterion]
+fn bench_compressed_entry(c: &mut Criterion) {
+    let initialization = InitializationMethod::default();
+    let entries: Vec<HashMapEmbeddingEntry> = (0..BATCH_SIZE)
+        .map(|sign| {
+            let entry = HashMapEmbeddingEntry::new(&initialization, DIM, 0, sign, sign);
+            let (data, scale) = entry.quantize_int8();
+            HashMapEmbeddingEntry::from_int8(&data, scale, sign)
+        })
+        .collect();
+
+    let raw_len: usize = entries
+        .iter()
+        .map(|x| x.write_to_vec().unwrap().len())
+        .sum();
+    for level in [0, 9] {
+        let mut buffer = Vec::new();
+        entries
+            .iter()
+            .for_each(|x| x.write_compressed(&mut buffer, level).unwrap());
+        println!(
+            "compression level {}: {} -> {} bytes, ratio {:.3}",
+            level,
+            raw_len,
+            buffer.len(),
+            raw_len as f64 / buffer.len() as f64
+        );
+    }
+
+    let mut group = c.benchmark_group("compressed_entry");
+    group.throughput(Throughput::Elements(BATCH_SIZE));
+    group.bench_function("write_compressed", |b| {
+        b.iter(|| {
+            let mut buffer = Vec::new();
+            entries
+                .iter()
+                .for_each(|x| x.write_compressed(&mut buffer, 0).unwrap());
+            black_box(buffer)
+        })
+    });
+    group.finish();
+}
+
+#[criterion]
+fn bench_dot(c: &mut Criterion) {
+    let initialization = InitializationMethod::default();
+    let entries: Vec<HashMapEmbeddingEntry> = (0..BATCH_SIZE)
+        .map(|sign| HashMapEmbeddingEntry::new(&initialization, DIM, 0, sign, sign))
+        .collect();
+    let query = entries[0].emb().to_vec();
+
+    let mut group = c.benchmark_group("dot");
+    group.throughput(Throughput::Elements(BATCH_SIZE));
+    group.bench_function("naive_loop", |b| {
+        b.iter(|| {
+            entries
+                .iter()
+                .map(|x| {
+                    x.emb()
+                        .iter()
+                        .zip(query.iter())
+                        .map(|(a, b)| a * b)
+                        .sum::<f32>()
+                })
+                .sum::<f32>()
+        })
+    });
+    group.bench_function("dot_avx2", |b| {
+        b.iter(|| entries.iter().map(|x| x.dot(&query).unwrap()).sum::<f32>())
+    });
+    let aligned_entries: Vec<HashMapEmbeddingEntry> =
+        entries.iter().map(|x| x.clone().into_aligned()).collect();
+    group.bench_function("dot_avx2_aligned", |b| {
+        b.iter(|| {
+            aligned_entries
+                .iter()
+                .map(|x| x.dot(&query).unwrap())
+                .sum::<f32>()
+        })
+    });
+    group.finish();
+}
+
+#[criterion]
+fn bench_entry_pool(c: &mut Criterion) {
+    const ROWS: usize = 1_000_000;
+    let mut group = c.benchmark_group("entry_allocation");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.sample_size(10);
+    group.bench_function("vec", |b| {
+        b.iter(|| {
+            let rows: Vec<Vec<f32>> = (0..ROWS).map(|_| vec![0f32; DIM * 2]).collect();
+            black_box(rows)
+        })
+    });
+    group.bench_function("entry_pool", |b| {
+        b.iter(|| {
+            let pool = EntryPool::new(DIM * 2, 4096);
+            let rows: Vec<PooledEntry> = (0..ROWS).map(|x| pool.acquire(x as u64)).collect();
+            black_box(rows)
+        })
+    });
+    group.bench_function("arena", |b| {
+        let mut factory = ArenaEntryFactory::new();
+        b.iter(|| {
+            factory.reset();
+            let rows: Vec<&mut [f32]> = (0..ROWS).map(|_| factory.alloc_entry(DIM, DIM)).collect();
+            black_box(rows.len())
+        })
+    });
+    group.finish();
+}
+
+fn insert_and_lookup<M: MapBackend<u64>>(signs: &[u64]) -> usize {
+    let mut map: EvictionMap<u64, HashMapEmbeddingEntry, M> =
+        EvictionMap::with_backend(signs.len(), signs.len());
+    signs.iter().for_each(|sign| {
+        let _ = map.insert(*sign, HashMapEmbeddingEntry::from_emb(vec![0.0; 4], *sign));
+    });
+    signs.iter().filter(|sign| map.get(sign).is_some()).count()
+}
+
+#[criterion]
+fn bench_map_backend(c: &mut Criterion) {
+    let signs: Vec<u64> = (0..BATCH_SIZE)
+        .map(|x| x.wrapping_mul(0x9E3779B97F4A7C15))
+        .collect();
+    let mut group = c.benchmark_group("map_backend");
+    group.throughput(Throughput::Elements(BATCH_SIZE));
+    group.bench_function("hashbrown", |b| {
+        b.iter(|| black_box(insert_and_lookup::<DefaultMapBackend<u64>>(&signs)))
+    });
+    group.bench_function("std", |b| {
+        b.iter(|| black_box(insert_and_lookup::<StdMapBackend<u64>>(&signs)))
+    });
+    #[cfg(feature = "dashmap")]
+    group.bench_function("dashmap", |b| {
+        b.iter(|| black_box(insert_and_lookup::<DashMapBackend<u64>>(&signs)))
+    });
+    group.finish();
+}
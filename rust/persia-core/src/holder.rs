@@ -0,0 +1,62 @@
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+
+use persia_embedding_holder::{emb_entry::HashMapEmbeddingEntry, PersiaEmbeddingHolder};
+
+/// Read access to an embedding holder living in the current process, for inspecting
+/// embeddings from python.
+#[pyclass]
+pub struct EmbeddingHolder {
+    inner: PersiaEmbeddingHolder,
+}
+
+#[pymethods]
+impl EmbeddingHolder {
+    #[new]
+    pub fn new(capacity: usize, num_internal_shards: usize) -> Self {
+        Self {
+            inner: PersiaEmbeddingHolder::new(capacity, num_internal_shards),
+        }
+    }
+
+    /// The holder configured by the embedding parameter server config of this process.
+    #[staticmethod]
+    pub fn global() -> PyResult<Self> {
+        let inner =
+            PersiaEmbeddingHolder::get().map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    pub fn set_embedding(&self, sign: u64, embedding: Vec<f32>, optimizer_state: Vec<f32>) {
+        let entry =
+            HashMapEmbeddingEntry::from_emb_and_opt(embedding, optimizer_state.as_slice(), sign);
+        let _ = self.inner.shard(&sign).write().insert(sign, entry);
+    }
+
+    pub fn get_embedding(&self, sign: u64) -> Option<Vec<f32>> {
+        let shard = self.inner.shard(&sign).read();
+        shard
+            .get(&sign)
+            .filter(|entry| !entry.is_tombstoned())
+            .map(|entry| entry.emb().to_vec())
+    }
+
+    pub fn get_optimizer_state(&self, sign: u64) -> Option<Vec<f32>> {
+        let shard = self.inner.shard(&sign).read();
+        shard
+            .get(&sign)
+            .filter(|entry| !entry.is_tombstoned())
+            .map(|entry| entry.opt().to_vec())
+    }
+
+    pub fn num_total_signs(&self) -> usize {
+        self.inner.num_total_signs()
+    }
+}
+
+pub fn init_module(super_module: &PyModule, py: Python) -> PyResult<()> {
+    let module = PyModule::new(py, "holder")?;
+    module.add_class::<EmbeddingHolder>()?;
+    super_module.add_submodule(module)?;
+    Ok(())
+}
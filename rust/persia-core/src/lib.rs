@@ -7,6 +7,7 @@ mod backward;
 mod data;
 mod dlpack;
 mod forward;
+mod holder;
 mod metrics;
 mod nats;
 mod optim;
@@ -472,6 +473,7 @@ fn persia_core(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PersiaCommonContext>()?;
 
     forward::init_module(m, py)?;
+    holder::init_module(m, py)?;
     backward::init_module(m, py)?;
     data::init_module(m, py)?;
     utils::init_module(m, py)?;
@@ -5,13 +5,14 @@ use std::{collections::HashMap, path::PathBuf, sync::Arc};
 use persia_libs::{
     indexmap,
     once_cell::sync::OnceCell,
-    serde::{self, Deserialize, Serialize},
+    rand::rngs::SmallRng,
+    serde::{self, Deserialize, Deserializer, Serialize, Serializer},
     serde_yaml,
     thiserror::Error,
     tracing,
 };
 
-use persia_speedy::{Readable, Writable};
+use persia_speedy::{Context, Readable, Writable};
 
 #[derive(Readable, Writable, Error, Debug, Clone)]
 pub enum PersiaGlobalConfigError {
@@ -77,6 +78,250 @@ impl BoundedNormalInitialization {
     }
 }
 
+/// Normal distribution with samples outside `mean ± truncate_sigma * standard_deviation`
+/// redrawn. `truncate_sigma` has to be positive.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct TruncatedNormalInitialization {
+    pub mean: f32,
+    pub standard_deviation: f32,
+    pub truncate_sigma: f32,
+}
+
+impl TruncatedNormalInitialization {
+    pub fn new(mean: f32, standard_deviation: f32, truncate_sigma: f32) -> Self {
+        TruncatedNormalInitialization {
+            mean,
+            standard_deviation,
+            truncate_sigma,
+        }
+    }
+}
+
+/// Beta distribution, samples lie in `[0, 1]`. Both `alpha` and `beta` must be positive.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct BoundedBetaInitialization {
+    pub alpha: f32,
+    pub beta: f32,
+}
+
+impl BoundedBetaInitialization {
+    pub fn new(alpha: f32, beta: f32) -> Self {
+        BoundedBetaInitialization { alpha, beta }
+    }
+}
+
+/// Exponential distribution with rate `lambda`, samples are non negative with mean `1 / lambda`.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct BoundedExponentialInitialization {
+    pub lambda: f32,
+}
+
+impl BoundedExponentialInitialization {
+    pub fn new(lambda: f32) -> Self {
+        BoundedExponentialInitialization { lambda }
+    }
+}
+
+/// Log normal distribution, the logarithm of the samples is normal with mean `mu` and standard
+/// deviation `sigma`. Samples are strictly positive.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct BoundedLogNormalInitialization {
+    pub mu: f32,
+    pub sigma: f32,
+}
+
+impl BoundedLogNormalInitialization {
+    pub fn new(mu: f32, sigma: f32) -> Self {
+        BoundedLogNormalInitialization { mu, sigma }
+    }
+}
+
+/// Cauchy distribution centered at `median` with half width `scale`. The distribution has no
+/// mean or variance and samples can be arbitrarily large, so it is meant to be wrapped in
+/// [`InitializationMethod::Clamped`]. `scale` must be positive.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct BoundedCauchyInitialization {
+    pub median: f32,
+    pub scale: f32,
+}
+
+impl BoundedCauchyInitialization {
+    pub fn new(median: f32, scale: f32) -> Self {
+        BoundedCauchyInitialization { median, scale }
+    }
+}
+
+/// Laplace distribution with location `mean` and scale `scale`, the variance is
+/// `2 * scale * scale`. Tails are heavier than normal but light enough that
+/// [`InitializationMethod::Clamped`] is optional. `scale` must be positive.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct BoundedLaplaceInitialization {
+    pub mean: f32,
+    pub scale: f32,
+}
+
+impl BoundedLaplaceInitialization {
+    pub fn new(mean: f32, scale: f32) -> Self {
+        BoundedLaplaceInitialization { mean, scale }
+    }
+}
+
+/// Samples every value from the discrete set `values`, value `i` with probability proportional
+/// to `weights[i]`, or uniformly if there are no weights. Weights must be non negative, not all
+/// zero and as many as the values.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct CategoricalInitialization {
+    pub values: Vec<f32>,
+    pub weights: Option<Vec<f32>>,
+}
+
+impl CategoricalInitialization {
+    pub fn new(values: Vec<f32>, weights: Option<Vec<f32>>) -> Self {
+        CategoricalInitialization { values, weights }
+    }
+}
+
+/// Function sampling the embedding of `dim` values from the seeded rng.
+pub type CustomInitializationFn = dyn Fn(&mut SmallRng, usize) -> Vec<f32> + Send + Sync;
+
+/// User provided initialization, for distributions without a dedicated variant. Functions only
+/// live in the process that created them, serializing a custom initialization fails.
+#[derive(Clone)]
+pub struct CustomInitialization(pub Arc<CustomInitializationFn>);
+
+impl CustomInitialization {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&mut SmallRng, usize) -> Vec<f32> + Send + Sync + 'static,
+    {
+        CustomInitialization(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for CustomInitialization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CustomInitialization")
+    }
+}
+
+const CUSTOM_INITIALIZATION_SERIALIZE_ERROR: &str = "custom initialization can not be serialized";
+
+impl Serialize for CustomInitialization {
+    fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom(
+            CUSTOM_INITIALIZATION_SERIALIZE_ERROR,
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomInitialization {
+    fn deserialize<D: Deserializer<'de>>(_deserializer: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom(
+            CUSTOM_INITIALIZATION_SERIALIZE_ERROR,
+        ))
+    }
+}
+
+impl<C: Context> Writable<C> for CustomInitialization {
+    fn write_to<W: ?Sized + persia_speedy::Writer<C>>(
+        &self,
+        _writer: &mut W,
+    ) -> Result<(), C::Error> {
+        Err(persia_speedy::Error::custom(CUSTOM_INITIALIZATION_SERIALIZE_ERROR.to_string()).into())
+    }
+}
+
+impl<'a, C: Context> Readable<'a, C> for CustomInitialization {
+    fn read_from<R: persia_speedy::Reader<'a, C>>(_reader: &mut R) -> Result<Self, C::Error> {
+        Err(persia_speedy::Error::custom(CUSTOM_INITIALIZATION_SERIALIZE_ERROR.to_string()).into())
+    }
+}
+
+/// Orthogonal initialization. Entries are initialized row by row, so orthogonality only holds
+/// for rows initialized jointly as a block.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct OrthogonalInitialization {
+    pub gain: f32,
+}
+
+impl OrthogonalInitialization {
+    pub fn new(gain: f32) -> Self {
+        OrthogonalInitialization { gain }
+    }
+}
+
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct ConstantInitialization {
+    pub value: f32,
+}
+
+impl ConstantInitialization {
+    pub fn new(value: f32) -> Self {
+        ConstantInitialization { value }
+    }
+}
+
+/// Variance scaling initialization from Glorot & Bengio. Fan values default to the
+/// embedding dim when omitted.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct GlorotInitialization {
+    pub fan_in: Option<usize>,
+    pub fan_out: Option<usize>,
+}
+
+impl GlorotInitialization {
+    pub fn new(fan_in: Option<usize>, fan_out: Option<usize>) -> Self {
+        GlorotInitialization { fan_in, fan_out }
+    }
+
+    pub fn fan_sum(&self, dim: usize) -> usize {
+        self.fan_in.unwrap_or(dim) + self.fan_out.unwrap_or(dim)
+    }
+}
+
+/// Variance scaling initialization from He et al., suited for ReLU activations.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct KaimingInitialization {
+    pub fan_in: usize,
+}
+
+impl KaimingInitialization {
+    pub fn new(fan_in: usize) -> Self {
+        KaimingInitialization { fan_in }
+    }
+}
+
+/// Runs any `inner` initialization and clamps every value into `[lower, upper]`, giving
+/// bounded semantics to distributions without a truncated variant.
+#[derive(Serialize, Deserialize, Readable, Writable, Debug, Default, Clone)]
+#[serde(crate = "self::serde")]
+pub struct ClampedInitialization {
+    pub inner: Box<InitializationMethod>,
+    pub lower: f32,
+    pub upper: f32,
+}
+
+impl ClampedInitialization {
+    pub fn new(inner: InitializationMethod, lower: f32, upper: f32) -> Self {
+        ClampedInitialization {
+            inner: Box::new(inner),
+            lower,
+            upper,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Readable, Writable, Debug, Clone)]
 #[serde(crate = "self::serde")]
 pub enum InitializationMethod {
@@ -85,6 +330,22 @@ pub enum InitializationMethod {
     BoundedGamma(BoundedGammaInitialization),
     BoundedPoisson(BoundedPoissonInitialization),
     BoundedNormal(BoundedNormalInitialization),
+    TruncatedNormal(TruncatedNormalInitialization),
+    GlorotUniform(GlorotInitialization),
+    GlorotNormal(GlorotInitialization),
+    KaimingUniform(KaimingInitialization),
+    KaimingNormal(KaimingInitialization),
+    Orthogonal(OrthogonalInitialization),
+    Constant(ConstantInitialization),
+    Zeros,
+    BoundedBeta(BoundedBetaInitialization),
+    BoundedExponential(BoundedExponentialInitialization),
+    BoundedLogNormal(BoundedLogNormalInitialization),
+    Custom(CustomInitialization),
+    Clamped(ClampedInitialization),
+    BoundedCauchy(BoundedCauchyInitialization),
+    BoundedLaplace(BoundedLaplaceInitialization),
+    Categorical(CategoricalInitialization),
 }
 
 impl Default for InitializationMethod {
@@ -96,6 +357,174 @@ impl Default for InitializationMethod {
     }
 }
 
+#[derive(Readable, Writable, Error, Debug, Clone)]
+pub enum InitializationParseError {
+    #[error("unknown initialization method {0:?}")]
+    UnknownMethod(String),
+    #[error("malformed initialization method {0:?}, expected name or name(arg, ...)")]
+    Malformed(String),
+    #[error("initialization method {method} takes {expected} arguments, got {actual}")]
+    InvalidArgumentCount {
+        method: String,
+        expected: String,
+        actual: usize,
+    },
+    #[error("invalid argument {argument:?} of initialization method {method}")]
+    InvalidArgument { method: String, argument: String },
+}
+
+fn parse_init_args<T: std::str::FromStr>(
+    method: &str,
+    args: &[&str],
+    expected: &[usize],
+) -> Result<Vec<T>, InitializationParseError> {
+    if !expected.contains(&args.len()) {
+        let expected: Vec<String> = expected.iter().map(|x| x.to_string()).collect();
+        return Err(InitializationParseError::InvalidArgumentCount {
+            method: method.to_string(),
+            expected: expected.join(" or "),
+            actual: args.len(),
+        });
+    }
+    args.iter()
+        .map(|x| {
+            x.parse()
+                .map_err(|_| InitializationParseError::InvalidArgument {
+                    method: method.to_string(),
+                    argument: x.to_string(),
+                })
+        })
+        .collect()
+}
+
+/// Parses initialization methods written as `name` or `name(arg, ...)`, e.g. `zeros`,
+/// `uniform(-0.1, 0.1)`, `normal(0.0, 0.01)` or `glorot_uniform`, so that trainer and server
+/// configs share one spelling. Custom, clamped and categorical initializations have no string
+/// form. Arguments are only parsed as numbers here; whether they are valid for the method is
+/// checked when an entry is initialized with it.
+impl std::str::FromStr for InitializationMethod {
+    type Err = InitializationParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args): (&str, Vec<&str>) = match s.find('(') {
+            Some(open) => {
+                let inner = s[open + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| InitializationParseError::Malformed(s.to_string()))?;
+                let args = if inner.trim().is_empty() {
+                    Vec::new()
+                } else {
+                    inner.split(',').map(|x| x.trim()).collect()
+                };
+                (s[..open].trim(), args)
+            }
+            None => (s, Vec::new()),
+        };
+        if name.is_empty() || name.contains(')') || args.iter().any(|x| x.contains('(')) {
+            return Err(InitializationParseError::Malformed(s.to_string()));
+        }
+
+        let method = match name {
+            "zeros" => {
+                parse_init_args::<f32>(name, &args, &[0])?;
+                InitializationMethod::Zeros
+            }
+            "inverse_embedding_size_sqrt" => {
+                parse_init_args::<f32>(name, &args, &[0])?;
+                InitializationMethod::InverseEmbeddingSizeSqrt
+            }
+            "constant" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[1])?;
+                InitializationMethod::Constant(ConstantInitialization::new(args[0]))
+            }
+            "uniform" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedUniform(BoundedUniformInitialization {
+                    lower: args[0],
+                    upper: args[1],
+                })
+            }
+            "normal" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedNormal(BoundedNormalInitialization::new(
+                    args[0], args[1],
+                ))
+            }
+            "truncated_normal" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[3])?;
+                InitializationMethod::TruncatedNormal(TruncatedNormalInitialization::new(
+                    args[0], args[1], args[2],
+                ))
+            }
+            "gamma" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedGamma(BoundedGammaInitialization::new(
+                    args[0], args[1],
+                ))
+            }
+            "poisson" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[1])?;
+                InitializationMethod::BoundedPoisson(BoundedPoissonInitialization::new(args[0]))
+            }
+            "beta" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedBeta(BoundedBetaInitialization::new(args[0], args[1]))
+            }
+            "exponential" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[1])?;
+                InitializationMethod::BoundedExponential(BoundedExponentialInitialization::new(
+                    args[0],
+                ))
+            }
+            "log_normal" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedLogNormal(BoundedLogNormalInitialization::new(
+                    args[0], args[1],
+                ))
+            }
+            "cauchy" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedCauchy(BoundedCauchyInitialization::new(
+                    args[0], args[1],
+                ))
+            }
+            "laplace" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[2])?;
+                InitializationMethod::BoundedLaplace(BoundedLaplaceInitialization::new(
+                    args[0], args[1],
+                ))
+            }
+            "glorot_uniform" | "glorot_normal" => {
+                let args: Vec<usize> = parse_init_args(name, &args, &[0, 2])?;
+                let glorot = GlorotInitialization::new(args.first().copied(), args.get(1).copied());
+                if name == "glorot_uniform" {
+                    InitializationMethod::GlorotUniform(glorot)
+                } else {
+                    InitializationMethod::GlorotNormal(glorot)
+                }
+            }
+            "kaiming_uniform" | "kaiming_normal" => {
+                let args: Vec<usize> = parse_init_args(name, &args, &[1])?;
+                let kaiming = KaimingInitialization::new(args[0]);
+                if name == "kaiming_uniform" {
+                    InitializationMethod::KaimingUniform(kaiming)
+                } else {
+                    InitializationMethod::KaimingNormal(kaiming)
+                }
+            }
+            "orthogonal" => {
+                let args: Vec<f32> = parse_init_args(name, &args, &[0, 1])?;
+                InitializationMethod::Orthogonal(OrthogonalInitialization::new(
+                    args.first().copied().unwrap_or(1.0),
+                ))
+            }
+            _ => return Err(InitializationParseError::UnknownMethod(name.to_string())),
+        };
+        Ok(method)
+    }
+}
+
 #[derive(Readable, Writable, Debug, Clone)]
 pub struct PersiaEmbeddingModelHyperparameters {
     pub initialization_method: InitializationMethod,
@@ -263,6 +692,10 @@ fn get_thousand() -> usize {
     1000
 }
 
+fn get_hundred_thousand() -> usize {
+    100_000
+}
+
 fn get_million() -> usize {
     1_000_000
 }
@@ -271,6 +704,18 @@ fn get_billion() -> usize {
     1_000_000_000
 }
 
+fn get_default_bloom_filter_false_positive_rate() -> f64 {
+    0.01
+}
+
+fn get_default_max_entry_len() -> usize {
+    1 << 24
+}
+
+fn get_default_max_embedding_dim() -> usize {
+    1 << 20
+}
+
 fn get_default_incremental_dir() -> String {
     String::from("/workspace/incremental_dir/")
 }
@@ -422,6 +867,26 @@ pub struct EmbeddingParameterServerConfig {
     pub capacity: usize,
     #[serde(default = "get_hundred")]
     pub num_hashmap_internal_shards: usize,
+    // Admission filter config, a sign is admitted after being observed admission_threshold
+    // times. Disabled when the threshold is zero. The sketch is allocated per internal shard.
+    #[serde(default)]
+    pub admission_threshold: u32,
+    #[serde(default = "get_hundred_thousand")]
+    pub admission_sketch_width: usize,
+    #[serde(default = "get_four")]
+    pub admission_sketch_depth: usize,
+    // Sign collision guard config, records a fingerprint of the feature of every sign to
+    // detect distinct features hashed onto one sign.
+    #[serde(default = "get_false")]
+    pub enable_collision_guard: bool,
+    #[serde(default = "get_false")]
+    pub reject_sign_collisions: bool,
+    // Bloom filter config, answers lookups of missing signs without probing the map. The
+    // filter of every internal shard is sized for the capacity of the shard.
+    #[serde(default = "get_false")]
+    pub enable_bloom_filter: bool,
+    #[serde(default = "get_default_bloom_filter_false_positive_rate")]
+    pub bloom_filter_false_positive_rate: f64,
     // incremental dump config
     #[serde(default = "get_false")]
     pub enable_incremental_update: bool,
@@ -431,6 +896,14 @@ pub struct EmbeddingParameterServerConfig {
     pub incremental_dir: String,
     #[serde(default = "get_thousand")]
     pub incremental_channel_capacity: usize,
+    // Max number of values, embedding and optimizer state, of a single entry. Entries of
+    // larger dims, e.g. from a bad config, are rejected instead of allocated.
+    #[serde(default = "get_default_max_entry_len")]
+    pub max_entry_len: usize,
+    // Max embedding dim of an entry read from a checkpoint or the network. Entries claiming
+    // larger dims, e.g. from a corrupt header, are rejected before allocating.
+    #[serde(default = "get_default_max_embedding_dim")]
+    pub max_embedding_dim: usize,
 }
 
 impl Default for EmbeddingParameterServerConfig {
@@ -438,10 +911,19 @@ impl Default for EmbeddingParameterServerConfig {
         Self {
             capacity: 1_000_000_000,
             num_hashmap_internal_shards: 1000,
+            admission_threshold: 0,
+            admission_sketch_width: 100_000,
+            admission_sketch_depth: 4,
+            enable_collision_guard: false,
+            reject_sign_collisions: false,
+            enable_bloom_filter: false,
+            bloom_filter_false_positive_rate: get_default_bloom_filter_false_positive_rate(),
             enable_incremental_update: false,
             incremental_buffer_size: 1_000_000,
             incremental_dir: get_default_incremental_dir(),
             incremental_channel_capacity: 1000,
+            max_entry_len: get_default_max_entry_len(),
+            max_embedding_dim: get_default_max_embedding_dim(),
         }
     }
 }
@@ -648,3 +1130,95 @@ pub fn parse_embedding_config(config: EmbeddingConfig) -> EmbeddingConfig {
 
     config
 }
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_initialization_method() {
+        let parse = |s: &str| s.parse::<InitializationMethod>().unwrap();
+
+        assert!(matches!(parse("zeros"), InitializationMethod::Zeros));
+        assert!(matches!(
+            parse(" normal(0.0,0.01) "),
+            InitializationMethod::BoundedNormal(x) if x.mean == 0.0 && x.standard_deviation == 0.01
+        ));
+        assert!(matches!(
+            parse("uniform(-0.1, 0.1)"),
+            InitializationMethod::BoundedUniform(x) if x.lower == -0.1 && x.upper == 0.1
+        ));
+        assert!(matches!(
+            parse("glorot_uniform"),
+            InitializationMethod::GlorotUniform(GlorotInitialization {
+                fan_in: None,
+                fan_out: None
+            })
+        ));
+        assert!(matches!(
+            parse("glorot_normal(16, 32)"),
+            InitializationMethod::GlorotNormal(GlorotInitialization {
+                fan_in: Some(16),
+                fan_out: Some(32)
+            })
+        ));
+        assert!(matches!(
+            parse("truncated_normal(0.0, 0.1, 2.0)"),
+            InitializationMethod::TruncatedNormal(x) if x.truncate_sigma == 2.0
+        ));
+        assert!(matches!(
+            parse("kaiming_normal(64)"),
+            InitializationMethod::KaimingNormal(KaimingInitialization { fan_in: 64 })
+        ));
+        assert!(matches!(
+            parse("orthogonal"),
+            InitializationMethod::Orthogonal(x) if x.gain == 1.0
+        ));
+        assert!(matches!(
+            parse("constant(0.5)"),
+            InitializationMethod::Constant(x) if x.value == 0.5
+        ));
+        assert!(matches!(
+            parse("cauchy(0.0, 0.01)"),
+            InitializationMethod::BoundedCauchy(x) if x.median == 0.0 && x.scale == 0.01
+        ));
+        assert!(matches!(
+            parse("laplace(1.0, 0.5)"),
+            InitializationMethod::BoundedLaplace(x) if x.mean == 1.0 && x.scale == 0.5
+        ));
+    }
+
+    #[test]
+    fn test_parse_initialization_method_errors() {
+        let parse = |s: &str| s.parse::<InitializationMethod>().unwrap_err();
+
+        assert!(matches!(
+            parse("weibull(1, 1)"),
+            InitializationParseError::UnknownMethod(x) if x == "weibull"
+        ));
+        assert!(matches!(
+            parse("normal(0.0, 0.01"),
+            InitializationParseError::Malformed(_)
+        ));
+        assert!(matches!(
+            parse("(1.0)"),
+            InitializationParseError::Malformed(_)
+        ));
+        assert!(matches!(
+            parse("uniform(-0.1)"),
+            InitializationParseError::InvalidArgumentCount { actual: 1, .. }
+        ));
+        assert!(matches!(
+            parse("normal(zero, 0.01)"),
+            InitializationParseError::InvalidArgument { argument, .. } if argument == "zero"
+        ));
+        assert!(matches!(
+            parse("zeros(1)"),
+            InitializationParseError::InvalidArgumentCount { actual: 1, .. }
+        ));
+        assert_eq!(
+            parse("glorot_uniform(1)").to_string(),
+            "initialization method glorot_uniform takes 0 or 2 arguments, got 1"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::hash::{Hash, Hasher};
+
+// Finalizer of splitmix64, decorrelates the sketch hash from the hash used to pick the shard.
+#[inline]
+pub(crate) fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Count-min sketch of key occurrences with saturating `u32` counters.
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        let width = width.max(1);
+        let depth = depth.max(1);
+        Self {
+            width,
+            depth,
+            counters: vec![0; width * depth],
+        }
+    }
+
+    #[inline]
+    fn cell_indices<K: Hash>(&self, key: &K) -> impl Iterator<Item = usize> {
+        let mut s = ahash::AHasher::default();
+        key.hash(&mut s);
+        let hash = mix64(s.finish());
+        let (h1, h2) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+        let width = self.width;
+        (0..self.depth).map(move |row| row * width + h1.wrapping_add(row.wrapping_mul(h2)) % width)
+    }
+
+    /// Record one occurrence of `key` and return its estimated count.
+    pub fn observe<K: Hash>(&mut self, key: &K) -> u32 {
+        let indices: Vec<usize> = self.cell_indices(key).collect();
+        indices
+            .into_iter()
+            .map(|idx| {
+                let counter = &mut self.counters[idx];
+                *counter = counter.saturating_add(1);
+                *counter
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn estimate<K: Hash>(&self, key: &K) -> u32 {
+        self.cell_indices(key)
+            .map(|idx| self.counters[idx])
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.iter_mut().for_each(|x| *x = 0);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+}
+
+/// Admits a key only after it has been observed `threshold` times within a window. The sketch is
+/// cleared every `sketch_width` observations, which keeps the overestimation of the count bounded
+/// and forgets keys that stopped showing up.
+pub struct AdmissionFilter {
+    sketch: CountMinSketch,
+    threshold: u32,
+    num_observations: usize,
+}
+
+impl AdmissionFilter {
+    pub fn new(threshold: u32, sketch_width: usize, sketch_depth: usize) -> Self {
+        Self {
+            sketch: CountMinSketch::new(sketch_width, sketch_depth),
+            threshold,
+            num_observations: 0,
+        }
+    }
+
+    pub fn admit<K: Hash>(&mut self, key: &K) -> bool {
+        if self.num_observations >= self.sketch.width() {
+            self.clear();
+        }
+        self.num_observations += 1;
+        self.sketch.observe(key) >= self.threshold
+    }
+
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+        self.num_observations = 0;
+    }
+}
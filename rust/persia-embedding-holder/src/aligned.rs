@@ -0,0 +1,230 @@
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+use persia_libs::serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Alignment of [`AlignedVec`] buffers, a cache line and the width of an AVX-512 register.
+pub const ENTRY_ALIGNMENT: usize = 64;
+
+/// Fixed capacity `f32` buffer whose data starts on an [`ENTRY_ALIGNMENT`] byte boundary.
+pub struct AlignedVec {
+    ptr: NonNull<f32>,
+    len: usize,
+}
+
+// AlignedVec uniquely owns its buffer like a Vec does.
+unsafe impl Send for AlignedVec {}
+unsafe impl Sync for AlignedVec {}
+
+impl AlignedVec {
+    #[inline]
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len * std::mem::size_of::<f32>(), ENTRY_ALIGNMENT)
+            .expect("invalid aligned buffer layout")
+    }
+
+    #[inline]
+    fn dangling() -> NonNull<f32> {
+        // an empty buffer still reports an aligned pointer
+        unsafe { NonNull::new_unchecked(ENTRY_ALIGNMENT as *mut f32) }
+    }
+
+    pub fn zeroed(len: usize) -> Self {
+        if len == 0 {
+            return Self {
+                ptr: Self::dangling(),
+                len,
+            };
+        }
+        let layout = Self::layout(len);
+        let ptr = unsafe { alloc::alloc_zeroed(layout) } as *mut f32;
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Self { ptr, len }
+    }
+
+    pub fn from_slice(data: &[f32]) -> Self {
+        let mut result = Self::zeroed(data.len());
+        result.copy_from_slice(data);
+        result
+    }
+
+    pub fn resize(&mut self, new_len: usize, value: f32) {
+        if new_len == self.len {
+            return;
+        }
+        let old_len = self.len;
+        if new_len == 0 || old_len == 0 {
+            let mut result = Self::zeroed(new_len);
+            let copied = old_len.min(new_len);
+            result[..copied].copy_from_slice(&self[..copied]);
+            result[copied..].iter_mut().for_each(|x| *x = value);
+            *self = result;
+            return;
+        }
+
+        let layout = Self::layout(old_len);
+        let new_size = Self::layout(new_len).size();
+        let ptr = unsafe { alloc::realloc(self.ptr.as_ptr() as *mut u8, layout, new_size) };
+        self.ptr = NonNull::new(ptr as *mut f32)
+            .unwrap_or_else(|| alloc::handle_alloc_error(Self::layout(new_len)));
+        self.len = new_len;
+        self[old_len.min(new_len)..]
+            .iter_mut()
+            .for_each(|x| *x = value);
+    }
+}
+
+impl Drop for AlignedVec {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { alloc::dealloc(self.ptr.as_ptr() as *mut u8, Self::layout(self.len)) }
+        }
+    }
+}
+
+impl Deref for AlignedVec {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedVec {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Clone for AlignedVec {
+    fn clone(&self) -> Self {
+        Self::from_slice(self)
+    }
+}
+
+impl std::fmt::Debug for AlignedVec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Storage of an embedding entry, either a plain `Vec` or an [`AlignedVec`] for entries fed
+/// to SIMD kernels.
+#[derive(Clone, Debug)]
+pub(crate) enum EntryBuffer {
+    Plain(Vec<f32>),
+    Aligned(AlignedVec),
+}
+
+impl EntryBuffer {
+    pub(crate) fn is_aligned(&self) -> bool {
+        matches!(self, EntryBuffer::Aligned(_))
+    }
+
+    pub(crate) fn as_slice(&self) -> &[f32] {
+        self
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [f32] {
+        self
+    }
+
+    /// Number of values the buffer has room for without reallocating.
+    pub(crate) fn capacity(&self) -> usize {
+        match self {
+            EntryBuffer::Plain(x) => x.capacity(),
+            EntryBuffer::Aligned(x) => x.len(),
+        }
+    }
+
+    pub(crate) fn resize(&mut self, new_len: usize, value: f32) {
+        match self {
+            EntryBuffer::Plain(x) => x.resize(new_len, value),
+            EntryBuffer::Aligned(x) => x.resize(new_len, value),
+        }
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, other: &[f32]) {
+        match self {
+            EntryBuffer::Plain(x) => x.extend_from_slice(other),
+            EntryBuffer::Aligned(x) => {
+                let len = x.len();
+                x.resize(len + other.len(), 0.0);
+                x[len..].copy_from_slice(other);
+            }
+        }
+    }
+
+    pub(crate) fn into_aligned(self) -> Self {
+        match self {
+            EntryBuffer::Plain(x) => EntryBuffer::Aligned(AlignedVec::from_slice(&x)),
+            aligned => aligned,
+        }
+    }
+}
+
+impl From<Vec<f32>> for EntryBuffer {
+    fn from(inner: Vec<f32>) -> Self {
+        EntryBuffer::Plain(inner)
+    }
+}
+
+impl Deref for EntryBuffer {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        match self {
+            EntryBuffer::Plain(x) => x.as_slice(),
+            EntryBuffer::Aligned(x) => x,
+        }
+    }
+}
+
+impl DerefMut for EntryBuffer {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        match self {
+            EntryBuffer::Plain(x) => x.as_mut_slice(),
+            EntryBuffer::Aligned(x) => x,
+        }
+    }
+}
+
+impl Serialize for EntryBuffer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EntryBuffer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<f32>::deserialize(deserializer).map(EntryBuffer::Plain)
+    }
+}
+
+#[cfg(test)]
+mod aligned_tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_vec() {
+        let mut buffer = AlignedVec::from_slice(&[1.0, 2.0, 3.0]);
+        assert_eq!(buffer.as_ptr() as usize % ENTRY_ALIGNMENT, 0);
+        assert_eq!(&buffer[..], &[1.0, 2.0, 3.0]);
+
+        buffer.resize(40, 0.5);
+        assert_eq!(buffer.as_ptr() as usize % ENTRY_ALIGNMENT, 0);
+        assert_eq!(&buffer[..4], &[1.0, 2.0, 3.0, 0.5]);
+        assert_eq!(buffer.len(), 40);
+
+        buffer.resize(2, 0.0);
+        assert_eq!(&buffer[..], &[1.0, 2.0]);
+        buffer.resize(0, 0.0);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr() as usize % ENTRY_ALIGNMENT, 0);
+
+        let cloned = AlignedVec::from_slice(&[4.0; 17]).clone();
+        assert_eq!(cloned.as_ptr() as usize % ENTRY_ALIGNMENT, 0);
+        assert_eq!(&cloned[..], &[4.0; 17]);
+    }
+}
@@ -0,0 +1,71 @@
+use bumpalo::Bump;
+
+/// Bump allocator for embedding rows, implementing option 5 of the `HashMapEmbeddingEntry` TODO
+/// notes. Rows are carved out of large chunks and never freed one by one, instead the whole
+/// arena is reclaimed at once by [`ArenaEntryFactory::reset`], e.g. between epochs.
+///
+/// A factory is not `Sync`, each worker thread is expected to own its factory so that
+/// allocation needs no synchronization. Rows returned by [`ArenaEntryFactory::alloc_entry`]
+/// borrow the factory, so the borrow checker guarantees that no row outlives the factory or
+/// is used after a `reset`, which requires unique access to the factory. Rows that need to
+/// live longer must be copied out, e.g. into a `HashMapEmbeddingEntry`.
+#[derive(Default)]
+pub struct ArenaEntryFactory {
+    bump: Bump,
+}
+
+impl ArenaEntryFactory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(num_floats: usize) -> Self {
+        Self {
+            bump: Bump::with_capacity(num_floats * std::mem::size_of::<f32>()),
+        }
+    }
+
+    /// Allocates a zeroed row of `dim + opt_space` floats, the embedding followed by the
+    /// optimizer state.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_entry(&self, dim: usize, opt_space: usize) -> &mut [f32] {
+        self.bump.alloc_slice_fill_default(dim + opt_space)
+    }
+
+    /// Bytes reserved by the arena chunks.
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
+    }
+
+    /// Reclaims all rows allocated so far, keeping the largest chunk for reuse.
+    pub fn reset(&mut self) {
+        self.bump.reset();
+    }
+}
+
+#[cfg(test)]
+mod arena_tests {
+    use super::*;
+
+    #[test]
+    fn test_contiguous_alloc() {
+        let mut factory = ArenaEntryFactory::with_capacity(1024);
+        {
+            let first = factory.alloc_entry(8, 8);
+            let second = factory.alloc_entry(8, 8);
+            assert_eq!(first.len(), 16);
+            assert!(second.iter().all(|x| *x == 0.0));
+            second[0] = 1.0;
+            assert_eq!(first[0], 0.0);
+
+            let distance = (first.as_ptr() as isize - second.as_ptr() as isize).abs();
+            assert_eq!(distance as usize, 16 * std::mem::size_of::<f32>());
+        }
+
+        let allocated = factory.allocated_bytes();
+        factory.reset();
+        let row = factory.alloc_entry(8, 8);
+        assert!(row.iter().all(|x| *x == 0.0));
+        assert!(factory.allocated_bytes() <= allocated);
+    }
+}
@@ -252,6 +252,12 @@ impl<T> ArrayLinkedList<T> {
         result
     }
 
+    /// Reserves capacity for at least `additional` more elements to be pushed without
+    /// reallocating, without adding them to the free list like [`Self::with_capacity`] does.
+    pub fn reserve(&mut self, additional: u32) {
+        self.elements.reserve(additional as _);
+    }
+
     fn insert_free_element(&mut self, element: LinkedListNode<T>) -> u32 {
         if self.free_index == 0 {
             self.elements.push(element);
@@ -835,6 +841,13 @@ impl<T> ArrayLinkedList<T> {
         Values(Indexed::new(self))
     }
 
+    /// Returns a mutably borrowing iterator over its elements.
+    ///
+    /// Unlike the other iterators, the elements are visited in index order instead of the logical order.
+    pub fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut T> {
+        self.elements.iter_mut().filter_map(|x| x.data.as_mut())
+    }
+
     /// Returns a borrowing iterator over its indexed elements.
     pub fn indexed<'a>(
         &'a self,
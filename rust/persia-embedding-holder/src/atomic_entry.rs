@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+
+/// Embedding entry whose values are stored as the bits of `AtomicU32`s, so that readers load
+/// rows without taking a lock and writers update single dims with compare and swap.
+///
+/// All accesses are relaxed and only single values are atomic. A row loaded concurrently with
+/// writers may mix values from before and after an update of the row, and updates of different
+/// dims may become visible in any order. This is fine for hogwild style training, entries that
+/// need consistent rows should stay behind the shard locks.
+#[derive(Debug)]
+pub struct AtomicEmbeddingEntry {
+    inner: Vec<AtomicU32>,
+    embedding_dim: usize,
+    sign: u64,
+}
+
+impl AtomicEmbeddingEntry {
+    pub fn from_emb_and_opt(emb: &[f32], opt: &[f32], sign: u64) -> Self {
+        let inner = emb
+            .iter()
+            .chain(opt.iter())
+            .map(|x| AtomicU32::new(x.to_bits()))
+            .collect();
+        Self {
+            inner,
+            embedding_dim: emb.len(),
+            sign,
+        }
+    }
+
+    pub fn embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    pub fn sign(&self) -> u64 {
+        self.sign
+    }
+
+    pub fn inner_size(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn load(&self, idx: usize) -> f32 {
+        f32::from_bits(self.inner[idx].load(Ordering::Relaxed))
+    }
+
+    pub fn store(&self, idx: usize, value: f32) {
+        self.inner[idx].store(value.to_bits(), Ordering::Relaxed)
+    }
+
+    /// Adds `delta` to the value at `idx` and returns the previous value.
+    pub fn fetch_add(&self, idx: usize, delta: f32) -> f32 {
+        let cell = &self.inner[idx];
+        let mut current = cell.load(Ordering::Relaxed);
+        loop {
+            let new = (f32::from_bits(current) + delta).to_bits();
+            match cell.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return f32::from_bits(current),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Loads the embedding and the optimizer state, see the type level docs for the
+    /// consistency of the result.
+    pub fn load_row(&self) -> Vec<f32> {
+        self.inner
+            .iter()
+            .map(|x| f32::from_bits(x.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn load_emb(&self) -> Vec<f32> {
+        self.inner[..self.embedding_dim]
+            .iter()
+            .map(|x| f32::from_bits(x.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn to_entry(&self) -> HashMapEmbeddingEntry {
+        let mut emb = self.load_row();
+        let opt = emb.split_off(self.embedding_dim);
+        HashMapEmbeddingEntry::from_emb_and_opt(emb, &opt, self.sign)
+    }
+}
+
+impl From<&HashMapEmbeddingEntry> for AtomicEmbeddingEntry {
+    fn from(entry: &HashMapEmbeddingEntry) -> Self {
+        Self::from_emb_and_opt(entry.emb(), entry.opt(), entry.sign())
+    }
+}
+
+#[cfg(test)]
+mod atomic_entry_tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_concurrent_fetch_add() {
+        const NUM_WRITERS: usize = 4;
+        const NUM_ADDS: usize = 10_000;
+        let entry = Arc::new(AtomicEmbeddingEntry::from_emb_and_opt(&[0.0; 8], &[0.0], 1));
+
+        let writers: Vec<_> = (0..NUM_WRITERS)
+            .map(|_| {
+                let entry = entry.clone();
+                std::thread::spawn(move || {
+                    (0..NUM_ADDS).for_each(|_| {
+                        (0..entry.inner_size()).for_each(|idx| {
+                            entry.fetch_add(idx, 1.0);
+                        })
+                    })
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..NUM_WRITERS)
+            .map(|_| {
+                let entry = entry.clone();
+                std::thread::spawn(move || {
+                    let mut last = entry.load_row();
+                    (0..NUM_ADDS).for_each(|_| {
+                        let row = entry.load_row();
+                        // every value only ever grows by whole increments
+                        row.iter().zip(last.iter()).for_each(|(x, prev)| {
+                            assert!(x >= prev);
+                            assert_eq!(x.fract(), 0.0);
+                        });
+                        last = row;
+                    })
+                })
+            })
+            .collect();
+        writers
+            .into_iter()
+            .chain(readers)
+            .for_each(|x| x.join().unwrap());
+
+        let total = (NUM_WRITERS * NUM_ADDS) as f32;
+        assert_eq!(entry.load_row(), vec![total; 9]);
+        let converted = entry.to_entry();
+        assert_eq!(converted.emb(), &[total; 8]);
+        assert_eq!(converted.opt(), &[total]);
+    }
+}
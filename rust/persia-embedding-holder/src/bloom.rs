@@ -0,0 +1,153 @@
+use std::hash::{Hash, Hasher};
+
+use crate::admission::mix64;
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+/// Bloom filter answering whether a key may have been inserted. It has no false negatives and
+/// keys can not be removed, so keys removed from the map stay false positives until the filter
+/// is rebuilt.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Filter sized for a false positive rate of `false_positive_rate` at `expected_items`
+    /// inserted keys.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "bloom filter false positive rate must be in (0, 1)"
+        );
+        let expected_items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-expected_items * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = (num_bits as f64 / expected_items * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; ((num_bits + 63) / 64) as usize],
+            num_bits,
+            num_hashes: num_hashes.min(32),
+        }
+    }
+
+    // Double hashing, the i-th bit of a key is h1 + i * h2.
+    #[inline]
+    fn bit_indices<K: Hash>(&self, key: &K) -> impl Iterator<Item = u64> {
+        let mut s = ahash::AHasher::default();
+        key.hash(&mut s);
+        let h1 = mix64(s.finish());
+        let h2 = mix64(h1) | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn insert<K: Hash>(&mut self, key: &K) {
+        let indices: Vec<u64> = self.bit_indices(key).collect();
+        indices
+            .into_iter()
+            .for_each(|idx| self.bits[(idx / 64) as usize] |= 1 << (idx % 64));
+    }
+
+    /// False means `key` was never inserted since the last clear, true means it may have been.
+    pub fn may_contain<K: Hash>(&self, key: &K) -> bool {
+        self.bit_indices(key)
+            .all(|idx| self.bits[(idx / 64) as usize] & (1 << (idx % 64)) != 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.iter_mut().for_each(|x| *x = 0);
+    }
+
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Puts a bloom filter with a false positive rate of `false_positive_rate` at
+    /// `expected_signs` signs in front of every shard, see [`PersiaEmbeddingHolder::contains`].
+    /// The filters are updated on insert, also of entries present already.
+    pub fn with_bloom_filter(self, expected_signs: usize, false_positive_rate: f64) -> Self {
+        let expected_per_shard = expected_signs / self.num_internal_shards();
+        self.inner.inner.iter().for_each(|x| {
+            x.write()
+                .enable_bloom_filter(expected_per_shard, false_positive_rate)
+        });
+        self
+    }
+
+    /// Whether `sign` is present and not tombstoned. With bloom filters most missing signs are
+    /// answered by the filter, only maybe present signs probe the map.
+    pub fn contains(&self, sign: u64) -> bool {
+        let shard = self.shard(&sign).read();
+        shard.may_contain(&sign)
+            && shard
+                .hashmap
+                .get(&sign)
+                .and_then(|idx| shard.linkedlist[idx as usize].as_ref())
+                .map_or(false, |entry| !entry.is_tombstoned())
+    }
+
+    /// Rebuilds the bloom filters from the present signs, e.g. after loading a checkpoint or
+    /// after many evictions, which leave false positives behind.
+    pub fn rebuild_bloom_filters(&self) {
+        self.inner
+            .inner
+            .iter()
+            .for_each(|x| x.write().rebuild_bloom_filter());
+    }
+}
+
+#[cfg(test)]
+mod bloom_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    #[test]
+    fn test_false_positive_rate() {
+        let target = 0.01;
+        let mut filter = BloomFilter::new(100_000, target);
+        assert_eq!(filter.num_hashes(), 7);
+        (0..100_000u64).for_each(|x| filter.insert(&(x * 7919)));
+        assert!((0..100_000u64).all(|x| filter.may_contain(&(x * 7919))));
+
+        let num_queries = 200_000u64;
+        let false_positives = (0..num_queries)
+            .filter(|x| filter.may_contain(&(x * 7919 + 1)))
+            .count();
+        let rate = false_positives as f64 / num_queries as f64;
+        assert!(rate > target / 2.0 && rate < target * 1.5, "{}", rate);
+
+        filter.clear();
+        assert!(!filter.may_contain(&0u64));
+    }
+
+    #[test]
+    fn test_holder_contains() {
+        let holder = PersiaEmbeddingHolder::new(10_000, 4).with_bloom_filter(1000, 0.01);
+        (0..1000u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb(vec![1.0; 2], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        assert!((0..1000u64).all(|sign| holder.contains(sign)));
+        assert!((1000..2000u64).all(|sign| !holder.contains(sign)));
+
+        // signs removed from the map stay set in the filter until it is rebuilt
+        holder.prune_to_top_k(0);
+        assert!(holder.shard(&1).read().may_contain(&1));
+        assert!(!holder.contains(1));
+        holder.rebuild_bloom_filters();
+        let num_maybe = (0..1000u64)
+            .filter(|sign| holder.shard(sign).read().may_contain(sign))
+            .count();
+        assert_eq!(num_maybe, 0);
+    }
+}
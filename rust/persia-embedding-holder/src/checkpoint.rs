@@ -0,0 +1,537 @@
+use std::io::{self, Read, Write};
+
+use persia_speedy::{Readable, Writable};
+
+use crate::emb_entry::{max_serialized_entry_len, HashMapEmbeddingEntry};
+use crate::map_backend::{DefaultMapBackend, HolderMapBackend};
+use crate::PersiaEmbeddingHolder;
+
+/// Version of the holder stream written by [`PersiaEmbeddingHolder::dump_stream`].
+pub const HOLDER_STREAM_VERSION: u16 = 1;
+
+/// Header of a holder stream, the embedding dim is 0 when entries do not share one dim.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HolderStreamHeader {
+    pub version: u16,
+    pub num_entries: u64,
+    pub embedding_dim: u64,
+}
+
+impl HolderStreamHeader {
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.version.to_le_bytes())?;
+        w.write_all(&self.num_entries.to_le_bytes())?;
+        w.write_all(&self.embedding_dim.to_le_bytes())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut version = [0u8; 2];
+        let mut num_entries = [0u8; 8];
+        let mut embedding_dim = [0u8; 8];
+        r.read_exact(&mut version)?;
+        r.read_exact(&mut num_entries)?;
+        r.read_exact(&mut embedding_dim)?;
+        let header = Self {
+            version: u16::from_le_bytes(version),
+            num_entries: u64::from_le_bytes(num_entries),
+            embedding_dim: u64::from_le_bytes(embedding_dim),
+        };
+        if header.version > HOLDER_STREAM_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported holder stream version {}",
+                header.version
+            )));
+        }
+        Ok(header)
+    }
+}
+
+pub(crate) fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Record of an incremental checkpoint, see [`PersiaEmbeddingHolder::checkpoint_dirty`].
+#[derive(Debug)]
+pub(crate) enum EntryRecord {
+    Entry(HashMapEmbeddingEntry),
+    /// The sign was tombstoned and is removed when the record is loaded.
+    Deleted(u64),
+}
+
+// Length of a deletion record, which is followed by the sign instead of an entry.
+const DELETED_RECORD_LEN: u32 = u32::MAX;
+
+/// Writes `entry` as a record, its speedy serialized length as a little endian u32 followed by
+/// the serialized bytes.
+pub(crate) fn write_entry_record<W: Write>(
+    w: &mut W,
+    entry: &HashMapEmbeddingEntry,
+) -> io::Result<()> {
+    let bytes = entry.write_to_vec().map_err(invalid_data)?;
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(&bytes)
+}
+
+/// Writes the deletion of `sign` as a record, `u32::MAX` as length followed by the sign as a
+/// little endian u64.
+pub(crate) fn write_deletion_record<W: Write>(w: &mut W, sign: u64) -> io::Result<()> {
+    w.write_all(&DELETED_RECORD_LEN.to_le_bytes())?;
+    w.write_all(&sign.to_le_bytes())
+}
+
+/// Reads a record written by [`write_entry_record`] or [`write_deletion_record`], returns
+/// `None` at the end of the stream. Records longer than an entry of
+/// [`crate::emb_entry::max_entry_len`] values are rejected before allocating.
+pub(crate) fn read_entry_record<R: Read>(r: &mut R) -> io::Result<Option<EntryRecord>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes(len);
+    if len == DELETED_RECORD_LEN {
+        let mut sign = [0u8; 8];
+        r.read_exact(&mut sign)?;
+        return Ok(Some(EntryRecord::Deleted(u64::from_le_bytes(sign))));
+    }
+    if len as usize > max_serialized_entry_len() {
+        return Err(invalid_data(format!(
+            "entry record of {} bytes exceeds the max entry length",
+            len
+        )));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    r.read_exact(&mut bytes)?;
+    HashMapEmbeddingEntry::read_from_buffer(&bytes)
+        .map(|x| Some(EntryRecord::Entry(x)))
+        .map_err(invalid_data)
+}
+
+// Embedding dim shared by all entries, 0 if they do not share one.
+fn common_dim<'a, I: Iterator<Item = &'a HashMapEmbeddingEntry>>(entries: I) -> usize {
+    let mut dims = entries.map(|x| x.embedding_dim());
+    match dims.next() {
+        Some(dim) if dims.all(|x| x == dim) => dim,
+        _ => 0,
+    }
+}
+
+fn write_stream<'a, W: Write, I: Iterator<Item = &'a HashMapEmbeddingEntry>>(
+    w: &mut W,
+    num_entries: usize,
+    embedding_dim: usize,
+    entries: I,
+) -> io::Result<()> {
+    HolderStreamHeader {
+        version: HOLDER_STREAM_VERSION,
+        num_entries: num_entries as u64,
+        embedding_dim: embedding_dim as u64,
+    }
+    .write_to(w)?;
+    for entry in entries {
+        entry.write_to_stream(&mut *w).map_err(invalid_data)?;
+    }
+    Ok(())
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Writes every entry created or modified since the previous incremental checkpoint and
+    /// clears their dirty bits, returns the number of written records. Tombstoned entries are
+    /// written as deletions and removed from the holder. Shards are written one at a time under
+    /// their write lock.
+    pub fn checkpoint_dirty<W: Write>(&self, w: &mut W) -> io::Result<usize> {
+        let mut num_written = 0;
+        for shard in self.inner.inner.iter() {
+            let mut shard = shard.write();
+            let indices: Vec<u32> = shard.linkedlist.indices().collect();
+            let mut deleted = Vec::new();
+            for idx in indices {
+                if let Some(entry) = shard.linkedlist[idx as usize].as_mut() {
+                    if entry.is_tombstoned() {
+                        write_deletion_record(w, entry.sign())?;
+                        deleted.push(entry.sign());
+                        num_written += 1;
+                    } else if entry.is_dirty() {
+                        write_entry_record(w, entry)?;
+                        entry.clear_dirty();
+                        num_written += 1;
+                    }
+                }
+            }
+            deleted.iter().for_each(|sign| {
+                shard.remove(sign);
+            });
+        }
+        Ok(num_written)
+    }
+
+    /// Applies a delta written by [`PersiaEmbeddingHolder::checkpoint_dirty`], replacing the
+    /// existing entries of the contained signs and removing the deleted ones. Returns the number
+    /// of applied records.
+    pub fn load_incremental<R: Read>(&self, r: &mut R) -> io::Result<usize> {
+        let mut num_loaded = 0;
+        while let Some(record) = read_entry_record(r)? {
+            match record {
+                EntryRecord::Entry(entry) => {
+                    let sign = entry.sign();
+                    let _ = self.shard(&sign).write().insert(sign, entry);
+                }
+                EntryRecord::Deleted(sign) => {
+                    self.shard(&sign).write().remove(&sign);
+                }
+            }
+            num_loaded += 1;
+        }
+        Ok(num_loaded)
+    }
+
+    /// Streams all entries to `w` after a [`HolderStreamHeader`], serializing one entry at a
+    /// time so memory stays bounded. Tombstoned entries are left out. All shards are read
+    /// locked for the duration of the dump, which makes it a point in time snapshot.
+    pub fn dump_stream<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let shards: Vec<_> = self.inner.inner.iter().map(|x| x.read()).collect();
+        let live = || {
+            shards
+                .iter()
+                .flat_map(|x| x.linkedlist.iter())
+                .filter(|x| !x.is_tombstoned())
+        };
+        let num_entries = live().count();
+        write_stream(w, num_entries, common_dim(live()), live())
+    }
+
+    /// [`PersiaEmbeddingHolder::dump_stream`] with the entries ordered by sign, so that holders
+    /// with the same entries dump the same bytes regardless of insertion order and sharding.
+    /// The stream is read back by [`PersiaEmbeddingHolder::load_stream`].
+    pub fn dump_sorted<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let shards: Vec<_> = self.inner.inner.iter().map(|x| x.read()).collect();
+        let mut entries: Vec<&HashMapEmbeddingEntry> = shards
+            .iter()
+            .flat_map(|x| x.linkedlist.iter())
+            .filter(|x| !x.is_tombstoned())
+            .collect();
+        entries.sort_unstable_by_key(|x| x.sign());
+        let embedding_dim = common_dim(entries.iter().copied());
+        write_stream(w, entries.len(), embedding_dim, entries.into_iter())
+    }
+
+    /// Reads a stream written by [`PersiaEmbeddingHolder::dump_stream`] entry by entry and
+    /// inserts the entries, returns the number of loaded entries. The bloom filters are rebuilt
+    /// afterwards, dropping the signs evicted during the load.
+    pub fn load_stream<R: Read>(&self, r: &mut R) -> io::Result<usize> {
+        self.load_stream_filtered(r, |_| true)
+    }
+
+    /// Same as [`PersiaEmbeddingHolder::load_stream`], but only inserts the entries whose sign
+    /// passes `keep`, e.g. the signs owned by this parameter server. Other entries are read and
+    /// discarded, so `r` does not need to be seekable. Returns the number of inserted entries.
+    /// The bloom filters are rebuilt afterwards as in `load_stream`.
+    pub fn load_stream_filtered<R: Read, F: Fn(u64) -> bool>(
+        &self,
+        r: &mut R,
+        keep: F,
+    ) -> io::Result<usize> {
+        let header = HolderStreamHeader::read_from(r)?;
+        let mut num_loaded = 0;
+        for _ in 0..header.num_entries {
+            let entry = HashMapEmbeddingEntry::read_from_stream_unbuffered(&mut *r)
+                .map_err(invalid_data)?;
+            let sign = entry.sign();
+            if keep(sign) {
+                let _ = self.shard(&sign).write().insert(sign, entry);
+                num_loaded += 1;
+            }
+        }
+        self.rebuild_bloom_filters();
+        Ok(num_loaded)
+    }
+}
+
+/// Writes holder checkpoints on a background thread so that training is not blocked.
+///
+/// Consistency model: [`AsyncCheckpointer::begin_checkpoint`] read locks all shards at once and
+/// copies every entry before returning, so the checkpoint is a point in time snapshot of the
+/// holder. Updates issued after `begin_checkpoint` returns never show up in it, updates that
+/// race with it wait for the copy to finish. The price is a full copy of the table in memory
+/// until the background write completes.
+pub struct AsyncCheckpointer<M: HolderMapBackend = DefaultMapBackend<u64>> {
+    holder: PersiaEmbeddingHolder<M>,
+}
+
+impl<M: HolderMapBackend> AsyncCheckpointer<M> {
+    pub fn new(holder: PersiaEmbeddingHolder<M>) -> Self {
+        Self { holder }
+    }
+
+    /// Snapshots the holder and writes it to `w` in the [`PersiaEmbeddingHolder::dump_stream`]
+    /// format on a dedicated thread. Tombstoned entries are left out.
+    pub fn begin_checkpoint<W: Write + Send + 'static>(&self, w: W) -> CheckpointHandle<W> {
+        let snapshot: Vec<HashMapEmbeddingEntry> = {
+            let shards: Vec<_> = self.holder.inner.inner.iter().map(|x| x.read()).collect();
+            shards
+                .iter()
+                .flat_map(|x| x.linkedlist.iter())
+                .filter(|x| !x.is_tombstoned())
+                .cloned()
+                .collect()
+        };
+        let num_entries = snapshot.len();
+        let handle = std::thread::spawn(move || {
+            let mut w = w;
+            let embedding_dim = common_dim(snapshot.iter());
+            write_stream(&mut w, snapshot.len(), embedding_dim, snapshot.iter())?;
+            w.flush()?;
+            Ok(w)
+        });
+        CheckpointHandle {
+            handle,
+            num_entries,
+        }
+    }
+}
+
+pub struct CheckpointHandle<W> {
+    handle: std::thread::JoinHandle<io::Result<W>>,
+    num_entries: usize,
+}
+
+impl<W> CheckpointHandle<W> {
+    /// Number of entries in the snapshot being written.
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Waits for the background write to finish and returns the writer.
+    pub fn join(self) -> io::Result<W> {
+        self.handle
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "checkpoint thread panicked"))?
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use super::*;
+    use persia_embedding_config::InitializationMethod;
+
+    fn assert_same_holder(lhs: &PersiaEmbeddingHolder, rhs: &PersiaEmbeddingHolder, signs: &[u64]) {
+        assert_eq!(lhs.num_total_signs(), rhs.num_total_signs());
+        signs.iter().for_each(|sign| {
+            let lhs = lhs.shard(sign).read();
+            let rhs = rhs.shard(sign).read();
+            assert_eq!(
+                lhs.get(sign).unwrap().as_emb_entry_slice(),
+                rhs.get(sign).unwrap().as_emb_entry_slice()
+            );
+        });
+    }
+
+    #[test]
+    fn test_checkpoint_dirty() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..50).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 4, 1);
+
+        let mut base = Vec::new();
+        assert_eq!(holder.checkpoint_dirty(&mut base).unwrap(), 50);
+        assert_eq!(holder.checkpoint_dirty(&mut Vec::new()).unwrap(), 0);
+
+        [3u64, 17, 42].iter().for_each(|sign| {
+            let mut shard = holder.shard(sign).write();
+            shard.get_mut(sign).unwrap().emb_mut()[0] += 1.0;
+        });
+        let mut delta = Vec::new();
+        assert_eq!(holder.checkpoint_dirty(&mut delta).unwrap(), 3);
+
+        let mut emitted = Vec::new();
+        let mut reader = delta.as_slice();
+        while let Some(EntryRecord::Entry(entry)) = read_entry_record(&mut reader).unwrap() {
+            emitted.push(entry.sign());
+        }
+        emitted.sort_unstable();
+        assert_eq!(emitted, vec![3, 17, 42]);
+
+        let restored = PersiaEmbeddingHolder::new(1000, 4);
+        assert_eq!(restored.load_incremental(&mut base.as_slice()).unwrap(), 50);
+        assert_eq!(restored.load_incremental(&mut delta.as_slice()).unwrap(), 3);
+        assert_same_holder(&holder, &restored, &signs);
+
+        // a corrupt length prefix fails instead of allocating 4GB
+        let mut corrupt = delta;
+        corrupt[..4].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+        assert!(restored.load_incremental(&mut corrupt.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_tombstones_are_not_checkpointed() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..20).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 0, 1);
+        let mut base = Vec::new();
+        holder.checkpoint_dirty(&mut base).unwrap();
+        assert!(holder.tombstone(7));
+
+        let mut dump = Vec::new();
+        holder.dump_stream(&mut dump).unwrap();
+        let mut sorted = Vec::new();
+        holder.dump_sorted(&mut sorted).unwrap();
+        let snapshot = AsyncCheckpointer::new(holder.clone())
+            .begin_checkpoint(Vec::new())
+            .join()
+            .unwrap();
+        for bytes in [dump, sorted, snapshot].iter() {
+            let restored = PersiaEmbeddingHolder::new(1000, 4);
+            assert_eq!(restored.load_stream(&mut bytes.as_slice()).unwrap(), 19);
+            assert!(restored.get_entry(7).is_none());
+            assert!(restored.get_entry(8).is_some());
+        }
+
+        // the incremental checkpoint records the deletion and hard deletes the sign
+        let mut delta = Vec::new();
+        assert_eq!(holder.checkpoint_dirty(&mut delta).unwrap(), 1);
+        assert!(holder.shard(&7).read().get(&7).is_none());
+        assert_eq!(holder.num_total_signs(), 19);
+        let restored = PersiaEmbeddingHolder::new(1000, 4);
+        restored.load_incremental(&mut base.as_slice()).unwrap();
+        assert!(restored.get_entry(7).is_some());
+        assert_eq!(restored.load_incremental(&mut delta.as_slice()).unwrap(), 1);
+        assert!(restored.get_entry(7).is_none());
+        assert_eq!(restored.num_total_signs(), 19);
+    }
+
+    #[test]
+    fn test_dump_stream() {
+        let holder = PersiaEmbeddingHolder::new(1_000_000, 16);
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..100_000).collect();
+        holder.get_or_init_many(&signs, &initialization, 8, 8, 3);
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        holder.dump_stream(&mut cursor).unwrap();
+
+        cursor.set_position(0);
+        let header = HolderStreamHeader::read_from(&mut cursor).unwrap();
+        assert_eq!(header.num_entries, 100_000);
+        assert_eq!(header.embedding_dim, 8);
+
+        cursor.set_position(0);
+        let restored = PersiaEmbeddingHolder::new(1_000_000, 16);
+        assert_eq!(restored.load_stream(&mut cursor).unwrap(), 100_000);
+        assert_same_holder(&holder, &restored, &signs);
+    }
+
+    #[test]
+    fn test_dump_sorted() {
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..200).map(|x| x * 7919 % 1009).collect();
+        let mut reversed = signs.clone();
+        reversed.reverse();
+
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        holder.get_or_init_many(&signs, &initialization, 4, 4, 9);
+        let other = PersiaEmbeddingHolder::new(1000, 8);
+        other.get_or_init_many(&reversed, &initialization, 4, 4, 9);
+
+        let mut dump = Vec::new();
+        holder.dump_sorted(&mut dump).unwrap();
+        let mut other_dump = Vec::new();
+        other.dump_sorted(&mut other_dump).unwrap();
+        assert_eq!(dump, other_dump);
+
+        let restored = PersiaEmbeddingHolder::new(1000, 4);
+        assert_eq!(restored.load_stream(&mut dump.as_slice()).unwrap(), 200);
+        assert_same_holder(&holder, &restored, &signs);
+    }
+
+    #[test]
+    fn test_load_stream_filtered() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..101).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 0, 5);
+        let mut dump = Vec::new();
+        holder.dump_stream(&mut dump).unwrap();
+
+        let num_shards = 2;
+        let parts: Vec<PersiaEmbeddingHolder> = (0..num_shards)
+            .map(|my_shard| {
+                let part = PersiaEmbeddingHolder::new(1000, 4);
+                part.load_stream_filtered(&mut dump.as_slice(), |sign| {
+                    sign % num_shards == my_shard
+                })
+                .unwrap();
+                part
+            })
+            .collect();
+
+        assert_eq!(parts[0].num_total_signs(), 51);
+        assert_eq!(parts[1].num_total_signs(), 50);
+        signs.iter().for_each(|sign| {
+            let owner = &parts[(sign % num_shards) as usize];
+            let other = &parts[((sign + 1) % num_shards) as usize];
+            assert_eq!(
+                owner.shard(sign).read().get(sign).unwrap().emb(),
+                holder.shard(sign).read().get(sign).unwrap().emb()
+            );
+            assert!(other.shard(sign).read().get(sign).is_none());
+        });
+
+        // the signs evicted during the load are dropped from the bloom filters
+        let small = PersiaEmbeddingHolder::new(20, 4).with_bloom_filter(100, 0.01);
+        small
+            .load_stream_filtered(&mut dump.as_slice(), |sign| sign % 2 == 0)
+            .unwrap();
+        assert!(small.num_total_signs() <= 20);
+        let num_stale = signs
+            .iter()
+            .filter(|sign| {
+                let shard = small.shard(sign).read();
+                shard.may_contain(sign) && shard.get(sign).is_none()
+            })
+            .count();
+        assert!(num_stale < 5, "{}", num_stale);
+    }
+
+    #[test]
+    fn test_async_checkpoint() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let signs: Vec<u64> = (0..200).collect();
+        signs.iter().for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb(vec![0.0; 16], *sign);
+            let _ = holder.shard(sign).write().insert(*sign, entry);
+        });
+
+        // every update writes the same value to all elements of an entry, so a torn entry in
+        // the snapshot would show different values
+        let updater = {
+            let holder = holder.clone();
+            let signs = signs.clone();
+            std::thread::spawn(move || {
+                (1..50).for_each(|step| {
+                    signs.iter().for_each(|sign| {
+                        let mut shard = holder.shard(sign).write();
+                        let entry = shard.get_mut(sign).unwrap();
+                        entry.emb_mut().iter_mut().for_each(|x| *x = step as f32);
+                    });
+                });
+            })
+        };
+
+        let checkpointer = AsyncCheckpointer::new(holder.clone());
+        let handle = checkpointer.begin_checkpoint(Vec::new());
+        assert_eq!(handle.num_entries(), 200);
+        let bytes = handle.join().unwrap();
+        updater.join().unwrap();
+
+        let restored = PersiaEmbeddingHolder::new(1000, 4);
+        assert_eq!(restored.load_stream(&mut bytes.as_slice()).unwrap(), 200);
+        signs.iter().for_each(|sign| {
+            let shard = restored.shard(sign).read();
+            let emb = shard.get(sign).unwrap().emb();
+            assert!(emb.iter().all(|x| *x == emb[0]));
+        });
+    }
+}
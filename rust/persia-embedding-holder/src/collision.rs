@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use persia_libs::{hashbrown::HashMap, parking_lot::RwLock};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::map_backend::HolderMapBackend;
+use crate::sharded::get_index;
+use crate::{PersiaEmbeddingHolder, PersiaEmbeddingHolderError};
+
+/// 32 bit fingerprint of the raw feature bytes a sign was hashed from. It is derived
+/// independently of the sign, so two features hashed onto one sign almost always have
+/// different fingerprints.
+pub fn feature_fingerprint(feature: &[u8]) -> u32 {
+    farmhash::fingerprint32(feature)
+}
+
+/// Fingerprints of the signs inserted into a holder, used to detect distinct features colliding
+/// onto one sign. Fingerprints are kept until the holder is cleared, also for evicted signs,
+/// which costs 12 bytes per distinct sign.
+pub(crate) struct CollisionGuard {
+    fingerprints: Vec<RwLock<HashMap<u64, u32>>>,
+    reject: bool,
+    collisions: AtomicU64,
+}
+
+impl CollisionGuard {
+    pub(crate) fn new(num_internal_shards: usize, reject: bool) -> Self {
+        Self {
+            fingerprints: (0..num_internal_shards)
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            reject,
+            collisions: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, sign: u64) -> &RwLock<HashMap<u64, u32>> {
+        &self.fingerprints[get_index(&sign, self.fingerprints.len())]
+    }
+
+    fn collision(&self, sign: u64) -> Result<(), PersiaEmbeddingHolderError> {
+        self.collisions.fetch_add(1, Ordering::Relaxed);
+        if self.reject {
+            Err(PersiaEmbeddingHolderError::SignCollision(sign))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records the fingerprint of `sign` if it has none yet.
+    fn record(&self, sign: u64, fingerprint: u32) -> Result<(), PersiaEmbeddingHolderError> {
+        let recorded = *self.shard(sign).write().entry(sign).or_insert(fingerprint);
+        if recorded != fingerprint {
+            return self.collision(sign);
+        }
+        Ok(())
+    }
+
+    fn check(&self, sign: u64, fingerprint: u32) -> Result<(), PersiaEmbeddingHolderError> {
+        match self.shard(sign).read().get(&sign) {
+            Some(recorded) if *recorded != fingerprint => self.collision(sign),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.fingerprints.iter().for_each(|x| x.write().clear());
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Enables collision detection for the `_checked` methods. A sign used with a fingerprint
+    /// other than the one it was first inserted with counts as a collision, which is an error
+    /// when `reject` is set.
+    pub fn with_collision_guard(mut self, reject: bool) -> Self {
+        self.collision_guard = Some(std::sync::Arc::new(CollisionGuard::new(
+            self.num_internal_shards(),
+            reject,
+        )));
+        self
+    }
+
+    /// Number of collisions detected, zero without a collision guard.
+    pub fn collision_count(&self) -> u64 {
+        self.collision_guard
+            .as_ref()
+            .map(|x| x.collisions.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Inserts `entry`, recording `fingerprint` for its sign when the collision guard is
+    /// enabled. A rejected collision leaves the holder unchanged.
+    pub fn insert_checked(
+        &self,
+        entry: HashMapEmbeddingEntry,
+        fingerprint: u32,
+    ) -> Result<(), PersiaEmbeddingHolderError> {
+        let sign = entry.sign();
+        if let Some(guard) = &self.collision_guard {
+            guard.record(sign, fingerprint)?;
+        }
+        let _ = self.shard(&sign).write().insert(sign, entry);
+        Ok(())
+    }
+
+    /// Looks up `sign` on behalf of the feature with `fingerprint`.
+    pub fn get_checked(
+        &self,
+        sign: u64,
+        fingerprint: u32,
+    ) -> Result<Option<HashMapEmbeddingEntry>, PersiaEmbeddingHolderError> {
+        if let Some(guard) = &self.collision_guard {
+            guard.check(sign, fingerprint)?;
+        }
+        Ok(self.get_entry(sign))
+    }
+}
+
+#[cfg(test)]
+mod collision_tests {
+    use super::*;
+
+    #[test]
+    fn test_collision_guard() {
+        let holder = PersiaEmbeddingHolder::new(100, 4).with_collision_guard(false);
+        let user = feature_fingerprint(b"user_id=42");
+        let item = feature_fingerprint(b"item_id=7");
+        assert_ne!(user, item);
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![1.0; 4], 5);
+        holder.insert_checked(entry.clone(), user).unwrap();
+        assert!(holder.get_checked(5, user).unwrap().is_some());
+        assert_eq!(holder.collision_count(), 0);
+
+        // detected but not rejected
+        assert!(holder.get_checked(5, item).unwrap().is_some());
+        holder.insert_checked(entry.clone(), item).unwrap();
+        assert_eq!(holder.collision_count(), 2);
+
+        let holder = PersiaEmbeddingHolder::new(100, 4).with_collision_guard(true);
+        holder.insert_checked(entry.clone(), user).unwrap();
+        assert!(matches!(
+            holder.get_checked(5, item),
+            Err(PersiaEmbeddingHolderError::SignCollision(5))
+        ));
+        let other = HashMapEmbeddingEntry::from_emb(vec![2.0; 4], 5);
+        assert!(holder.insert_checked(other, item).is_err());
+        assert_eq!(holder.get_entry(5).unwrap().emb(), entry.emb());
+        assert_eq!(holder.collision_count(), 2);
+
+        // fingerprints go away with the entries
+        holder.clear();
+        holder.insert_checked(entry, item).unwrap();
+        assert_eq!(holder.collision_count(), 2);
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{self, Read, Write};
+
+use persia_libs::hashbrown::HashMap;
+
+use crate::checkpoint::invalid_data;
+use crate::emb_entry::{content_hash, max_entry_len, HashMapEmbeddingEntry};
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+/// Version of the deduplicated dump written by [`PersiaEmbeddingHolder::dump_deduped`].
+pub const DEDUPED_STREAM_VERSION: u16 = 1;
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Writes the embeddings with every distinct embedding stored once, e.g. for tables where
+    /// many rows stopped changing. The dump holds a little endian header of the version as u16
+    /// and the number of rows, the number of signs and the embedding dim as u64, then the rows
+    /// of `dim` f32 values, then one `(sign, row)` pair of u64 per sign. Optimizer states are not
+    /// stored. Rows are shared by bit identical embeddings found through
+    /// [`HashMapEmbeddingEntry::content_hash`]. Tombstoned entries are left out. Fails if the
+    /// entries do not share one embedding dim, returns the number of stored rows.
+    pub fn dump_deduped<W: Write>(&self, w: &mut W) -> io::Result<usize> {
+        let guard = self.read_all();
+        let mut entries = guard.iter();
+        let dim = entries.next().map(|x| x.emb.len()).unwrap_or(0);
+        if let Some(entry) = entries.find(|x| x.emb.len() != dim) {
+            return Err(invalid_data(format!(
+                "sign {} has embedding dim {}, expected {}",
+                entry.sign,
+                entry.emb.len(),
+                dim
+            )));
+        }
+
+        // rows by content hash, hash collisions of distinct embeddings get their own rows
+        let mut rows_by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut rows: Vec<&[f32]> = Vec::new();
+        let mut index: Vec<(u64, usize)> = Vec::new();
+        for entry in guard.iter() {
+            let candidates = rows_by_hash.entry(content_hash(entry.emb)).or_default();
+            let same_bits = |row: &[f32]| {
+                row.iter()
+                    .zip(entry.emb.iter())
+                    .all(|(x, y)| x.to_bits() == y.to_bits())
+            };
+            let found = candidates.iter().copied().find(|x| same_bits(rows[*x]));
+            let row = match found {
+                Some(row) => row,
+                None => {
+                    rows.push(entry.emb);
+                    candidates.push(rows.len() - 1);
+                    rows.len() - 1
+                }
+            };
+            index.push((entry.sign, row));
+        }
+
+        w.write_all(&DEDUPED_STREAM_VERSION.to_le_bytes())?;
+        w.write_all(&(rows.len() as u64).to_le_bytes())?;
+        w.write_all(&(index.len() as u64).to_le_bytes())?;
+        w.write_all(&(dim as u64).to_le_bytes())?;
+        for row in rows.iter() {
+            let bytes: Vec<u8> = row.iter().flat_map(|x| x.to_le_bytes()).collect();
+            w.write_all(&bytes)?;
+        }
+        for (sign, row) in index.iter() {
+            w.write_all(&sign.to_le_bytes())?;
+            w.write_all(&(*row as u64).to_le_bytes())?;
+        }
+        Ok(rows.len())
+    }
+
+    /// Reads a dump written by [`PersiaEmbeddingHolder::dump_deduped`] and inserts an entry
+    /// without optimizer state for every sign, copying the shared rows. Returns the number of
+    /// loaded entries.
+    pub fn load_deduped<R: Read>(&self, r: &mut R) -> io::Result<usize> {
+        let mut version = [0u8; 2];
+        r.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version > DEDUPED_STREAM_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported deduped stream version {}",
+                version
+            )));
+        }
+        let num_rows = read_u64(r)?;
+        let num_signs = read_u64(r)?;
+        let dim = read_u64(r)? as usize;
+        if dim > max_entry_len() {
+            return Err(invalid_data(format!(
+                "embedding dim {} exceeds max entry length {}",
+                dim,
+                max_entry_len()
+            )));
+        }
+
+        // every written row is referenced by a sign, and only an empty dump has no dim
+        if num_rows > num_signs || (num_rows > 0 && dim == 0) {
+            return Err(invalid_data(format!(
+                "{} rows of dim {} for {} signs",
+                num_rows, dim, num_signs
+            )));
+        }
+
+        // rows are read one at a time and are not empty, so a corrupted row count fails at the
+        // end of the stream instead of allocating up front
+        let mut row_bytes = vec![0u8; dim * 4];
+        let mut rows: Vec<Vec<f32>> = Vec::new();
+        for _ in 0..num_rows {
+            r.read_exact(&mut row_bytes)?;
+            rows.push(
+                row_bytes
+                    .chunks_exact(4)
+                    .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                    .collect(),
+            );
+        }
+        for _ in 0..num_signs {
+            let sign = read_u64(r)?;
+            let row = read_u64(r)?;
+            let emb = rows.get(row as usize).ok_or_else(|| {
+                invalid_data(format!("sign {} refers to missing row {}", sign, row))
+            })?;
+            let entry = HashMapEmbeddingEntry::from_emb(emb.clone(), sign);
+            let _ = self.shard(&sign).write().insert(sign, entry);
+        }
+        Ok(num_signs as usize)
+    }
+}
+
+#[cfg(test)]
+mod dedup_tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_deduped() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let embs = [
+            (1u64, vec![0.5, -1.0, 2.0]),
+            (2, vec![0.5, -1.0, 2.0]),
+            (3, vec![0.0, 1.0, 1.0]),
+            (4, vec![-0.0, 1.0, 1.0]),
+        ];
+        embs.iter().for_each(|(sign, emb)| {
+            let entry =
+                HashMapEmbeddingEntry::from_emb_and_opt(emb.clone(), &[*sign as f32], *sign);
+            let _ = holder.shard(sign).write().insert(*sign, entry);
+        });
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![7.0; 3], 5);
+        let _ = holder.shard(&5).write().insert(5, entry);
+        holder.tombstone(5);
+
+        let mut dump = Vec::new();
+        // signs 1 and 2 share a row, the signed zero keeps 3 and 4 apart
+        assert_eq!(holder.dump_deduped(&mut dump).unwrap(), 3);
+        assert_eq!(dump.len(), 2 + 3 * 8 + 3 * 3 * 4 + 4 * 16);
+
+        let restored = PersiaEmbeddingHolder::new(1000, 4);
+        assert_eq!(restored.load_deduped(&mut dump.as_slice()).unwrap(), 4);
+        assert!(restored.get_entry(5).is_none());
+        embs.iter().for_each(|(sign, emb)| {
+            let entry = restored.get_entry(*sign).unwrap();
+            assert_eq!(entry.emb(), emb.as_slice());
+            assert_eq!(entry.emb()[0].to_bits(), emb[0].to_bits());
+            assert!(entry.opt().is_empty());
+        });
+        assert_eq!(
+            restored.get_entry(1).unwrap().content_hash(),
+            restored.get_entry(2).unwrap().content_hash()
+        );
+
+        let truncated = &dump[..dump.len() - 4];
+        assert!(PersiaEmbeddingHolder::new(1000, 4)
+            .load_deduped(&mut &truncated[..])
+            .is_err());
+        let entry = HashMapEmbeddingEntry::from_emb(vec![0.0; 8], 10);
+        let _ = holder.shard(&10).write().insert(10, entry);
+        assert!(holder.dump_deduped(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_load_deduped_rejects_corrupt_header() {
+        let header = |num_rows: u64, num_signs: u64, dim: u64| {
+            let mut dump = DEDUPED_STREAM_VERSION.to_le_bytes().to_vec();
+            [num_rows, num_signs, dim]
+                .iter()
+                .for_each(|x| dump.extend_from_slice(&x.to_le_bytes()));
+            dump
+        };
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        // empty rows would be read without consuming the stream
+        assert!(holder
+            .load_deduped(&mut header(u64::MAX, u64::MAX, 0).as_slice())
+            .is_err());
+        assert!(holder
+            .load_deduped(&mut header(u64::MAX, 1, 4).as_slice())
+            .is_err());
+
+        let mut empty = Vec::new();
+        assert_eq!(holder.dump_deduped(&mut empty).unwrap(), 0);
+        assert_eq!(empty, header(0, 0, 0));
+        assert_eq!(holder.load_deduped(&mut empty.as_slice()).unwrap(), 0);
+    }
+}
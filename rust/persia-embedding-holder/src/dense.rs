@@ -0,0 +1,276 @@
+use std::borrow::Cow;
+
+use persia_libs::rayon::prelude::*;
+
+use persia_embedding_config::InitializationMethod;
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::map_backend::HolderMapBackend;
+use crate::sharded::get_index;
+use crate::{PersiaEmbeddingHolder, PersiaEmbeddingHolderError};
+
+/// How [`PersiaEmbeddingHolder::gather_dense`] fills the rows of missing and tombstoned signs,
+/// and how [`PersiaEmbeddingHolder::scatter_update`] treats missing signs.
+#[derive(Clone, Debug)]
+pub enum MissingPolicy {
+    /// Fill the row with zeros, skip the sign when updating.
+    Zero,
+    /// Fail with [`PersiaEmbeddingHolderError::SignNotFound`].
+    Error,
+    /// Use the embedding initialized with seed `seed_base ^ sign`, which is the embedding
+    /// [`PersiaEmbeddingHolder::get_or_init`] would store for the sign. `gather_dense` does not
+    /// store it.
+    InitOnTheFly(InitializationMethod, u64),
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Batched lookup returning the embeddings packed into one row major `[signs.len(), dim]`
+    /// buffer, row `i` is the embedding of `signs[i]`. Rows are copied straight from the shards
+    /// into the buffer in parallel. Fails if a present entry has another embedding dim than
+    /// `dim`, or if a sign is missing under [`MissingPolicy::Error`].
+    pub fn gather_dense(
+        &self,
+        signs: &[u64],
+        dim: usize,
+        missing: MissingPolicy,
+    ) -> Result<Vec<f32>, PersiaEmbeddingHolderError> {
+        assert!(dim > 0, "embedding dim must be positive");
+        let mut output = vec![0f32; signs.len() * dim];
+        let hits: Result<u64, PersiaEmbeddingHolderError> = output
+            .par_chunks_mut(dim)
+            .zip(signs.par_iter())
+            .map(|(row, sign)| {
+                let shard = self.shard(sign).read();
+                match shard.get(sign).filter(|x| !x.is_tombstoned()) {
+                    Some(entry) if entry.embedding_dim() != dim => {
+                        Err(PersiaEmbeddingHolderError::DimMismatch {
+                            sign: *sign,
+                            expected: dim,
+                            actual: entry.embedding_dim(),
+                        })
+                    }
+                    Some(entry) => {
+                        row.copy_from_slice(entry.emb());
+                        Ok(1)
+                    }
+                    None => {
+                        drop(shard);
+                        match &missing {
+                            MissingPolicy::Zero => Ok(0),
+                            MissingPolicy::Error => {
+                                Err(PersiaEmbeddingHolderError::SignNotFound(*sign))
+                            }
+                            MissingPolicy::InitOnTheFly(initialization_method, seed_base) => {
+                                let entry = HashMapEmbeddingEntry::new(
+                                    initialization_method,
+                                    dim,
+                                    0,
+                                    seed_base ^ sign,
+                                    *sign,
+                                );
+                                row.copy_from_slice(entry.emb());
+                                Ok(0)
+                            }
+                        }
+                    }
+                }
+            })
+            .sum();
+        let hits = hits?;
+        self.counters
+            .record_lookups(hits, signs.len() as u64 - hits);
+        Ok(output)
+    }
+
+    /// Applies SGD updates from a packed `[signs.len(), dim]` gradient buffer, the counterpart
+    /// of [`PersiaEmbeddingHolder::gather_dense`]. The gradients of a sign occurring more than
+    /// once are summed and applied once. Missing signs are skipped under [`MissingPolicy::Zero`],
+    /// fail the update under [`MissingPolicy::Error`], and are initialized like in
+    /// `gather_dense`, stored without optimizer space and then updated under
+    /// [`MissingPolicy::InitOnTheFly`]. Tombstoned and frozen signs are skipped. Returns the
+    /// number of updated signs.
+    ///
+    /// Like [`PersiaEmbeddingHolder::apply_gradients`], a shard is checked before any of its
+    /// entries is updated, while other shards may already be updated when an error is returned.
+    pub fn scatter_update(
+        &self,
+        signs: &[u64],
+        dim: usize,
+        grads: &[f32],
+        lr: f32,
+        missing: MissingPolicy,
+    ) -> Result<usize, PersiaEmbeddingHolderError> {
+        assert!(dim > 0, "embedding dim must be positive");
+        assert_eq!(
+            grads.len(),
+            signs.len() * dim,
+            "signs and gradients length mismatch"
+        );
+        let grad_rows: Vec<&[f32]> = grads.chunks(dim).collect();
+        let num_internal_shards = self.num_internal_shards();
+        let mut groups: Vec<Vec<(u64, Cow<[f32]>)>> =
+            (0..num_internal_shards).map(|_| Vec::new()).collect();
+        PersiaEmbeddingHolder::merge_gradients(signs, &grad_rows)
+            .into_iter()
+            .for_each(|(sign, grad)| {
+                groups[get_index(&sign, num_internal_shards)].push((sign, grad));
+            });
+
+        let updated: Result<usize, PersiaEmbeddingHolderError> = groups
+            .par_iter()
+            .enumerate()
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(shard_idx, group)| {
+                let mut shard = self.get_shard_by_index(shard_idx).write();
+                for (sign, _) in group.iter() {
+                    match shard.get(sign) {
+                        Some(entry) if entry.embedding_dim() != dim => {
+                            return Err(PersiaEmbeddingHolderError::DimMismatch {
+                                sign: *sign,
+                                expected: dim,
+                                actual: entry.embedding_dim(),
+                            });
+                        }
+                        None if matches!(missing, MissingPolicy::Error) => {
+                            return Err(PersiaEmbeddingHolderError::SignNotFound(*sign));
+                        }
+                        _ => {}
+                    }
+                }
+
+                let (mut updated, mut insertions, mut evictions) = (0, 0, 0);
+                for (sign, grad) in group.iter() {
+                    if shard.get(sign).is_none() {
+                        match &missing {
+                            MissingPolicy::InitOnTheFly(initialization_method, seed_base) => {
+                                let entry = HashMapEmbeddingEntry::new(
+                                    initialization_method,
+                                    dim,
+                                    0,
+                                    seed_base ^ sign,
+                                    *sign,
+                                );
+                                let (_, evicted) = shard.insert(*sign, entry);
+                                insertions += 1;
+                                evictions += evicted.is_some() as u64;
+                            }
+                            _ => continue,
+                        }
+                    }
+                    let skipped = shard
+                        .get(sign)
+                        .map_or(true, |x| x.is_tombstoned() || x.is_frozen());
+                    if skipped {
+                        continue;
+                    }
+                    let entry = shard.get_mut(sign).unwrap();
+                    entry
+                        .emb_mut()
+                        .iter_mut()
+                        .zip(grad.iter())
+                        .for_each(|(w, g)| *w -= lr * g);
+                    updated += 1;
+                }
+                self.counters.record_insertions(insertions, evictions);
+                Ok(updated)
+            })
+            .sum();
+        updated
+    }
+}
+
+#[cfg(test)]
+mod dense_tests {
+    use super::*;
+
+    fn holder_with_rows() -> PersiaEmbeddingHolder {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        (1..4u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![sign as f32; 2], &[9.0], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        holder
+    }
+
+    #[test]
+    fn test_gather_dense_zero_and_error() {
+        let holder = holder_with_rows();
+        holder.tombstone(2);
+
+        let dense = holder
+            .gather_dense(&[3, 7, 1, 2, 3], 2, MissingPolicy::Zero)
+            .unwrap();
+        assert_eq!(
+            dense,
+            vec![3.0, 3.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 3.0, 3.0]
+        );
+
+        assert!(holder
+            .gather_dense(&[1, 3], 2, MissingPolicy::Error)
+            .is_ok());
+        assert!(matches!(
+            holder.gather_dense(&[1, 7], 2, MissingPolicy::Error),
+            Err(PersiaEmbeddingHolderError::SignNotFound(7))
+        ));
+        assert!(matches!(
+            holder.gather_dense(&[1], 4, MissingPolicy::Zero),
+            Err(PersiaEmbeddingHolderError::DimMismatch {
+                sign: 1,
+                expected: 4,
+                actual: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_scatter_update_sums_duplicates() {
+        let holder = holder_with_rows();
+        let signs = [1u64, 2, 1, 7];
+        let grads = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 8.0];
+
+        let updated = holder
+            .scatter_update(&signs, 2, &grads, 0.5, MissingPolicy::Zero)
+            .unwrap();
+        assert_eq!(updated, 2);
+        // sign 1 is updated once with the sum of its two gradients
+        assert_eq!(holder.get_entry(1).unwrap().emb(), &[-2.0, -3.0]);
+        assert_eq!(holder.get_entry(2).unwrap().emb(), &[0.5, 0.0]);
+        assert_eq!(holder.get_entry(1).unwrap().opt(), &[9.0]);
+        assert!(holder.get_entry(7).is_none());
+
+        assert!(matches!(
+            holder.scatter_update(&signs, 2, &grads, 0.5, MissingPolicy::Error),
+            Err(PersiaEmbeddingHolderError::SignNotFound(7))
+        ));
+
+        let initialization = InitializationMethod::default();
+        let policy = MissingPolicy::InitOnTheFly(initialization, 42);
+        let initialized = holder.gather_dense(&[7], 2, policy.clone()).unwrap();
+        holder
+            .scatter_update(&[7], 2, &[2.0, -2.0], 0.5, policy)
+            .unwrap();
+        let entry = holder.get_entry(7).unwrap();
+        assert_eq!(entry.emb(), &[initialized[0] - 1.0, initialized[1] + 1.0]);
+    }
+
+    #[test]
+    fn test_gather_dense_init_on_the_fly() {
+        let holder = holder_with_rows();
+        let initialization = InitializationMethod::default();
+        let policy = MissingPolicy::InitOnTheFly(initialization.clone(), 42);
+
+        let dense = holder.gather_dense(&[1, 8, 9], 2, policy.clone()).unwrap();
+        assert_eq!(&dense[..2], &[1.0, 1.0]);
+        assert_eq!(dense, holder.gather_dense(&[1, 8, 9], 2, policy).unwrap());
+        // nothing is stored, the rows match what get_or_init stores afterwards
+        assert!(holder.get_entry(8).is_none());
+        assert_eq!(
+            &dense[2..4],
+            holder.get_or_init(8, &initialization, 2, 1, 42).emb()
+        );
+        assert_eq!(
+            &dense[4..],
+            holder.get_or_init(9, &initialization, 2, 1, 42).emb()
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::iter::EmbeddingEntryRef;
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+/// Differences between two holders, see [`PersiaEmbeddingHolder::diff`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HolderDiff {
+    /// Signs only present in the other holder, in ascending order.
+    pub added: Vec<u64>,
+    /// Signs only present in this holder, in ascending order.
+    pub removed: Vec<u64>,
+    /// Signs whose values differ with the max absolute element delta, the signs that moved most
+    /// come first. Entries whose lengths differ have an infinite delta.
+    pub changed: Vec<(u64, f32)>,
+}
+
+impl HolderDiff {
+    pub fn num_added(&self) -> usize {
+        self.added.len()
+    }
+
+    pub fn num_removed(&self) -> usize {
+        self.removed.len()
+    }
+
+    pub fn num_changed(&self) -> usize {
+        self.changed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn max_abs_delta(x: &[f32], y: &[f32]) -> f32 {
+    if x.len() != y.len() {
+        return f32::INFINITY;
+    }
+    x.iter()
+        .zip(y.iter())
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f32::max)
+}
+
+fn entry_delta(x: &EmbeddingEntryRef, y: &EmbeddingEntryRef, include_opt: bool) -> f32 {
+    let delta = max_abs_delta(x.emb, y.emb);
+    if include_opt {
+        delta.max(max_abs_delta(x.opt, y.opt))
+    } else {
+        delta
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Compares the embeddings of this holder, e.g. loaded from an earlier checkpoint, with the
+    /// ones of `other`. Optimizer states are not compared.
+    pub fn diff(&self, other: &Self) -> HolderDiff {
+        self.diff_with_opt(other, false)
+    }
+
+    /// Like [`PersiaEmbeddingHolder::diff`], but also compares optimizer states when
+    /// `include_opt` is set.
+    pub fn diff_with_opt(&self, other: &Self, include_opt: bool) -> HolderDiff {
+        let this = self.read_all();
+        let other = other.read_all();
+        let mut diff = HolderDiff::default();
+        this.iter().for_each(|entry| match other.get(entry.sign) {
+            Some(other_entry) => {
+                let delta = entry_delta(&entry, &other_entry, include_opt);
+                if delta > 0.0 || delta.is_nan() {
+                    diff.changed.push((entry.sign, delta));
+                }
+            }
+            None => diff.removed.push(entry.sign),
+        });
+        diff.added = other
+            .iter()
+            .filter(|entry| this.get(entry.sign).is_none())
+            .map(|entry| entry.sign)
+            .collect();
+
+        diff.added.sort_unstable();
+        diff.removed.sort_unstable();
+        diff.changed.sort_unstable_by(|x, y| {
+            y.1.partial_cmp(&x.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(x.0.cmp(&y.0))
+        });
+        diff
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    fn holder_with(signs: &[u64]) -> PersiaEmbeddingHolder {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        signs.iter().for_each(|sign| {
+            let entry =
+                HashMapEmbeddingEntry::from_emb_and_opt(vec![*sign as f32; 4], &[0.5], *sign);
+            let _ = holder.shard(sign).write().insert(*sign, entry);
+        });
+        holder
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = holder_with(&[1, 2, 3, 4]);
+        let after = holder_with(&[2, 3, 4, 5]);
+        assert!(before.diff(&before).is_empty());
+
+        after.shard(&3).write().get_mut(&3).unwrap().emb_mut()[2] = 3.25;
+        after.shard(&4).write().get_mut(&4).unwrap().opt_mut()[0] = 0.0;
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added, vec![5]);
+        assert_eq!(diff.removed, vec![1]);
+        assert_eq!(diff.changed, vec![(3, 0.25)]);
+        assert_eq!(
+            (diff.num_added(), diff.num_removed(), diff.num_changed()),
+            (1, 1, 1)
+        );
+
+        let diff = before.diff_with_opt(&after, true);
+        assert_eq!(diff.changed, vec![(4, 0.5), (3, 0.25)]);
+    }
+}
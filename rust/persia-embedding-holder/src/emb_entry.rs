@@ -1,30 +1,211 @@
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use persia_libs::{
     ndarray::Array1,
-    ndarray_rand::rand_distr::{Gamma, Normal, Poisson, Uniform},
+    ndarray_rand::rand_distr::{
+        Beta, Cauchy, Distribution, Exp, Gamma, LogNormal, Normal, Poisson, Uniform, WeightedIndex,
+    },
     ndarray_rand::RandomExt,
     rand::prelude::SmallRng,
     rand::SeedableRng,
+    rayon::prelude::*,
     serde::{self, Deserialize, Serialize},
+    thiserror, zstd,
 };
 
 use persia_embedding_config::InitializationMethod;
-use persia_speedy::{Readable, Writable};
+use persia_speedy::{Context, Readable, Writable};
 
+use crate::aligned::{AlignedVec, EntryBuffer};
 use crate::eviction_map::EvictionMapValue;
+use crate::half_entry::{Bf16EmbeddingEntry, F16EmbeddingEntry};
+use crate::PersiaEmbeddingHolderError;
+
+// Serialized entries start with ENTRY_MAGIC followed by a little endian u16 format version.
+// Version 1 is the layout derived by speedy in earlier releases, which has no magic and starts
+// with the u32 number of values. No entry has u32::MAX values, see max_entry_len, so the magic
+// tells the layouts apart in either byte order. Fields of later versions are always written
+// little endian regardless of the host. Since version 4 entries end with a CRC32 of the version
+// and the following bytes.
+pub const ENTRY_FORMAT_VERSION: u16 = 4;
+const ENTRY_FORMAT_FIRST_CHECKSUMMED: u16 = 4;
+const ENTRY_MAGIC: u32 = u32::MAX;
+// Set in the v2 flags byte when the fields were written big endian.
+const ENTRY_BIG_ENDIAN_FLAG: u8 = 0x80;
+// Set in the v2 flags byte when the entry was stored in an aligned buffer, so that the reader
+// restores the alignment.
+const ENTRY_ALIGNED_FLAG: u8 = 0x01;
+// Set in the v2 flags byte when the entry is frozen.
+const ENTRY_FROZEN_FLAG: u8 = 0x02;
+
+// Default of the max entry length, 16M values or 64MB per entry.
+pub const DEFAULT_MAX_ENTRY_LEN: usize = 1 << 24;
+static MAX_ENTRY_LEN: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_ENTRY_LEN);
+
+/// Max number of values, embedding and optimizer state, of an entry built by the `try_new*`
+/// constructors. Larger entries are rejected before allocating.
+pub fn max_entry_len() -> usize {
+    MAX_ENTRY_LEN.load(Ordering::Relaxed)
+}
+
+/// Sets the process wide [`max_entry_len`], e.g. from the parameter server config.
+pub fn set_max_entry_len(len: usize) {
+    MAX_ENTRY_LEN.store(len, Ordering::Relaxed);
+}
+
+// Magic, version, flags, four u64 header fields and the checksum of a current entry.
+const ENTRY_OVERHEAD_BYTES: usize = 4 + 2 + 1 + 4 * 8 + 4;
+
+/// Max length of a serialized entry of at most [`max_entry_len`] values, used to reject
+/// corrupt length prefixes before allocating.
+pub(crate) fn max_serialized_entry_len() -> usize {
+    max_entry_len()
+        .saturating_mul(4)
+        .saturating_add(ENTRY_OVERHEAD_BYTES)
+}
+
+// Default of the max embedding dim accepted when reading entries.
+pub const DEFAULT_MAX_EMBEDDING_DIM: usize = 1 << 20;
+static MAX_EMBEDDING_DIM: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_EMBEDDING_DIM);
+
+/// Max embedding dim of a deserialized entry. Entries claiming a larger dim, e.g. from a corrupt
+/// or untrusted checkpoint, are rejected before allocating. Speedy contexts only carry the
+/// endianness, so like [`max_entry_len`] this limit is process wide.
+pub fn max_embedding_dim() -> usize {
+    MAX_EMBEDDING_DIM.load(Ordering::Relaxed)
+}
+
+/// Sets the process wide [`max_embedding_dim`], e.g. from the parameter server config.
+pub fn set_max_embedding_dim(dim: usize) {
+    MAX_EMBEDDING_DIM.store(dim, Ordering::Relaxed);
+}
+
+// Rejection sampling retries of truncated normal initialization before clamping into bounds.
+const TRUNCATED_NORMAL_MAX_RETRIES: usize = 32;
 
-#[derive(Serialize, Deserialize, Readable, Writable, Clone, Debug)]
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum EntryError {
+    #[error("embedding dim {embedding_dim} exceeds entry length {inner_len}")]
+    InvalidLength {
+        embedding_dim: usize,
+        inner_len: usize,
+    },
+    #[error("unsupported embedding entry format version {version}, max supported version is {max_supported}")]
+    UnsupportedVersion { version: u16, max_supported: u16 },
+    #[error("input length {actual} does not match embedding dim {expected}")]
+    DimMismatch { expected: usize, actual: usize },
+    #[error("corrupt embedding entry of sign {sign}, checksum {actual:#010x} does not match stored checksum {expected:#010x}")]
+    CorruptEntry {
+        sign: u64,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("embedding dim {dim} of serialized entry exceeds max embedding dim {max_dim}")]
+    InvalidDimension { dim: usize, max_dim: usize },
+}
+
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("unsupported initialization method for hashmap impl: {0}")]
+    UnsupportedMethod(String),
+    #[error("invalid parameters of {method} initialization: {reason}")]
+    InvalidParameter { method: String, reason: String },
+    #[error("custom initialization returned {actual} values for embedding dim {expected}")]
+    InvalidLength { expected: usize, actual: usize },
+    #[error("entry of embedding dim {dim} with optimizer space {require_space} exceeds max entry length {max_len}")]
+    EntryTooLarge {
+        dim: usize,
+        require_space: usize,
+        max_len: usize,
+    },
+}
+
+// Length of an entry, checked for overflow and against the max entry length before allocating.
+fn checked_entry_len(dim: usize, require_space: usize) -> Result<usize, InitError> {
+    let max_len = max_entry_len();
+    dim.checked_add(require_space)
+        .filter(|len| *len <= max_len)
+        .ok_or(InitError::EntryTooLarge {
+            dim,
+            require_space,
+            max_len,
+        })
+}
+
+/// [`HashMapEmbeddingEntry::content_hash`] of an entry with embedding `emb`.
+pub(crate) fn content_hash(emb: &[f32]) -> u64 {
+    let mut bytes = Vec::with_capacity(8 + emb.len() * 4);
+    bytes.extend_from_slice(&(emb.len() as u64).to_le_bytes());
+    emb.iter()
+        .for_each(|x| bytes.extend_from_slice(&x.to_le_bytes()));
+    farmhash::hash64(bytes.as_slice())
+}
+
+fn invalid_parameter(method: &str, reason: impl std::fmt::Display) -> InitError {
+    InitError::InvalidParameter {
+        method: method.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// Uniform::new panics on empty or infinite ranges, e.g. from a zero fan.
+fn uniform(method: &'static str, lower: f32, upper: f32) -> Result<Uniform<f32>, InitError> {
+    let valid = lower < upper && (upper - lower).is_finite();
+    if !valid {
+        return Err(invalid_parameter(
+            method,
+            format!("empty or infinite range [{}, {})", lower, upper),
+        ));
+    }
+    Ok(Uniform::new(lower, upper))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(crate = "self::serde")]
 pub struct HashMapEmbeddingEntry {
-    inner: Vec<f32>, // TODO option1: consider using smallvec and slab allocator, and reference that smallvec with &[f32] here to avoid const generics
+    inner: EntryBuffer, // TODO option1: consider using smallvec and slab allocator, and reference that smallvec with &[f32] here to avoid const generics
     // TODO option2: consider wrap BufferPool (see crates.io) or modify sharded slab to allocate &[f32] here
     // TODO option3: consider using a object pool of &[f32] with predefined length and all these &[f32] comes from a large continuous Vec. When the object pool is exhausted, create a new large continuous Vec and split it to &[f32]s and add them to the object pool
     // TODO option4: allocate slices and put them in the slice_arena (see crates.io), then put the slice in the arena into a reusable object pool for consumption
     // TODO option5: allocate slices in bumpalo_herd allocator with alloc_slice_fill_default, and unsafely converts it to Vec, then put the Vec in a reusable object pool for consumption. In this case we can actually put the whole entry in the pool
     embedding_dim: usize,
     sign: u64,
+    // Set by mutable access, cleared by incremental checkpoints. New entries start dirty,
+    // entries decoded from a checkpoint start clean.
+    #[serde(skip)]
+    dirty: bool,
+    // Number of mutable accesses through the holder, driving the frequency adaptive learning
+    // rate. It is kept in memory only and restarts from zero after loading a checkpoint.
+    #[serde(skip)]
+    access_count: u32,
+    // Frozen entries, e.g. of tables fixed during fine tuning, panic on mutable access.
+    #[serde(default)]
+    frozen: bool,
+    // Soft deleted entries are hidden from holder lookups until the holder is compacted.
+    #[serde(skip)]
+    tombstoned: bool,
+    // Last training step that updated the entry, set by the optimizer through `touch`.
+    #[serde(default)]
+    last_step: u64,
 }
 
 impl HashMapEmbeddingEntry {
+    // New entries start dirty, unfrozen and never touched by the optimizer.
+    fn from_parts(inner: EntryBuffer, embedding_dim: usize, sign: u64) -> Self {
+        Self {
+            inner,
+            embedding_dim,
+            sign,
+            dirty: true,
+            access_count: 0,
+            frozen: false,
+            tombstoned: false,
+            last_step: 0,
+        }
+    }
+
     pub fn new(
         initialization_method: &InitializationMethod,
         dim: usize,
@@ -32,80 +213,519 @@ impl HashMapEmbeddingEntry {
         seed: u64,
         sign: u64,
     ) -> Self {
-        let emb = {
-            let mut rng = SmallRng::seed_from_u64(seed);
-            match initialization_method {
-                InitializationMethod::BoundedUniform(x) => {
-                    Array1::random_using((dim,), Uniform::new(x.lower, x.upper), &mut rng)
-                }
-                InitializationMethod::BoundedGamma(x) => {
-                    Array1::random_using((dim,), Gamma::new(x.shape, x.scale).unwrap(), &mut rng)
-                }
-                InitializationMethod::BoundedPoisson(x) => {
-                    Array1::random_using((dim,), Poisson::new(x.lambda).unwrap(), &mut rng)
+        Self::try_new(initialization_method, dim, require_space, seed, sign)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`HashMapEmbeddingEntry::new`], but returns an error instead of panicking for
+    /// unsupported initialization methods and invalid distribution parameters, so that one bad
+    /// config row does not take down the server.
+    pub fn try_new(
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        seed: u64,
+        sign: u64,
+    ) -> Result<Self, InitError> {
+        checked_entry_len(dim, require_space)?;
+        let emb = match initialization_method {
+            InitializationMethod::Zeros => Array1::zeros((dim,)),
+            InitializationMethod::Constant(x) => Array1::from_elem((dim,), x.value),
+            InitializationMethod::Custom(x) => {
+                return Self::try_new_with_fn(&*x.0, dim, require_space, seed, sign)
+            }
+            InitializationMethod::Clamped(x) => {
+                let valid = x.lower <= x.upper;
+                if !valid {
+                    return Err(invalid_parameter(
+                        "clamped",
+                        format!("empty range [{}, {}]", x.lower, x.upper),
+                    ));
                 }
-                InitializationMethod::BoundedNormal(x) => Array1::random_using(
-                    (dim,),
-                    Normal::new(x.mean, x.standard_deviation).unwrap(),
-                    &mut rng,
-                ),
-                _ => panic!(
-                    "unsupported initialization method for hashmap impl: {:?}",
-                    initialization_method
-                ),
+                let mut entry = Self::try_new(&x.inner, dim, require_space, seed, sign)?;
+                entry
+                    .emb_mut()
+                    .iter_mut()
+                    .for_each(|v| *v = v.max(x.lower).min(x.upper));
+                return Ok(entry);
             }
+            _ => Self::sample_emb(initialization_method, dim, seed)?,
         };
 
         let mut inner = emb.into_raw_vec();
         if require_space > 0 {
             inner.resize(inner.len() + require_space, 0.0_f32);
         }
-        Self {
-            inner,
-            embedding_dim: dim,
+        Ok(Self::from_parts(inner.into(), dim, sign))
+    }
+
+    /// Initialize the embedding with `f`, which receives the rng seeded with `seed` and the
+    /// embedding dim and returns the `dim` values of the embedding.
+    pub fn new_with_fn<F>(f: F, dim: usize, require_space: usize, seed: u64, sign: u64) -> Self
+    where
+        F: FnOnce(&mut SmallRng, usize) -> Vec<f32>,
+    {
+        Self::try_new_with_fn(f, dim, require_space, seed, sign).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new_with_fn<F>(
+        f: F,
+        dim: usize,
+        require_space: usize,
+        seed: u64,
+        sign: u64,
+    ) -> Result<Self, InitError>
+    where
+        F: FnOnce(&mut SmallRng, usize) -> Vec<f32>,
+    {
+        let len = checked_entry_len(dim, require_space)?;
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut inner = f(&mut rng, dim);
+        if inner.len() != dim {
+            return Err(InitError::InvalidLength {
+                expected: dim,
+                actual: inner.len(),
+            });
+        }
+        inner.resize(len, 0.0_f32);
+        Ok(Self::from_parts(inner.into(), dim, sign))
+    }
+
+    /// Initialize the entry of `sign` with the per row seed [`seed_for_sign`] derives from
+    /// `global_seed`. Identical `(sign, global_seed)` pairs yield identical entries on every
+    /// server, without coordinating seeds.
+    pub fn new_deterministic(
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        global_seed: u64,
+        sign: u64,
+    ) -> Self {
+        Self::new(
+            initialization_method,
+            dim,
+            require_space,
+            seed_for_sign(sign, global_seed),
             sign,
+        )
+    }
+
+    fn sample_emb(
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        seed: u64,
+    ) -> Result<Array1<f32>, InitError> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let emb = match initialization_method {
+            InitializationMethod::BoundedUniform(x) => {
+                Array1::random_using((dim,), uniform("uniform", x.lower, x.upper)?, &mut rng)
+            }
+            InitializationMethod::BoundedGamma(x) => {
+                let gamma =
+                    Gamma::new(x.shape, x.scale).map_err(|e| invalid_parameter("gamma", e))?;
+                Array1::random_using((dim,), gamma, &mut rng)
+            }
+            InitializationMethod::BoundedPoisson(x) => {
+                let poisson =
+                    Poisson::new(x.lambda).map_err(|e| invalid_parameter("poisson", e))?;
+                Array1::random_using((dim,), poisson, &mut rng)
+            }
+            InitializationMethod::BoundedNormal(x) => {
+                let normal = Normal::new(x.mean, x.standard_deviation)
+                    .map_err(|e| invalid_parameter("normal", e))?;
+                Array1::random_using((dim,), normal, &mut rng)
+            }
+            InitializationMethod::GlorotUniform(x) => {
+                let bound = (6.0 / x.fan_sum(dim) as f32).sqrt();
+                Array1::random_using((dim,), uniform("glorot uniform", -bound, bound)?, &mut rng)
+            }
+            InitializationMethod::GlorotNormal(x) => {
+                let standard_deviation = (2.0 / x.fan_sum(dim) as f32).sqrt();
+                let normal = Normal::new(0.0, standard_deviation)
+                    .map_err(|e| invalid_parameter("glorot normal", e))?;
+                Array1::random_using((dim,), normal, &mut rng)
+            }
+            InitializationMethod::KaimingUniform(x) => {
+                let bound = (6.0 / x.fan_in as f32).sqrt();
+                Array1::random_using((dim,), uniform("kaiming uniform", -bound, bound)?, &mut rng)
+            }
+            InitializationMethod::KaimingNormal(x) => {
+                let standard_deviation = (2.0 / x.fan_in as f32).sqrt();
+                let normal = Normal::new(0.0, standard_deviation)
+                    .map_err(|e| invalid_parameter("kaiming normal", e))?;
+                Array1::random_using((dim,), normal, &mut rng)
+            }
+            InitializationMethod::TruncatedNormal(x) => {
+                let valid = x.truncate_sigma > 0.0;
+                if !valid {
+                    return Err(invalid_parameter(
+                        "truncated normal",
+                        format!("requires a positive truncate sigma, got {:?}", x),
+                    ));
+                }
+                let normal = Normal::new(x.mean, x.standard_deviation)
+                    .map_err(|e| invalid_parameter("truncated normal", e))?;
+                let lower = x.mean - x.truncate_sigma * x.standard_deviation;
+                let upper = x.mean + x.truncate_sigma * x.standard_deviation;
+                Array1::from_shape_fn((dim,), |_| {
+                    for _ in 0..TRUNCATED_NORMAL_MAX_RETRIES {
+                        let value: f32 = normal.sample(&mut rng);
+                        if value >= lower && value <= upper {
+                            return value;
+                        }
+                    }
+                    let value: f32 = normal.sample(&mut rng);
+                    value.max(lower).min(upper)
+                })
+            }
+            InitializationMethod::BoundedBeta(x) => {
+                let valid = x.alpha > 0.0 && x.beta > 0.0;
+                if !valid {
+                    return Err(invalid_parameter(
+                        "beta",
+                        format!("requires positive alpha and beta, got {:?}", x),
+                    ));
+                }
+                let beta = Beta::new(x.alpha, x.beta).map_err(|e| invalid_parameter("beta", e))?;
+                Array1::random_using((dim,), beta, &mut rng)
+            }
+            InitializationMethod::BoundedExponential(x) => {
+                let valid = x.lambda > 0.0;
+                if !valid {
+                    return Err(invalid_parameter(
+                        "exponential",
+                        format!("requires a positive lambda, got {:?}", x),
+                    ));
+                }
+                let exp = Exp::new(x.lambda).map_err(|e| invalid_parameter("exponential", e))?;
+                Array1::random_using((dim,), exp, &mut rng)
+            }
+            InitializationMethod::BoundedLogNormal(x) => {
+                let log_normal = LogNormal::new(x.mu, x.sigma)
+                    .map_err(|e| invalid_parameter("log normal", e))?;
+                Array1::random_using((dim,), log_normal, &mut rng)
+            }
+            InitializationMethod::BoundedCauchy(x) => {
+                let valid = x.scale > 0.0;
+                if !valid {
+                    return Err(invalid_parameter(
+                        "cauchy",
+                        format!("requires a positive scale, got {:?}", x),
+                    ));
+                }
+                let cauchy =
+                    Cauchy::new(x.median, x.scale).map_err(|e| invalid_parameter("cauchy", e))?;
+                Array1::random_using((dim,), cauchy, &mut rng)
+            }
+            InitializationMethod::BoundedLaplace(x) => {
+                let valid = x.scale > 0.0;
+                if !valid {
+                    return Err(invalid_parameter(
+                        "laplace",
+                        format!("requires a positive scale, got {:?}", x),
+                    ));
+                }
+                // the difference of two exponential samples with rate 1 / scale is laplace
+                let exp = Exp::new(1.0 / x.scale).map_err(|e| invalid_parameter("laplace", e))?;
+                Array1::from_shape_fn((dim,), |_| {
+                    let (lhs, rhs): (f32, f32) = (exp.sample(&mut rng), exp.sample(&mut rng));
+                    x.mean + lhs - rhs
+                })
+            }
+            InitializationMethod::Categorical(x) => {
+                if x.values.is_empty() {
+                    return Err(invalid_parameter(
+                        "categorical",
+                        "requires at least one value",
+                    ));
+                }
+                match &x.weights {
+                    Some(weights) => {
+                        if weights.len() != x.values.len() {
+                            return Err(invalid_parameter(
+                                "categorical",
+                                format!("{} weights for {} values", weights.len(), x.values.len()),
+                            ));
+                        }
+                        let index = WeightedIndex::new(weights)
+                            .map_err(|e| invalid_parameter("categorical", e))?;
+                        Array1::from_shape_fn((dim,), |_| x.values[index.sample(&mut rng)])
+                    }
+                    None => {
+                        let index = Uniform::new(0, x.values.len());
+                        Array1::from_shape_fn((dim,), |_| x.values[index.sample(&mut rng)])
+                    }
+                }
+            }
+            InitializationMethod::Orthogonal(x) => {
+                let mut emb =
+                    Array1::random_using((dim,), Normal::new(0.0, 1.0).unwrap(), &mut rng);
+                let norm = emb.dot(&emb).sqrt();
+                if norm > 0.0 {
+                    emb.mapv_inplace(|v| v * x.gain / norm);
+                }
+                emb
+            }
+            _ => {
+                return Err(InitError::UnsupportedMethod(format!(
+                    "{:?}",
+                    initialization_method
+                )))
+            }
+        };
+        Ok(emb)
+    }
+
+    /// Initialize a contiguous batch of rows jointly. Orthogonal initialization of a single
+    /// row in [`HashMapEmbeddingEntry::new`] only yields a scaled unit vector, while here the
+    /// rows are orthogonalized against each other in groups of at most `dim` rows. Other
+    /// initialization methods initialize row `i` independently with seed `seed + i`. Panics on
+    /// the same errors as [`HashMapEmbeddingEntry::new`].
+    pub fn new_block(
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        seed: u64,
+        signs: &[u64],
+    ) -> Vec<Self> {
+        checked_entry_len(dim, require_space).unwrap_or_else(|e| panic!("{}", e));
+        match initialization_method {
+            InitializationMethod::Orthogonal(x) => {
+                let mut rng = SmallRng::seed_from_u64(seed);
+                let mut block = Array1::random_using(
+                    (signs.len() * dim,),
+                    Normal::new(0.0, 1.0).unwrap(),
+                    &mut rng,
+                )
+                .into_raw_vec();
+                orthonormalize_rows(block.as_mut_slice(), dim);
+
+                // one row per sign, also when the rows are empty
+                signs
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, sign)| {
+                        let row = &block[idx * dim..(idx + 1) * dim];
+                        let mut inner: Vec<f32> = row.iter().map(|v| v * x.gain).collect();
+                        inner.resize(dim + require_space, 0.0_f32);
+                        Self::from_parts(inner.into(), dim, *sign)
+                    })
+                    .collect()
+            }
+            _ => signs
+                .iter()
+                .enumerate()
+                .map(|(idx, sign)| {
+                    Self::new(
+                        initialization_method,
+                        dim,
+                        require_space,
+                        seed.wrapping_add(idx as u64),
+                        *sign,
+                    )
+                })
+                .collect(),
         }
     }
 
+    /// Initialize the entries of `signs` in parallel, e.g. to cold start a large table. The
+    /// entry of `sign` is seeded with `seed_base.wrapping_add(sign)` and gets its own rng, so
+    /// the result does not depend on how the work is scheduled across threads.
+    pub fn init_bulk(
+        signs: &[u64],
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        seed_base: u64,
+    ) -> Vec<Self> {
+        signs
+            .par_iter()
+            .map(|sign| {
+                Self::new(
+                    initialization_method,
+                    dim,
+                    require_space,
+                    seed_base.wrapping_add(*sign),
+                    *sign,
+                )
+            })
+            .collect()
+    }
+
     pub fn new_empty(dim: usize, require_space: usize, sign: u64) -> Self {
+        Self::try_new_empty(dim, require_space, sign).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new_empty(dim: usize, require_space: usize, sign: u64) -> Result<Self, InitError> {
+        let len = checked_entry_len(dim, require_space)?;
+        Ok(Self::from_parts(vec![0f32; len].into(), dim, sign))
+    }
+
+    /// Same as [`HashMapEmbeddingEntry::new`], but the entry is stored in a buffer aligned to
+    /// [`crate::aligned::ENTRY_ALIGNMENT`] bytes for SIMD kernels.
+    pub fn new_aligned(
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        seed: u64,
+        sign: u64,
+    ) -> Self {
+        Self::new(initialization_method, dim, require_space, seed, sign).into_aligned()
+    }
+
+    pub fn new_empty_aligned(dim: usize, require_space: usize, sign: u64) -> Self {
+        let len = checked_entry_len(dim, require_space).unwrap_or_else(|e| panic!("{}", e));
+        Self::from_parts(EntryBuffer::Aligned(AlignedVec::zeroed(len)), dim, sign)
+    }
+
+    pub fn into_aligned(self) -> Self {
         Self {
-            inner: vec![0f32; dim + require_space],
-            embedding_dim: dim,
-            sign,
+            inner: self.inner.into_aligned(),
+            ..self
         }
     }
 
+    pub fn is_aligned(&self) -> bool {
+        self.inner.is_aligned()
+    }
+
     pub fn from_emb(emb: Vec<f32>, sign: u64) -> Self {
         let embedding_dim = emb.len();
-        Self {
-            inner: emb,
-            embedding_dim,
-            sign,
-        }
+        Self::from_parts(emb.into(), embedding_dim, sign)
     }
 
     pub fn from_emb_and_opt(emb: Vec<f32>, opt: &[f32], sign: u64) -> Self {
         let embedding_dim = emb.len();
         let mut inner = emb;
         inner.extend_from_slice(opt);
-        Self {
-            inner,
-            embedding_dim,
-            sign,
+        Self::from_parts(inner.into(), embedding_dim, sign)
+    }
+
+    /// Build an entry from its raw content, where `inner` holds the embedding followed by the
+    /// optimizer state.
+    pub fn from_raw(inner: Vec<f32>, embedding_dim: usize, sign: u64) -> Result<Self, EntryError> {
+        let entry = Self::from_parts(inner.into(), embedding_dim, sign);
+        entry.validate()?;
+        Ok(entry)
+    }
+
+    /// Check the invariants of an entry, e.g. one decoded from an untrusted peer, so malformed
+    /// entries can be rejected before slicing into them.
+    pub fn validate(&self) -> Result<(), EntryError> {
+        if self.inner.len() < self.embedding_dim {
+            return Err(EntryError::InvalidLength {
+                embedding_dim: self.embedding_dim,
+                inner_len: self.inner.len(),
+            });
         }
+        Ok(())
     }
 
+    /// Copies the embedding and the optimizer state of `other`, returns false if the embedding
+    /// dims differ. Panics if the entry is frozen.
     pub fn copy_from_other(&mut self, other: &Self) -> bool {
+        match self.try_copy_from_other(other) {
+            Ok(()) => true,
+            Err(PersiaEmbeddingHolderError::DimMismatch { .. }) => false,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like [`HashMapEmbeddingEntry::copy_from_other`], but returns an error instead of false
+    /// or panicking.
+    pub fn try_copy_from_other(&mut self, other: &Self) -> Result<(), PersiaEmbeddingHolderError> {
         if self.embedding_dim() != other.embedding_dim() {
-            return false;
+            return Err(PersiaEmbeddingHolderError::DimMismatch {
+                sign: self.sign,
+                expected: self.embedding_dim(),
+                actual: other.embedding_dim(),
+            });
+        }
+        if self.frozen {
+            return Err(PersiaEmbeddingHolderError::EntryFrozen(self.sign));
         }
+        self.mark_mutated();
         for (dst, src) in self.inner.iter_mut().zip(other.inner.iter()) {
             *dst = *src;
         }
-        return true;
+        Ok(())
+    }
+
+    /// Decodes a speedy serialized entry, failing with
+    /// [`PersiaEmbeddingHolderError::CorruptEntry`] for malformed bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PersiaEmbeddingHolderError> {
+        Self::read_from_buffer(bytes)
+            .map_err(|e| PersiaEmbeddingHolderError::CorruptEntry(e.to_string()))
+    }
+
+    /// Like [`HashMapEmbeddingEntry::copy_from_other`] but tolerating a source of another dim,
+    /// e.g. when loading an old checkpoint into a wider model. The first
+    /// `min(self.dim, other.dim)` embedding values are copied and the remaining ones are taken
+    /// from an embedding initialized with `initialization_method` and `seed`. The dim of `self`
+    /// is kept, its optimizer state is only copied from a source of the same dim and zeroed
+    /// otherwise.
+    pub fn copy_from_other_resized(
+        &mut self,
+        other: &Self,
+        initialization_method: &InitializationMethod,
+        seed: u64,
+    ) {
+        if self.embedding_dim == other.embedding_dim && self.inner.len() == other.inner.len() {
+            self.copy_from_other(other);
+            return;
+        }
+        let dim = self.embedding_dim;
+        let copied = dim.min(other.embedding_dim);
+        let (emb, opt) = self.emb_and_opt_mut();
+        emb[..copied].copy_from_slice(&other.emb()[..copied]);
+        if copied < dim {
+            let init = Self::new(initialization_method, dim, 0, seed, other.sign);
+            emb[copied..].copy_from_slice(&init.emb()[copied..]);
+        }
+        opt.iter_mut().for_each(|x| *x = 0.0);
+    }
+
+    /// Grows or shrinks the embedding to `new_dim` values in place. Shrinking truncates the
+    /// embedding, growing fills the new values with the trailing values of an embedding of
+    /// `new_dim` initialized with `initialization_method` and `seed`. The optimizer state is
+    /// zeroed and resized keeping its layout of `k * dim + c` values, which covers the layouts
+    /// of all [`crate::optim_state::OptimizerKind`]s for dims larger than one.
+    pub fn resize_dim(
+        &mut self,
+        new_dim: usize,
+        initialization_method: &InitializationMethod,
+        seed: u64,
+    ) {
+        // checked before anything changes, so a frozen entry stays intact
+        self.mark_mutated();
+        let old_dim = self.embedding_dim;
+        let opt_len = self.inner.len() - old_dim;
+        let new_opt_len = match old_dim {
+            0 => opt_len,
+            _ => opt_len / old_dim * new_dim + opt_len % old_dim,
+        };
+        let init = if new_dim > old_dim {
+            Some(Self::new(
+                initialization_method,
+                new_dim,
+                0,
+                seed,
+                self.sign,
+            ))
+        } else {
+            None
+        };
+
+        self.inner.resize(new_dim, 0.0);
+        if let Some(init) = init {
+            self.inner[old_dim..].copy_from_slice(&init.emb()[old_dim..]);
+        }
+        self.inner.resize(new_dim + new_opt_len, 0.0);
+        self.embedding_dim = new_dim;
     }
 
     pub fn as_mut_emb_entry_slice(&mut self) -> &mut [f32] {
+        self.mark_mutated();
         self.inner.as_mut_slice()
     }
 
@@ -117,6 +737,11 @@ impl HashMapEmbeddingEntry {
         self.inner.len()
     }
 
+    /// Bytes allocated for the embedding and optimizer state, including spare capacity.
+    pub fn heap_bytes(&self) -> usize {
+        self.inner.capacity() * std::mem::size_of::<f32>()
+    }
+
     pub fn dim(&self) -> usize {
         self.embedding_dim
     }
@@ -130,6 +755,7 @@ impl HashMapEmbeddingEntry {
     }
 
     pub fn emb_mut(&mut self) -> &mut [f32] {
+        self.mark_mutated();
         let dim = self.embedding_dim();
         &mut self.inner[..dim]
     }
@@ -143,11 +769,13 @@ impl HashMapEmbeddingEntry {
     }
 
     pub fn opt_mut(&mut self) -> &mut [f32] {
+        self.mark_mutated();
         let dim = self.embedding_dim();
         &mut self.inner[dim..]
     }
 
     pub fn emb_and_opt_mut(&mut self) -> (&mut [f32], &mut [f32]) {
+        self.mark_mutated();
         let dim = self.embedding_dim();
         self.inner.split_at_mut(dim)
     }
@@ -155,10 +783,1733 @@ impl HashMapEmbeddingEntry {
     pub fn sign(&self) -> u64 {
         self.sign
     }
+
+    /// Whether the entry was created or mutably accessed since the last [`Self::clear_dirty`].
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    #[inline]
+    fn mark_mutated(&mut self) {
+        assert!(
+            !self.frozen,
+            "mutable access to frozen embedding entry of sign {}",
+            self.sign
+        );
+        self.dirty = true;
+    }
+
+    /// Rejects further mutable access to the entry, every mutating method panics until
+    /// [`Self::unfreeze`] is called.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    pub fn unfreeze(&mut self) {
+        self.frozen = false;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Whether the entry was soft deleted by [`crate::PersiaEmbeddingHolder::tombstone`].
+    pub fn is_tombstoned(&self) -> bool {
+        self.tombstoned
+    }
+
+    // dirty, so that the next incremental checkpoint records the deletion
+    pub(crate) fn set_tombstoned(&mut self) {
+        self.tombstoned = true;
+        self.dirty = true;
+    }
+
+    /// Records that the entry was updated at training step `step`.
+    pub fn touch(&mut self, step: u64) {
+        self.last_step = step;
+    }
+
+    /// Last training step passed to [`Self::touch`], 0 if the entry was never touched.
+    pub fn last_step(&self) -> u64 {
+        self.last_step
+    }
+
+    /// Number of mutable accesses to the entry through its holder shard.
+    pub fn access_count(&self) -> u32 {
+        self.access_count
+    }
+
+    pub fn record_access(&mut self) {
+        self.access_count = self.access_count.saturating_add(1);
+    }
+
+    /// Learning rate scaled down by the access frequency of the sign, `base_lr / count^power`,
+    /// so that rare signs learn faster than frequent ones. An entry never accessed gets
+    /// `base_lr`.
+    pub fn adaptive_lr(&self, base_lr: f32, power: f32) -> f32 {
+        base_lr / (self.access_count.max(1) as f32).powf(power)
+    }
+
+    pub fn l2_norm(&self) -> f32 {
+        self.emb().iter().map(|x| x * x).sum::<f32>().sqrt()
+    }
+
+    /// Rescale the embedding in place to `target_norm`, the optimizer state is untouched.
+    /// A zero embedding is left as is.
+    pub fn normalize_l2(&mut self, target_norm: f32) {
+        let norm = self.l2_norm();
+        if norm == 0.0 {
+            return;
+        }
+        let factor = target_norm / norm;
+        self.emb_mut().iter_mut().for_each(|x| *x *= factor);
+    }
+
+    /// Scale the embedding down to `max_norm` if its norm exceeds it. Returns whether the
+    /// embedding was clipped.
+    pub fn clip_to_max_norm(&mut self, max_norm: f32) -> bool {
+        if self.l2_norm() > max_norm {
+            self.normalize_l2(max_norm);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn check_dim(&self, len: usize) -> Result<(), EntryError> {
+        if len != self.embedding_dim {
+            return Err(EntryError::DimMismatch {
+                expected: self.embedding_dim,
+                actual: len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Computes `emb += alpha * x`.
+    pub fn axpy(&mut self, alpha: f32, x: &[f32]) -> Result<(), EntryError> {
+        self.check_dim(x.len())?;
+        self.emb_mut()
+            .iter_mut()
+            .zip(x.iter())
+            .for_each(|(w, x)| *w += alpha * x);
+        Ok(())
+    }
+
+    /// Dot product of the embedding and `other`.
+    pub fn dot(&self, other: &[f32]) -> Result<f32, EntryError> {
+        self.check_dim(other.len())?;
+        Ok(unsafe { persia_simd::dot_avx2(self.emb(), other) })
+    }
+
+    /// Cosine similarity between the embedding and `other`, 0.0 when either of them is a zero
+    /// vector.
+    pub fn cosine_similarity(&self, other: &[f32]) -> Result<f32, EntryError> {
+        let dot = self.dot(other)?;
+        let norm = self.l2_norm();
+        let other_norm = other.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm == 0.0 || other_norm == 0.0 {
+            return Ok(0.0);
+        }
+        Ok(dot / (norm * other_norm))
+    }
+
+    pub fn scale(&mut self, factor: f32) {
+        self.emb_mut().iter_mut().for_each(|x| *x *= factor);
+    }
+
+    /// Multiplies every embedding dim by its weight in `scale`, e.g. a fixed feature importance
+    /// per dim.
+    pub fn apply_dim_scale(&mut self, scale: &[f32]) -> Result<(), EntryError> {
+        self.check_dim(scale.len())?;
+        self.emb_mut()
+            .iter_mut()
+            .zip(scale.iter())
+            .for_each(|(x, s)| *x *= s);
+        Ok(())
+    }
+
+    /// The embedding scaled per dim like [`Self::apply_dim_scale`], without modifying the
+    /// entry, e.g. at read time for serving.
+    pub fn scaled_vec(&self, scale: &[f32]) -> Result<Vec<f32>, EntryError> {
+        self.check_dim(scale.len())?;
+        Ok(self
+            .emb()
+            .iter()
+            .zip(scale.iter())
+            .map(|(x, s)| x * s)
+            .collect())
+    }
+
+    pub fn clip_values(&mut self, min: f32, max: f32) {
+        self.emb_mut()
+            .iter_mut()
+            .for_each(|x| *x = x.max(min).min(max));
+    }
+
+    /// Replace NaN and infinite values of the embedding with `replacement`, returns the number
+    /// of replaced values.
+    pub fn sanitize_non_finite(&mut self, replacement: f32) -> usize {
+        let mut num_replaced = 0;
+        self.emb_mut().iter_mut().for_each(|x| {
+            if !x.is_finite() {
+                *x = replacement;
+                num_replaced += 1;
+            }
+        });
+        num_replaced
+    }
+
+    /// Whether the embedding or the optimizer state holds a subnormal value.
+    pub fn has_denormals(&self) -> bool {
+        self.as_emb_entry_slice().iter().any(|x| x.is_subnormal())
+    }
+
+    /// Replace subnormal values of the embedding and the optimizer state with zero, returns the
+    /// number of replaced values. This is a performance fix, arithmetic on subnormals is much
+    /// slower on most CPUs, while flushing them changes the values by less than the smallest
+    /// normal f32. An entry without subnormals is not marked as mutated.
+    pub fn flush_denormals(&mut self) -> usize {
+        if !self.has_denormals() {
+            return 0;
+        }
+        let mut num_flushed = 0;
+        self.as_mut_emb_entry_slice().iter_mut().for_each(|x| {
+            if x.is_subnormal() {
+                *x = 0.0;
+                num_flushed += 1;
+            }
+        });
+        num_flushed
+    }
+
+    /// Checksum over sign, embedding dim and the raw content of the entry, which is stable across
+    /// platforms and can be verified after transferring the entry.
+    pub fn checksum(&self) -> u64 {
+        let mut bytes = Vec::with_capacity(16 + self.inner.len() * 4);
+        bytes.extend_from_slice(&self.sign.to_le_bytes());
+        bytes.extend_from_slice(&(self.embedding_dim as u64).to_le_bytes());
+        self.inner
+            .iter()
+            .for_each(|x| bytes.extend_from_slice(&x.to_le_bytes()));
+        farmhash::hash64(bytes.as_slice())
+    }
+
+    /// Hash of the embedding dim and the embedding values, excluding sign and optimizer state,
+    /// so entries with bit identical embeddings share it.
+    pub fn content_hash(&self) -> u64 {
+        content_hash(self.emb())
+    }
+
+    pub fn verify_checksum(&self, expected: u64) -> bool {
+        self.checksum() == expected
+    }
+
+    /// Whether both entries have the same sign and dim, and their embeddings differ by at most
+    /// `epsilon` in every dim, e.g. to validate a quantization round trip. Optimizer states are
+    /// not compared.
+    pub fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.sign == other.sign
+            && self.embedding_dim == other.embedding_dim
+            && self
+                .emb()
+                .iter()
+                .zip(other.emb().iter())
+                .all(|(x, y)| (x - y).abs() <= epsilon)
+    }
+
+    pub fn to_f16(&self) -> F16EmbeddingEntry {
+        F16EmbeddingEntry::from_entry(self)
+    }
+
+    pub fn to_bf16(&self) -> Bf16EmbeddingEntry {
+        Bf16EmbeddingEntry::from_entry(self)
+    }
+
+    /// Symmetric int8 quantization of the embedding with a per row scale of
+    /// `max(abs(emb)) / 127`. An all zero embedding gets scale 1.0.
+    pub fn quantize_int8(&self) -> (Vec<i8>, f32) {
+        let max_abs = self.emb().iter().fold(0.0_f32, |acc, x| acc.max(x.abs()));
+        let scale = if max_abs > 0.0 { max_abs / 127.0 } else { 1.0 };
+        let quantized = self
+            .emb()
+            .iter()
+            .map(|x| (x / scale).round().max(-127.0).min(127.0) as i8)
+            .collect();
+        (quantized, scale)
+    }
+
+    pub fn from_int8(data: &[i8], scale: f32, sign: u64) -> Self {
+        let emb = data.iter().map(|x| *x as f32 * scale).collect();
+        Self::from_emb(emb, sign)
+    }
+
+    /// Writes the speedy serialized entry as a zstd frame compressed at `level`, where 0
+    /// selects the zstd default level. The frame is prefixed with the uncompressed and the
+    /// compressed length as little endian u32, so the reader sizes its buffers up front.
+    pub fn write_compressed<W: Write>(&self, w: &mut W, level: i32) -> std::io::Result<()> {
+        let bytes = self
+            .write_to_vec()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let compressed = zstd::bulk::compress(&bytes, level)?;
+        let too_large = |what: &str, len: usize| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} entry of {} bytes does not fit a u32 length", what, len),
+            )
+        };
+        let len = u32::try_from(bytes.len()).map_err(|_| too_large("serialized", bytes.len()))?;
+        let compressed_len = u32::try_from(compressed.len())
+            .map_err(|_| too_large("compressed", compressed.len()))?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&compressed_len.to_le_bytes())?;
+        w.write_all(&compressed)
+    }
+
+    /// Reads an entry written by [`HashMapEmbeddingEntry::write_compressed`]. Lengths beyond
+    /// those of an entry of [`max_entry_len`] values are rejected before allocating.
+    pub fn read_compressed<R: Read>(r: &mut R) -> std::io::Result<Self> {
+        let invalid_data = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let mut lens = [0u8; 8];
+        r.read_exact(&mut lens)?;
+        let len = u32::from_le_bytes([lens[0], lens[1], lens[2], lens[3]]) as usize;
+        let compressed_len = u32::from_le_bytes([lens[4], lens[5], lens[6], lens[7]]) as usize;
+        let max_len = max_serialized_entry_len();
+        if len > max_len || compressed_len > zstd::zstd_safe::compress_bound(max_len) {
+            return Err(invalid_data(format!(
+                "compressed entry of {} bytes, {} uncompressed, exceeds the max entry length",
+                compressed_len, len
+            )));
+        }
+        let mut compressed = vec![0u8; compressed_len];
+        r.read_exact(&mut compressed)?;
+        let bytes = zstd::bulk::decompress(&compressed, len)?;
+        if bytes.len() != len {
+            return Err(invalid_data(format!(
+                "compressed entry holds {} bytes, expected {}",
+                bytes.len(),
+                len
+            )));
+        }
+        Self::read_from_buffer(&bytes).map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+#[inline]
+fn splitmix64(x: u64) -> u64 {
+    crate::admission::mix64(x.wrapping_add(0x9E3779B97F4A7C15))
+}
+
+/// Initialization seed of `sign` under `global_seed`, mixed with splitmix64 so that nearby signs
+/// and seeds give unrelated rngs. The result only depends on its arguments.
+pub fn seed_for_sign(sign: u64, global_seed: u64) -> u64 {
+    splitmix64(global_seed ^ splitmix64(sign))
+}
+
+// Modified Gram-Schmidt over the rows of a row-major matrix with row length `dim`. At most
+// `dim` rows can be mutually orthogonal, so rows are orthogonalized in groups of `dim`.
+fn orthonormalize_rows(matrix: &mut [f32], dim: usize) {
+    if dim == 0 {
+        return;
+    }
+    let num_rows = matrix.len() / dim;
+    for row_idx in 0..num_rows {
+        let group_start = (row_idx / dim) * dim;
+        let (prev_rows, rest) = matrix.split_at_mut(row_idx * dim);
+        let row = &mut rest[..dim];
+        for prev_idx in group_start..row_idx {
+            let prev = &prev_rows[prev_idx * dim..(prev_idx + 1) * dim];
+            let projection: f32 = row.iter().zip(prev.iter()).map(|(a, b)| a * b).sum();
+            row.iter_mut()
+                .zip(prev.iter())
+                .for_each(|(a, b)| *a -= projection * b);
+        }
+        let norm = row.iter().map(|a| a * a).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            row.iter_mut().for_each(|a| *a /= norm);
+        }
+    }
+}
+
+#[inline]
+fn read_fixed_u64<'a, C: Context, R: persia_speedy::Reader<'a, C>>(
+    reader: &mut R,
+    big_endian: bool,
+) -> Result<u64, C::Error> {
+    let mut bytes = [0u8; 8];
+    reader.read_bytes(&mut bytes)?;
+    match big_endian {
+        true => Ok(u64::from_be_bytes(bytes)),
+        false => Ok(u64::from_le_bytes(bytes)),
+    }
+}
+
+// Rejects the lengths of a serialized entry before anything is allocated for it.
+fn check_serialized_len<C: Context>(embedding_dim: usize, opt_len: usize) -> Result<(), C::Error> {
+    let max_dim = max_embedding_dim();
+    if embedding_dim > max_dim {
+        return Err(persia_speedy::Error::custom(
+            EntryError::InvalidDimension {
+                dim: embedding_dim,
+                max_dim,
+            }
+            .to_string(),
+        )
+        .into());
+    }
+    let max_len = max_entry_len();
+    if embedding_dim.saturating_add(opt_len) > max_len {
+        return Err(persia_speedy::Error::custom(
+            InitError::EntryTooLarge {
+                dim: embedding_dim,
+                require_space: opt_len,
+                max_len,
+            }
+            .to_string(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// CRC32 of the serialized version, flags, header fields and values, re-encoded in the byte
+// order they were written in.
+fn entry_checksum(
+    version: u16,
+    flags: u8,
+    header: &[u64],
+    values: &[f32],
+    big_endian: bool,
+) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&version.to_le_bytes());
+    hasher.update(&[flags]);
+    header.iter().for_each(|x| match big_endian {
+        true => hasher.update(&x.to_be_bytes()),
+        false => hasher.update(&x.to_le_bytes()),
+    });
+    let bytes: Vec<u8> = values
+        .iter()
+        .flat_map(|x| match big_endian {
+            true => x.to_be_bytes(),
+            false => x.to_le_bytes(),
+        })
+        .collect();
+    hasher.update(&bytes);
+    hasher.finalize()
+}
+
+#[inline]
+fn read_fixed_f32_into<'a, C: Context, R: persia_speedy::Reader<'a, C>>(
+    reader: &mut R,
+    len: usize,
+    big_endian: bool,
+    output: &mut Vec<f32>,
+) -> Result<(), C::Error> {
+    let mut bytes = vec![0u8; len * 4];
+    reader.read_bytes(bytes.as_mut_slice())?;
+    output.extend(bytes.chunks_exact(4).map(|x| {
+        let x = [x[0], x[1], x[2], x[3]];
+        match big_endian {
+            true => f32::from_be_bytes(x),
+            false => f32::from_le_bytes(x),
+        }
+    }));
+    Ok(())
+}
+
+impl HashMapEmbeddingEntry {
+    // v1 layout, the derived one of earlier releases: u32 inner length, inner, embedding_dim as
+    // u64, sign, all in the byte order of the speedy context. The inner length is already read.
+    fn read_v1<'a, C: Context, R: persia_speedy::Reader<'a, C>>(
+        reader: &mut R,
+        inner_len: u32,
+    ) -> Result<Self, C::Error> {
+        let inner_len = inner_len as usize;
+        check_serialized_len::<C>(0, inner_len)?;
+        let mut inner = Vec::with_capacity(inner_len);
+        for _ in 0..inner_len {
+            inner.push(reader.read_f32()?);
+        }
+        let embedding_dim = reader.read_u64()? as usize;
+        let sign = reader.read_u64()?;
+        if inner_len < embedding_dim {
+            return Err(persia_speedy::Error::custom(
+                EntryError::InvalidLength {
+                    embedding_dim,
+                    inner_len,
+                }
+                .to_string(),
+            )
+            .into());
+        }
+        check_serialized_len::<C>(embedding_dim, inner_len - embedding_dim)?;
+
+        Ok(Self {
+            dirty: false,
+            ..Self::from_parts(inner.into(), embedding_dim, sign)
+        })
+    }
+
+    // v2 layout: u16 version, flags byte, embedding_dim, opt length, sign, emb, opt.
+    // v3 layout: v2 layout with the last step following the sign.
+    // v4 layout: v3 layout followed by the little endian CRC32 of all preceding bytes.
+    fn read_v2<'a, C: Context, R: persia_speedy::Reader<'a, C>>(
+        reader: &mut R,
+        version: u16,
+    ) -> Result<Self, C::Error> {
+        let flags = reader.read_u8()?;
+        let big_endian = flags & ENTRY_BIG_ENDIAN_FLAG != 0;
+        let embedding_dim = read_fixed_u64(reader, big_endian)? as usize;
+        let opt_len = read_fixed_u64(reader, big_endian)? as usize;
+        let sign = read_fixed_u64(reader, big_endian)?;
+        let last_step = match version {
+            2 => 0,
+            _ => read_fixed_u64(reader, big_endian)?,
+        };
+        // a corrupt length must not turn into a huge allocation before the checksum is checked
+        check_serialized_len::<C>(embedding_dim, opt_len)?;
+
+        let mut inner = Vec::with_capacity(embedding_dim + opt_len);
+        read_fixed_f32_into(reader, embedding_dim, big_endian, &mut inner)?;
+        read_fixed_f32_into(reader, opt_len, big_endian, &mut inner)?;
+
+        if version >= ENTRY_FORMAT_FIRST_CHECKSUMMED {
+            let mut stored = [0u8; 4];
+            reader.read_bytes(&mut stored)?;
+            let expected = u32::from_le_bytes(stored);
+            let actual = entry_checksum(
+                version,
+                flags,
+                &[embedding_dim as u64, opt_len as u64, sign, last_step],
+                &inner,
+                big_endian,
+            );
+            if actual != expected {
+                return Err(persia_speedy::Error::custom(
+                    EntryError::CorruptEntry {
+                        sign,
+                        expected,
+                        actual,
+                    }
+                    .to_string(),
+                )
+                .into());
+            }
+        }
+
+        let mut inner = EntryBuffer::from(inner);
+        if flags & ENTRY_ALIGNED_FLAG != 0 {
+            inner = inner.into_aligned();
+        }
+
+        Ok(Self {
+            dirty: false,
+            frozen: flags & ENTRY_FROZEN_FLAG != 0,
+            last_step,
+            ..Self::from_parts(inner, embedding_dim, sign)
+        })
+    }
+}
+
+impl<'a, C> Readable<'a, C> for HashMapEmbeddingEntry
+where
+    C: Context,
+{
+    #[inline]
+    fn read_from<R: persia_speedy::Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        let prefix = reader.read_u32()?;
+        if prefix != ENTRY_MAGIC {
+            return Self::read_v1(reader, prefix);
+        }
+
+        let mut version = [0u8; 2];
+        reader.read_bytes(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        match version {
+            2..=ENTRY_FORMAT_VERSION => Self::read_v2(reader, version),
+            _ => Err(persia_speedy::Error::custom(
+                EntryError::UnsupportedVersion {
+                    version,
+                    max_supported: ENTRY_FORMAT_VERSION,
+                }
+                .to_string(),
+            )
+            .into()),
+        }
+    }
+
+    #[inline]
+    fn minimum_bytes_needed() -> usize {
+        4 + 8 * 2
+    }
+}
+
+impl<C> Writable<C> for HashMapEmbeddingEntry
+where
+    C: Context,
+{
+    #[inline]
+    fn write_to<W: ?Sized + persia_speedy::Writer<C>>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), C::Error> {
+        writer.write_bytes(&ENTRY_MAGIC.to_le_bytes())?;
+        writer.write_bytes(&ENTRY_FORMAT_VERSION.to_le_bytes())?;
+        let mut flags = 0;
+        if self.inner.is_aligned() {
+            flags |= ENTRY_ALIGNED_FLAG;
+        }
+        if self.frozen {
+            flags |= ENTRY_FROZEN_FLAG;
+        }
+        writer.write_u8(flags)?;
+        writer.write_bytes(&(self.embedding_dim as u64).to_le_bytes())?;
+        writer.write_bytes(&((self.inner.len() - self.embedding_dim) as u64).to_le_bytes())?;
+        writer.write_bytes(&self.sign.to_le_bytes())?;
+        writer.write_bytes(&self.last_step.to_le_bytes())?;
+        for x in self.inner.iter() {
+            writer.write_bytes(&x.to_le_bytes())?;
+        }
+        let checksum = entry_checksum(
+            ENTRY_FORMAT_VERSION,
+            flags,
+            &[
+                self.embedding_dim as u64,
+                (self.inner.len() - self.embedding_dim) as u64,
+                self.sign,
+                self.last_step,
+            ],
+            &self.inner,
+            false,
+        );
+        writer.write_bytes(&checksum.to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Entries are equal if sign, embedding dim and all values, embedding and optimizer state, are
+/// equal. Bookkeeping like the dirty and access state is ignored.
+impl PartialEq for HashMapEmbeddingEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.sign == other.sign
+            && self.embedding_dim == other.embedding_dim
+            && self.as_emb_entry_slice() == other.as_emb_entry_slice()
+    }
 }
 
 impl EvictionMapValue<u64> for HashMapEmbeddingEntry {
     fn hashmap_key(&self) -> u64 {
         self.sign
     }
+
+    fn on_mut_access(&mut self) {
+        self.record_access();
+    }
+}
+
+#[cfg(test)]
+mod emb_entry_tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use persia_embedding_config::{
+        BoundedBetaInitialization, BoundedCauchyInitialization, BoundedExponentialInitialization,
+        BoundedGammaInitialization, BoundedLaplaceInitialization, BoundedLogNormalInitialization,
+        BoundedNormalInitialization, BoundedPoissonInitialization, BoundedUniformInitialization,
+        CategoricalInitialization, ClampedInitialization, ConstantInitialization,
+        CustomInitialization, GlorotInitialization, KaimingInitialization,
+        OrthogonalInitialization, TruncatedNormalInitialization,
+    };
+    use persia_speedy::BigEndian;
+
+    fn variance(values: &[f32]) -> f32 {
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        values.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / values.len() as f32
+    }
+
+    #[test]
+    fn test_new_deterministic() {
+        assert_eq!(splitmix64(0), 0xe220a8397b1dcdaf);
+        assert_eq!(seed_for_sign(7, 42), 0x6eab8625df268fbc);
+        assert_ne!(seed_for_sign(7, 42), seed_for_sign(8, 42));
+        assert_ne!(seed_for_sign(7, 42), seed_for_sign(7, 43));
+
+        // two servers initializing the same sign independently
+        let initialization = InitializationMethod::default();
+        let first = HashMapEmbeddingEntry::new_deterministic(&initialization, 16, 4, 42, 7);
+        let second = HashMapEmbeddingEntry::new_deterministic(&initialization, 16, 4, 42, 7);
+        assert_eq!(first.as_emb_entry_slice(), second.as_emb_entry_slice());
+        let expected = HashMapEmbeddingEntry::new(&initialization, 16, 4, seed_for_sign(7, 42), 7);
+        assert_eq!(first.emb(), expected.emb());
+        let other = HashMapEmbeddingEntry::new_deterministic(&initialization, 16, 4, 43, 7);
+        assert_ne!(first.emb(), other.emb());
+    }
+
+    #[test]
+    fn test_init_bulk() {
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..10_000u64).map(|x| x * 7919).collect();
+        let bulk = HashMapEmbeddingEntry::init_bulk(&signs, &initialization, 16, 4, 17);
+        assert_eq!(bulk.len(), signs.len());
+        bulk.iter().zip(signs.iter()).for_each(|(entry, sign)| {
+            let serial = HashMapEmbeddingEntry::new(
+                &initialization,
+                16,
+                4,
+                17u64.wrapping_add(*sign),
+                *sign,
+            );
+            assert_eq!(entry.sign(), *sign);
+            assert_eq!(entry.as_emb_entry_slice(), serial.as_emb_entry_slice());
+        });
+    }
+
+    #[test]
+    fn test_glorot_initialization_variance() {
+        let dim = 100_000;
+        let fan = GlorotInitialization::new(Some(64), Some(32));
+        let expected_variance = 2.0 / 96.0;
+
+        let uniform = InitializationMethod::GlorotUniform(fan.clone());
+        let entry = HashMapEmbeddingEntry::new(&uniform, dim, 0, 42, 42);
+        let sampled = variance(entry.emb());
+        assert!((sampled - expected_variance).abs() / expected_variance < 0.05);
+
+        let normal = InitializationMethod::GlorotNormal(fan);
+        let entry = HashMapEmbeddingEntry::new(&normal, dim, 0, 42, 42);
+        let sampled = variance(entry.emb());
+        assert!((sampled - expected_variance).abs() / expected_variance < 0.05);
+    }
+
+    #[test]
+    fn test_glorot_initialization_default_fan() {
+        let dim = 16;
+        let initialization = InitializationMethod::GlorotUniform(GlorotInitialization::default());
+        let entry = HashMapEmbeddingEntry::new(&initialization, dim, 4, 7, 7);
+        let bound = (6.0 / (2 * dim) as f32).sqrt();
+        assert_eq!(entry.inner_size(), dim + 4);
+        assert!(entry.emb().iter().all(|x| x.abs() <= bound));
+    }
+
+    #[test]
+    fn test_kaiming_initialization_reproducible() {
+        let methods = vec![
+            InitializationMethod::KaimingUniform(KaimingInitialization::new(32)),
+            InitializationMethod::KaimingNormal(KaimingInitialization::new(32)),
+        ];
+        for initialization in methods.iter() {
+            let first = HashMapEmbeddingEntry::new(initialization, 64, 8, 1234, 1);
+            let second = HashMapEmbeddingEntry::new(initialization, 64, 8, 1234, 2);
+            let first_bits: Vec<u32> = first.emb().iter().map(|x| x.to_bits()).collect();
+            let second_bits: Vec<u32> = second.emb().iter().map(|x| x.to_bits()).collect();
+            assert_eq!(first_bits, second_bits);
+
+            let other_seed = HashMapEmbeddingEntry::new(initialization, 64, 8, 4321, 1);
+            assert_ne!(first.emb(), other_seed.emb());
+        }
+    }
+
+    #[test]
+    fn test_constant_initialization() {
+        let initialization = InitializationMethod::Constant(ConstantInitialization::new(0.5));
+        let entry = HashMapEmbeddingEntry::new(&initialization, 16, 16, 0, 0);
+        assert_eq!(entry.emb().len(), 16);
+        assert!(entry.emb().iter().all(|x| *x == 0.5));
+        assert!(entry.opt().iter().all(|x| *x == 0.0));
+
+        let entry = HashMapEmbeddingEntry::new(&InitializationMethod::Zeros, 16, 16, 0, 0);
+        assert!(entry.as_emb_entry_slice().iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn test_truncated_normal_initialization() {
+        let mean = 0.5;
+        let standard_deviation = 0.1;
+        let initialization = InitializationMethod::TruncatedNormal(
+            TruncatedNormalInitialization::new(mean, standard_deviation, 2.0),
+        );
+        let entry = HashMapEmbeddingEntry::new(&initialization, 100_000, 0, 3, 3);
+        assert!(entry.emb().iter().all(|x| *x >= 0.3 && *x <= 0.7));
+    }
+
+    #[test]
+    fn test_beta_initialization() {
+        let initialization =
+            InitializationMethod::BoundedBeta(BoundedBetaInitialization::new(0.5, 2.0));
+        let entry = HashMapEmbeddingEntry::new(&initialization, 100_000, 0, 5, 5);
+        assert!(entry.emb().iter().all(|x| *x >= 0.0 && *x <= 1.0));
+        // mean of the distribution is alpha / (alpha + beta)
+        let mean = entry.emb().iter().sum::<f32>() / entry.emb().len() as f32;
+        assert!((mean - 0.2).abs() < 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive alpha and beta")]
+    fn test_beta_initialization_invalid() {
+        let initialization =
+            InitializationMethod::BoundedBeta(BoundedBetaInitialization::new(0.0, 2.0));
+        HashMapEmbeddingEntry::new(&initialization, 8, 0, 5, 5);
+    }
+
+    #[test]
+    fn test_entry_too_large() {
+        let initialization = InitializationMethod::default();
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new(&initialization, usize::MAX, 1, 0, 1),
+            Err(InitError::EntryTooLarge {
+                dim: usize::MAX,
+                require_space: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new(&initialization, usize::MAX, 0, 0, 1),
+            Err(InitError::EntryTooLarge { .. })
+        ));
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new_with_fn(|_, _| Vec::new(), usize::MAX, 0, 0, 1),
+            Err(InitError::EntryTooLarge { .. })
+        ));
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new_empty(8, usize::MAX - 4, 1),
+            Err(InitError::EntryTooLarge { .. })
+        ));
+        assert!(HashMapEmbeddingEntry::try_new_empty(8, 8, 1).is_ok());
+    }
+
+    #[test]
+    fn test_try_new_errors() {
+        let invalid = [
+            (
+                InitializationMethod::BoundedGamma(BoundedGammaInitialization {
+                    shape: 0.0,
+                    scale: 1.0,
+                }),
+                "gamma",
+            ),
+            (
+                InitializationMethod::BoundedPoisson(BoundedPoissonInitialization { lambda: -1.0 }),
+                "poisson",
+            ),
+            (
+                InitializationMethod::BoundedNormal(BoundedNormalInitialization {
+                    mean: 0.0,
+                    standard_deviation: -1.0,
+                }),
+                "normal",
+            ),
+            (
+                InitializationMethod::BoundedUniform(BoundedUniformInitialization {
+                    lower: 1.0,
+                    upper: 1.0,
+                }),
+                "uniform",
+            ),
+            (
+                InitializationMethod::KaimingUniform(KaimingInitialization { fan_in: 0 }),
+                "kaiming uniform",
+            ),
+            (
+                InitializationMethod::TruncatedNormal(TruncatedNormalInitialization::new(
+                    0.0, 1.0, 0.0,
+                )),
+                "truncated normal",
+            ),
+            (
+                InitializationMethod::TruncatedNormal(TruncatedNormalInitialization::new(
+                    0.0, 1.0, -2.0,
+                )),
+                "truncated normal",
+            ),
+            (
+                InitializationMethod::BoundedBeta(BoundedBetaInitialization::new(0.0, 2.0)),
+                "beta",
+            ),
+            (
+                InitializationMethod::BoundedExponential(BoundedExponentialInitialization::new(
+                    0.0,
+                )),
+                "exponential",
+            ),
+            (
+                InitializationMethod::BoundedCauchy(BoundedCauchyInitialization::new(0.0, 0.0)),
+                "cauchy",
+            ),
+            (
+                InitializationMethod::BoundedLaplace(BoundedLaplaceInitialization::new(0.0, -1.0)),
+                "laplace",
+            ),
+            (
+                InitializationMethod::Categorical(CategoricalInitialization::new(
+                    vec![0.0, 1.0],
+                    Some(vec![1.0]),
+                )),
+                "categorical",
+            ),
+            (
+                InitializationMethod::Categorical(CategoricalInitialization::new(
+                    vec![0.0, 1.0],
+                    Some(vec![0.0, -1.0]),
+                )),
+                "categorical",
+            ),
+            (
+                InitializationMethod::Categorical(CategoricalInitialization::new(vec![], None)),
+                "categorical",
+            ),
+        ];
+        invalid.iter().for_each(
+            |(initialization, expected)| match HashMapEmbeddingEntry::try_new(
+                initialization,
+                8,
+                0,
+                7,
+                7,
+            ) {
+                Err(InitError::InvalidParameter { method, .. }) => assert_eq!(&method, expected),
+                other => panic!("expected invalid {} parameters, got {:?}", expected, other),
+            },
+        );
+
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new(
+                &InitializationMethod::InverseEmbeddingSizeSqrt,
+                8,
+                0,
+                7,
+                7
+            ),
+            Err(InitError::UnsupportedMethod(_))
+        ));
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new_with_fn(|_, _| vec![0.0; 3], 8, 0, 7, 7),
+            Err(InitError::InvalidLength {
+                expected: 8,
+                actual: 3
+            })
+        ));
+
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::try_new(&initialization, 8, 2, 7, 7).unwrap();
+        let expected = HashMapEmbeddingEntry::new(&initialization, 8, 2, 7, 7);
+        assert_eq!(entry.as_emb_entry_slice(), expected.as_emb_entry_slice());
+    }
+
+    #[test]
+    fn test_exponential_initialization() {
+        let initialization =
+            InitializationMethod::BoundedExponential(BoundedExponentialInitialization::new(4.0));
+        let entry = HashMapEmbeddingEntry::new(&initialization, 100_000, 0, 6, 6);
+        assert!(entry.emb().iter().all(|x| *x >= 0.0));
+        let mean = entry.emb().iter().sum::<f32>() / entry.emb().len() as f32;
+        assert!((mean - 0.25).abs() < 0.01);
+
+        let reproduced = HashMapEmbeddingEntry::new(&initialization, 100_000, 0, 6, 6);
+        assert_eq!(entry.emb(), reproduced.emb());
+    }
+
+    #[test]
+    fn test_log_normal_initialization() {
+        let initialization =
+            InitializationMethod::BoundedLogNormal(BoundedLogNormalInitialization::new(0.5, 0.5));
+        let entry = HashMapEmbeddingEntry::new(&initialization, 100_001, 0, 8, 8);
+        assert!(entry.emb().iter().all(|x| *x > 0.0));
+
+        let mut sorted = entry.emb().to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = sorted[sorted.len() / 2];
+        assert!((median - 0.5f32.exp()).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_cauchy_initialization() {
+        let cauchy =
+            InitializationMethod::BoundedCauchy(BoundedCauchyInitialization::new(0.5, 0.1));
+        let entry = HashMapEmbeddingEntry::new(&cauchy, 100_001, 0, 3, 3);
+        let reproduced = HashMapEmbeddingEntry::new(&cauchy, 100_001, 0, 3, 3);
+        assert_eq!(entry.emb(), reproduced.emb());
+        let mut sorted = entry.emb().to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!((sorted[sorted.len() / 2] - 0.5).abs() < 0.01);
+        // quartiles of cauchy lie at median -+ scale
+        assert!((sorted[sorted.len() / 4] - 0.4).abs() < 0.01);
+
+        let clamped = InitializationMethod::Clamped(ClampedInitialization::new(cauchy, -1.0, 2.0));
+        let entry = HashMapEmbeddingEntry::new(&clamped, 100_001, 0, 3, 3);
+        assert!(entry
+            .emb()
+            .iter()
+            .all(|x| x.is_finite() && *x >= -1.0 && *x <= 2.0));
+        assert_eq!(
+            entry.emb(),
+            HashMapEmbeddingEntry::new(&clamped, 100_001, 0, 3, 3).emb()
+        );
+    }
+
+    #[test]
+    fn test_laplace_initialization() {
+        let laplace =
+            InitializationMethod::BoundedLaplace(BoundedLaplaceInitialization::new(1.0, 0.5));
+        let entry = HashMapEmbeddingEntry::new(&laplace, 100_000, 0, 4, 4);
+        assert_eq!(
+            entry.emb(),
+            HashMapEmbeddingEntry::new(&laplace, 100_000, 0, 4, 4).emb()
+        );
+        assert!(entry.emb().iter().all(|x| x.is_finite()));
+        let mean = entry.emb().iter().sum::<f32>() / entry.emb().len() as f32;
+        assert!((mean - 1.0).abs() < 0.01);
+        assert!((variance(entry.emb()) - 0.5).abs() < 0.02);
+
+        let clamped = InitializationMethod::Clamped(ClampedInitialization::new(laplace, 0.0, 2.0));
+        let entry = HashMapEmbeddingEntry::new(&clamped, 1000, 0, 4, 4);
+        assert!(entry.emb().iter().all(|x| *x >= 0.0 && *x <= 2.0));
+    }
+
+    #[test]
+    fn test_categorical_initialization() {
+        let values = vec![-1.0, 0.0, 0.5, 2.0];
+        let weights = [0.1f32, 0.2, 0.3, 0.4];
+        let categorical = InitializationMethod::Categorical(CategoricalInitialization::new(
+            values.clone(),
+            Some(weights.to_vec()),
+        ));
+        let dim = 100_000;
+        let entry = HashMapEmbeddingEntry::new(&categorical, dim, 0, 5, 5);
+        assert_eq!(
+            entry.emb(),
+            HashMapEmbeddingEntry::new(&categorical, dim, 0, 5, 5).emb()
+        );
+        values
+            .iter()
+            .zip(weights.iter())
+            .for_each(|(value, weight)| {
+                let count = entry.emb().iter().filter(|x| *x == value).count();
+                assert!((count as f32 / dim as f32 - weight).abs() < 0.01);
+            });
+
+        let uniform =
+            InitializationMethod::Categorical(CategoricalInitialization::new(values.clone(), None));
+        let entry = HashMapEmbeddingEntry::new(&uniform, dim, 0, 5, 5);
+        values.iter().for_each(|value| {
+            let count = entry.emb().iter().filter(|x| *x == value).count();
+            assert!((count as f32 / dim as f32 - 0.25).abs() < 0.01);
+        });
+    }
+
+    #[test]
+    fn test_custom_initialization() {
+        let ramp = |_: &mut SmallRng, dim: usize| (0..dim).map(|x| x as f32).collect();
+        let entry = HashMapEmbeddingEntry::new_with_fn(ramp, 8, 2, 0, 1);
+        assert_eq!(entry.emb(), &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(entry.opt(), &[0.0, 0.0]);
+
+        // the rng is seeded like the built in distributions
+        let initialization = InitializationMethod::Custom(CustomInitialization::new(
+            |rng: &mut SmallRng, dim: usize| {
+                Array1::random_using((dim,), Uniform::new(-1.0, 1.0), rng).into_raw_vec()
+            },
+        ));
+        let uniform = InitializationMethod::BoundedUniform(BoundedUniformInitialization {
+            lower: -1.0,
+            upper: 1.0,
+        });
+        let custom = HashMapEmbeddingEntry::new(&initialization, 16, 4, 9, 9);
+        assert_eq!(
+            custom.emb(),
+            HashMapEmbeddingEntry::new(&uniform, 16, 4, 9, 9).emb()
+        );
+        assert_eq!(custom.opt(), &[0.0; 4]);
+        assert!(initialization.write_to_vec().is_err());
+    }
+
+    #[test]
+    fn test_clamped_initialization() {
+        let wide = InitializationMethod::BoundedNormal(BoundedNormalInitialization::new(0.0, 10.0));
+        let initialization =
+            InitializationMethod::Clamped(ClampedInitialization::new(wide.clone(), -1.0, 2.0));
+        let entry = HashMapEmbeddingEntry::new(&initialization, 1000, 3, 5, 5);
+        assert!(entry.emb().iter().all(|x| *x >= -1.0 && *x <= 2.0));
+        assert!(entry.emb().iter().any(|x| *x == -1.0));
+        assert!(entry.emb().iter().any(|x| *x == 2.0));
+        assert_eq!(entry.opt(), &[0.0; 3]);
+
+        // values inside the bounds are those of the inner method
+        let unclamped = HashMapEmbeddingEntry::new(&wide, 1000, 3, 5, 5);
+        entry
+            .emb()
+            .iter()
+            .zip(unclamped.emb().iter())
+            .filter(|(_, y)| **y > -1.0 && **y < 2.0)
+            .for_each(|(x, y)| assert_eq!(x, y));
+
+        let empty = InitializationMethod::Clamped(ClampedInitialization::new(wide, 1.0, -1.0));
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new(&empty, 4, 0, 0, 1),
+            Err(InitError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn test_orthogonal_initialization() {
+        let initialization = InitializationMethod::Orthogonal(OrthogonalInitialization::new(1.0));
+        let entry = HashMapEmbeddingEntry::new(&initialization, 32, 0, 11, 11);
+        let norm = entry.emb().iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+
+        let signs: Vec<u64> = (0..8).collect();
+        let block = HashMapEmbeddingEntry::new_block(&initialization, 16, 4, 11, &signs);
+        assert_eq!(block.len(), 8);
+        for (idx, entry) in block.iter().enumerate() {
+            assert_eq!(entry.sign(), signs[idx]);
+            assert_eq!(entry.opt(), &[0.0; 4]);
+            let norm = entry.emb().iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-5);
+            for other in block[..idx].iter() {
+                let dot: f32 = entry
+                    .emb()
+                    .iter()
+                    .zip(other.emb())
+                    .map(|(a, b)| a * b)
+                    .sum();
+                assert!(dot.abs() < 1e-4);
+            }
+        }
+
+        let empty_rows = HashMapEmbeddingEntry::new_block(&initialization, 0, 4, 11, &signs);
+        assert_eq!(empty_rows.len(), 8);
+        assert!(empty_rows
+            .iter()
+            .all(|x| x.dim() == 0 && x.opt() == [0.0; 4]));
+
+        let too_large = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            HashMapEmbeddingEntry::new_block(&initialization, usize::MAX, 1, 11, &signs)
+        }));
+        assert!(too_large.is_err());
+    }
+
+    #[test]
+    fn test_copy_from_other_resized() {
+        let initialization = InitializationMethod::default();
+        let narrow = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0, 2.0], &[0.5; 2], 3);
+        let wide = HashMapEmbeddingEntry::from_emb_and_opt(vec![4.0; 6], &[0.5; 6], 3);
+
+        // narrower source
+        let mut entry = HashMapEmbeddingEntry::new_empty(4, 4, 3);
+        entry.copy_from_other_resized(&narrow, &initialization, 7);
+        let init = HashMapEmbeddingEntry::new(&initialization, 4, 0, 7, 3);
+        assert_eq!(&entry.emb()[..2], &[1.0, 2.0]);
+        assert_eq!(&entry.emb()[2..], &init.emb()[2..]);
+        assert_eq!(entry.opt(), &[0.0; 4]);
+
+        // wider source
+        entry.copy_from_other_resized(&wide, &initialization, 7);
+        assert_eq!(entry.emb(), &[4.0; 4]);
+        assert_eq!(entry.opt(), &[0.0; 4]);
+
+        // same dim copies the optimizer state too
+        let same = HashMapEmbeddingEntry::from_emb_and_opt(vec![3.0; 4], &[0.25; 4], 3);
+        entry.copy_from_other_resized(&same, &initialization, 7);
+        assert_eq!(entry.as_emb_entry_slice(), same.as_emb_entry_slice());
+    }
+
+    #[test]
+    fn test_resize_dim() {
+        let initialization = InitializationMethod::default();
+        let mut entry =
+            HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0, 2.0, 3.0, 4.0], &[0.5; 9], 3);
+
+        // adam layout of 2 * dim + 1 values
+        entry.resize_dim(6, &initialization, 11);
+        assert_eq!(entry.embedding_dim(), 6);
+        assert_eq!(&entry.emb()[..4], &[1.0, 2.0, 3.0, 4.0]);
+        let init = HashMapEmbeddingEntry::new(&initialization, 6, 0, 11, 3);
+        assert_eq!(&entry.emb()[4..], &init.emb()[4..]);
+        assert_eq!(entry.opt(), &[0.0; 13]);
+        assert!(entry.is_dirty());
+
+        entry.resize_dim(2, &initialization, 11);
+        assert_eq!(entry.emb(), &[1.0, 2.0]);
+        assert_eq!(entry.opt(), &[0.0; 5]);
+
+        let mut entry = HashMapEmbeddingEntry::from_emb(vec![1.0; 4], 5);
+        entry.resize_dim(8, &InitializationMethod::Zeros, 0);
+        assert_eq!(entry.emb(), &[1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+        assert!(entry.opt().is_empty());
+    }
+
+    #[test]
+    fn test_l2_norm() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![3.0, 4.0], &[12.0], 0);
+        assert_eq!(entry.l2_norm(), 5.0);
+
+        entry.normalize_l2(1.0);
+        assert!((entry.emb()[0] - 0.6).abs() < 1e-6);
+        assert!((entry.emb()[1] - 0.8).abs() < 1e-6);
+        assert!((entry.l2_norm() - 1.0).abs() < 1e-6);
+        assert_eq!(entry.opt(), &[12.0]);
+    }
+
+    #[test]
+    fn test_normalize_zero_embedding() {
+        let mut entry = HashMapEmbeddingEntry::new_empty(8, 8, 0);
+        assert_eq!(entry.l2_norm(), 0.0);
+        entry.normalize_l2(1.0);
+        assert!(entry.as_emb_entry_slice().iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn test_clip_to_max_norm() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![30.0, 40.0], &[7.0, 7.0], 0);
+        assert!(entry.clip_to_max_norm(5.0));
+        assert!((entry.l2_norm() - 5.0).abs() < 1e-5);
+        assert_eq!(entry.opt(), &[7.0, 7.0]);
+
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![0.3, 0.4], &[7.0, 7.0], 0);
+        assert!(!entry.clip_to_max_norm(5.0));
+        assert_eq!(entry.emb(), &[0.3, 0.4]);
+        assert_eq!(entry.opt(), &[7.0, 7.0]);
+    }
+
+    #[test]
+    fn test_clip_values() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![-2.0, 0.5, 3.0], &[9.0], 0);
+        entry.clip_values(-1.0, 1.0);
+        assert_eq!(entry.emb(), &[-1.0, 0.5, 1.0]);
+        assert_eq!(entry.opt(), &[9.0]);
+    }
+
+    #[test]
+    fn test_sanitize_non_finite() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(
+            vec![f32::NAN, 1.0, f32::INFINITY, f32::NEG_INFINITY],
+            &[f32::NAN],
+            0,
+        );
+        assert_eq!(entry.sanitize_non_finite(0.0), 3);
+        assert_eq!(entry.emb(), &[0.0, 1.0, 0.0, 0.0]);
+        assert!(entry.opt()[0].is_nan());
+        assert_eq!(entry.sanitize_non_finite(0.0), 0);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let initialization = InitializationMethod::default();
+        let mut entry = HashMapEmbeddingEntry::new(&initialization, 16, 16, 5, 5);
+        let checksum = entry.checksum();
+        assert!(entry.verify_checksum(checksum));
+        assert!(entry.clone().verify_checksum(checksum));
+
+        let origin = entry.emb()[3];
+        entry.emb_mut()[3] = origin + 1.0;
+        assert!(!entry.verify_checksum(checksum));
+        entry.emb_mut()[3] = origin;
+        assert!(entry.verify_checksum(checksum));
+        entry.opt_mut()[0] = 1.0;
+        assert_ne!(entry.checksum(), checksum);
+
+        let other_sign = HashMapEmbeddingEntry::new(&initialization, 16, 16, 5, 6);
+        assert_ne!(other_sign.checksum(), checksum);
+    }
+
+    #[test]
+    fn test_int8_quantization() {
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 64, 64, 23, 23);
+        let (quantized, scale) = entry.quantize_int8();
+        assert_eq!(quantized.len(), 64);
+        assert!(quantized.iter().any(|x| x.abs() == 127));
+
+        let restored = HashMapEmbeddingEntry::from_int8(quantized.as_slice(), scale, 23);
+        assert_eq!(restored.sign(), 23);
+        assert_eq!(restored.dim(), 64);
+        assert!(restored.opt().is_empty());
+        restored
+            .emb()
+            .iter()
+            .zip(entry.emb())
+            .for_each(|(x, y)| assert!((x - y).abs() <= scale / 2.0 + 1e-7));
+    }
+
+    #[test]
+    fn test_int8_quantization_zero_row() {
+        let entry = HashMapEmbeddingEntry::new_empty(8, 0, 0);
+        let (quantized, scale) = entry.quantize_int8();
+        assert_eq!(scale, 1.0);
+        assert_eq!(quantized, vec![0; 8]);
+        let restored = HashMapEmbeddingEntry::from_int8(quantized.as_slice(), scale, 0);
+        assert_eq!(restored.emb(), entry.emb());
+    }
+
+    #[test]
+    fn test_from_raw() {
+        let entry = HashMapEmbeddingEntry::from_raw(vec![1.0, 2.0, 3.0], 2, 9).unwrap();
+        assert_eq!(entry.emb(), &[1.0, 2.0]);
+        assert_eq!(entry.opt(), &[3.0]);
+        assert!(entry.validate().is_ok());
+
+        let err = HashMapEmbeddingEntry::from_raw(vec![1.0, 2.0], 3, 9);
+        assert!(matches!(
+            err,
+            Err(EntryError::InvalidLength {
+                embedding_dim: 3,
+                inner_len: 2
+            })
+        ));
+    }
+
+    // The entry of earlier releases, whose derived speedy impl wrote the v1 layout.
+    #[derive(Readable, Writable)]
+    struct BaselineEntry {
+        inner: Vec<f32>,
+        embedding_dim: usize,
+        sign: u64,
+    }
+
+    fn write_v1(entry: &HashMapEmbeddingEntry, big_endian: bool) -> Vec<u8> {
+        let baseline = BaselineEntry {
+            inner: entry.as_emb_entry_slice().to_vec(),
+            embedding_dim: entry.dim(),
+            sign: entry.sign(),
+        };
+        match big_endian {
+            true => baseline
+                .write_to_vec_with_ctx(BigEndian::default())
+                .unwrap(),
+            false => baseline.write_to_vec().unwrap(),
+        }
+    }
+
+    fn assert_same_entry(lhs: &HashMapEmbeddingEntry, rhs: &HashMapEmbeddingEntry) {
+        assert_eq!(lhs.sign(), rhs.sign());
+        assert_eq!(lhs.dim(), rhs.dim());
+        assert_eq!(lhs.as_emb_entry_slice(), rhs.as_emb_entry_slice());
+    }
+
+    #[test]
+    fn test_fixed_endianness_serialization() {
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 8, 4, 29, 29);
+
+        let bytes = entry.write_to_vec().unwrap();
+        assert_eq!(bytes.len(), 4 + 2 + 1 + 8 * 4 + 4 * 12 + 4);
+        assert_eq!(&bytes[..4], &ENTRY_MAGIC.to_le_bytes());
+        assert_eq!(&bytes[4..6], &ENTRY_FORMAT_VERSION.to_le_bytes());
+        let big_endian_ctx_bytes = entry.write_to_vec_with_ctx(BigEndian::default()).unwrap();
+        assert_eq!(bytes, big_endian_ctx_bytes);
+
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap();
+        assert_same_entry(&decoded, &entry);
+
+        // v1 entries are read in the byte order of the context they were written with
+        let decoded = HashMapEmbeddingEntry::read_from_buffer_with_ctx(
+            BigEndian::default(),
+            &write_v1(&entry, true),
+        )
+        .unwrap();
+        assert_same_entry(&decoded, &entry);
+    }
+
+    #[test]
+    fn test_read_v1_layout() {
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 8, 9, 31, 31);
+
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&write_v1(&entry, false)).unwrap();
+        assert_same_entry(&decoded, &entry);
+        assert_eq!(decoded.opt(), entry.opt());
+
+        let no_opt = HashMapEmbeddingEntry::new(&initialization, 8, 0, 31, 31);
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&write_v1(&no_opt, false)).unwrap();
+        assert_same_entry(&decoded, &no_opt);
+        assert!(decoded.opt().is_empty());
+
+        // fewer values than the embedding dim
+        let short = BaselineEntry {
+            inner: vec![1.0; 3],
+            embedding_dim: 4,
+            sign: 31,
+        };
+        let err =
+            HashMapEmbeddingEntry::read_from_buffer(&short.write_to_vec().unwrap()).unwrap_err();
+        assert!(err.to_string().contains(
+            &EntryError::InvalidLength {
+                embedding_dim: 4,
+                inner_len: 3
+            }
+            .to_string()
+        ));
+    }
+
+    #[test]
+    fn test_read_baseline_entry_bytes() {
+        // entry of sign 11 with embedding [0.5, -1.0] and optimizer state [2.0], as written by
+        // the derived speedy impl of earlier releases in a little endian context
+        #[rustfmt::skip]
+        let bytes: [u8; 32] = [
+            3, 0, 0, 0,
+            0x00, 0x00, 0x00, 0x3f,
+            0x00, 0x00, 0x80, 0xbf,
+            0x00, 0x00, 0x00, 0x40,
+            2, 0, 0, 0, 0, 0, 0, 0,
+            11, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap();
+        assert_eq!(decoded.sign(), 11);
+        assert_eq!(decoded.emb(), &[0.5, -1.0]);
+        assert_eq!(decoded.opt(), &[2.0]);
+        assert!(!decoded.is_dirty());
+        assert_eq!(write_v1(&decoded, false), bytes);
+
+        // truncated entries fail instead of panicking
+        (0..bytes.len()).for_each(|len| {
+            assert!(HashMapEmbeddingEntry::read_from_buffer(&bytes[..len]).is_err());
+        });
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 8, 4, 37, 37);
+
+        let mut bytes = entry.write_to_vec().unwrap();
+        bytes[4..6].copy_from_slice(&(ENTRY_FORMAT_VERSION + 1).to_le_bytes());
+        let err = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unsupported embedding entry format version"));
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![0.25; 8], &[0.5; 2], 43);
+        let bytes = entry.write_to_vec().unwrap();
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap();
+        assert_same_entry(&decoded, &entry);
+
+        // flip one bit of every byte after the version, header and value bytes both count
+        (6..bytes.len()).for_each(|pos| {
+            let mut corrupt = bytes.clone();
+            corrupt[pos] ^= 0x10;
+            assert!(HashMapEmbeddingEntry::read_from_buffer(&corrupt).is_err());
+        });
+        let mut corrupt = bytes.clone();
+        corrupt[4 + 2 + 1 + 8 * 4] ^= 0x01;
+        let err = HashMapEmbeddingEntry::read_from_buffer(&corrupt).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("corrupt embedding entry of sign 43"));
+
+        // v3 entries carry no checksum and still load
+        let mut v3_bytes = bytes[..bytes.len() - 4].to_vec();
+        v3_bytes[4..6].copy_from_slice(&3u16.to_le_bytes());
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&v3_bytes).unwrap();
+        assert_same_entry(&decoded, &entry);
+    }
+
+    #[test]
+    fn test_huge_dim_rejected() {
+        // v3 header claiming a dim of 2^40 without any values following
+        let mut bytes = ENTRY_MAGIC.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&3u16.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&7u64.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        let err = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap_err();
+        assert!(err.to_string().contains("exceeds max embedding dim"));
+
+        // v1 entry of the same dim, which has fewer values than its dim
+        let mut bytes = 0u32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        bytes.extend_from_slice(&7u64.to_le_bytes());
+        let err = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap_err();
+        assert!(err.to_string().contains("exceeds entry length 0"));
+
+        // v1 length prefix beyond the max entry length fails before allocating
+        let bytes = (u32::MAX - 1).to_le_bytes();
+        assert!(HashMapEmbeddingEntry::read_from_buffer(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_entry_equality() {
+        let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![0.5, -1.25, 2.0], &[0.1], 47);
+        let mut same = entry.clone();
+        same.clear_dirty();
+        assert_eq!(entry, same);
+        assert_ne!(
+            entry,
+            HashMapEmbeddingEntry::from_emb_and_opt(vec![0.5, -1.25, 2.0], &[0.2], 47)
+        );
+        assert_ne!(
+            entry,
+            HashMapEmbeddingEntry::from_emb_and_opt(vec![0.5, -1.25, 2.0], &[0.1], 48)
+        );
+
+        let (data, scale) = entry.quantize_int8();
+        let dequantized = HashMapEmbeddingEntry::from_int8(&data, scale, 47);
+        assert_ne!(entry.emb(), dequantized.emb());
+        assert!(entry.approx_eq(&dequantized, scale));
+        assert!(!entry.approx_eq(&dequantized, 0.0));
+
+        // the same values split differently between embedding and optimizer state
+        let other_dim = HashMapEmbeddingEntry::from_emb_and_opt(vec![0.5, -1.25], &[2.0, 0.1], 47);
+        assert_ne!(entry, other_dim);
+        assert!(!entry.approx_eq(&other_dim, 1.0));
+    }
+
+    #[test]
+    fn test_flush_denormals() {
+        let subnormal = f32::MIN_POSITIVE / 4.0;
+        assert!(subnormal.is_subnormal());
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(
+            vec![1.0, subnormal, -subnormal],
+            &[subnormal],
+            53,
+        );
+        entry.clear_dirty();
+        assert!(entry.has_denormals());
+        assert_eq!(entry.flush_denormals(), 3);
+        assert_eq!(entry.emb(), &[1.0, 0.0, 0.0]);
+        assert_eq!(entry.opt(), &[0.0]);
+        assert!(entry.is_dirty());
+
+        entry.clear_dirty();
+        assert_eq!(entry.flush_denormals(), 0);
+        assert!(!entry.is_dirty());
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let data: Vec<i8> = (0..64).map(|x| (x % 5) as i8).collect();
+        let entry = HashMapEmbeddingEntry::from_int8(&data, 0.01, 41);
+
+        let mut buffer = Vec::new();
+        entry.write_compressed(&mut buffer, 0).unwrap();
+        entry.write_compressed(&mut buffer, 9).unwrap();
+        assert!(buffer.len() < 2 * entry.write_to_vec().unwrap().len());
+
+        let mut reader = buffer.as_slice();
+        for _ in 0..2 {
+            let decoded = HashMapEmbeddingEntry::read_compressed(&mut reader).unwrap();
+            assert_same_entry(&decoded, &entry);
+        }
+        assert!(reader.is_empty());
+        assert!(HashMapEmbeddingEntry::read_compressed(&mut reader).is_err());
+
+        // a corrupt length prefix is rejected instead of allocating 4GB
+        let mut hostile = buffer.clone();
+        hostile[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(HashMapEmbeddingEntry::read_compressed(&mut hostile.as_slice()).is_err());
+        let mut hostile = buffer;
+        hostile[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(HashMapEmbeddingEntry::read_compressed(&mut hostile.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_axpy_and_scale() {
+        let emb = vec![1.0, -2.0, 0.5, 3.0, 0.25];
+        let x = [0.5, 0.5, -1.0, 2.0, 4.0];
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(emb.clone(), &[9.0], 43);
+
+        entry.axpy(-0.1, &x).unwrap();
+        let expected: Vec<f32> = emb
+            .iter()
+            .zip(x.iter())
+            .map(|(w, x)| w + -0.1 * x)
+            .collect();
+        assert_eq!(entry.emb(), expected.as_slice());
+
+        entry.scale(2.0);
+        let expected: Vec<f32> = expected.iter().map(|x| x * 2.0).collect();
+        assert_eq!(entry.emb(), expected.as_slice());
+        assert_eq!(entry.opt(), &[9.0]);
+
+        assert!(matches!(
+            entry.axpy(1.0, &x[..3]),
+            Err(EntryError::DimMismatch {
+                expected: 5,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_dim_scale() {
+        let scale = [2.0, 0.5, 0.0, -1.0];
+        let mut entry =
+            HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0, 4.0, 3.0, 2.0], &[9.0], 1);
+        assert_eq!(entry.scaled_vec(&scale).unwrap(), vec![2.0, 2.0, 0.0, -2.0]);
+        assert_eq!(entry.emb(), &[1.0, 4.0, 3.0, 2.0]);
+        assert!(matches!(
+            entry.scaled_vec(&scale[..3]),
+            Err(EntryError::DimMismatch {
+                expected: 4,
+                actual: 3
+            })
+        ));
+
+        entry.apply_dim_scale(&scale).unwrap();
+        assert_eq!(entry.emb(), &[2.0, 2.0, 0.0, -2.0]);
+        assert_eq!(entry.opt(), &[9.0]);
+        assert!(matches!(
+            entry.apply_dim_scale(&scale[..2]),
+            Err(EntryError::DimMismatch {
+                expected: 4,
+                actual: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_dot() {
+        let emb: Vec<f32> = (0..19).map(|x| x as f32 * 0.5).collect();
+        let other: Vec<f32> = (0..19).map(|x| 1.0 - x as f32 * 0.25).collect();
+        let entry = HashMapEmbeddingEntry::from_emb_and_opt(emb.clone(), &[7.0; 19], 47);
+
+        let expected: f32 = emb.iter().zip(other.iter()).map(|(x, y)| x * y).sum();
+        assert!((entry.dot(&other).unwrap() - expected).abs() < 1e-4);
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![1.0, 2.0, 3.0], 47);
+        assert_eq!(entry.dot(&[4.0, -5.0, 6.0]).unwrap(), 12.0);
+    }
+
+    #[test]
+    fn test_dot_length_mismatch() {
+        let entry = HashMapEmbeddingEntry::from_emb(vec![1.0, 2.0, 3.0], 47);
+        assert!(matches!(
+            entry.dot(&[1.0, 2.0]),
+            Err(EntryError::DimMismatch {
+                expected: 3,
+                actual: 2
+            })
+        ));
+        assert!(entry.cosine_similarity(&[1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        let entry = HashMapEmbeddingEntry::from_emb(vec![1.0, 0.0, 2.0, 0.0], 53);
+        assert_eq!(
+            entry.cosine_similarity(&[0.0, 3.0, 0.0, -1.0]).unwrap(),
+            0.0
+        );
+        assert!((entry.cosine_similarity(&[1.0, 0.0, 2.0, 0.0]).unwrap() - 1.0).abs() < 1e-6);
+        assert!((entry.cosine_similarity(&[-2.0, 0.0, -4.0, 0.0]).unwrap() + 1.0).abs() < 1e-6);
+        assert_eq!(entry.cosine_similarity(&[0.0; 4]).unwrap(), 0.0);
+
+        let zero = HashMapEmbeddingEntry::from_emb(vec![0.0; 4], 53);
+        assert_eq!(zero.cosine_similarity(&[1.0, 0.0, 2.0, 0.0]).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_aligned_entry() {
+        let initialization = InitializationMethod::default();
+        let mut entry = HashMapEmbeddingEntry::new_aligned(&initialization, 13, 13, 59, 59);
+        assert!(entry.is_aligned());
+        assert_eq!(entry.as_emb_entry_slice().as_ptr() as usize % 64, 0);
+        entry.axpy(1.0, &[1.0; 13]).unwrap();
+
+        let bytes = entry.write_to_vec().unwrap();
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap();
+        assert!(decoded.is_aligned());
+        assert_eq!(decoded.as_emb_entry_slice().as_ptr() as usize % 64, 0);
+        assert_same_entry(&decoded, &entry);
+        assert_eq!(decoded.opt(), entry.opt());
+
+        let plain = HashMapEmbeddingEntry::new(&initialization, 13, 13, 59, 59);
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&plain.write_to_vec().unwrap());
+        assert!(!decoded.unwrap().is_aligned());
+
+        let empty = HashMapEmbeddingEntry::new_empty_aligned(8, 0, 1);
+        assert_eq!(empty.emb().as_ptr() as usize % 64, 0);
+        assert_eq!(empty.emb(), &[0.0; 8]);
+    }
+
+    #[test]
+    fn test_adaptive_lr() {
+        use crate::eviction_map::EvictionMap;
+
+        let mut map = EvictionMap::with_capacity(4);
+        let _ = map.insert(3, HashMapEmbeddingEntry::new_empty(4, 0, 3));
+        assert_eq!(map.get(&3).unwrap().adaptive_lr(0.1, 0.5), 0.1);
+
+        let mut last_lr = f32::INFINITY;
+        (1..=16u32).for_each(|count| {
+            let entry = map.get_mut(&3).unwrap();
+            assert_eq!(entry.access_count(), count);
+            let lr = entry.adaptive_lr(0.1, 0.5);
+            assert!(lr < last_lr || count == 1);
+            last_lr = lr;
+        });
+        assert!((last_lr - 0.025).abs() < 1e-7);
+
+        // maintenance is not an access
+        map.peek_mut(&3).unwrap().flush_denormals();
+        assert_eq!(map.get(&3).unwrap().access_count(), 16);
+        assert_eq!(map.get(&3).unwrap().adaptive_lr(0.1, 0.0), 0.1);
+    }
+
+    #[test]
+    fn test_frozen_entry() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0; 4], &[0.5; 4], 61);
+        entry.freeze();
+        assert!(entry.is_frozen());
+        assert_eq!(entry.emb(), &[1.0; 4]);
+
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&entry.write_to_vec().unwrap());
+        assert!(decoded.unwrap().into_aligned().is_frozen());
+
+        let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            entry.axpy(1.0, &[1.0; 4]).unwrap();
+        }));
+        assert!(rejected.is_err());
+        assert_eq!(entry.emb(), &[1.0; 4]);
+        let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            entry.resize_dim(8, &InitializationMethod::Zeros, 0);
+        }));
+        assert!(rejected.is_err());
+        assert_eq!(entry.embedding_dim(), 4);
+        assert_eq!(entry.as_emb_entry_slice().len(), 8);
+
+        entry.unfreeze();
+        entry.opt_mut()[0] = 0.0;
+        assert_eq!(entry.opt(), &[0.0, 0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "frozen embedding entry of sign 62")]
+    fn test_frozen_entry_rejects_mutation() {
+        let mut entry = HashMapEmbeddingEntry::from_emb(vec![1.0; 4], 62);
+        entry.freeze();
+        entry.emb_mut()[0] = 0.0;
+    }
+
+    #[test]
+    fn test_last_step() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0; 4], &[0.5], 67);
+        assert_eq!(entry.last_step(), 0);
+        entry.touch(1234);
+        assert_eq!(entry.last_step(), 1234);
+
+        let bytes = entry.write_to_vec().unwrap();
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&bytes).unwrap();
+        assert_eq!(decoded.last_step(), 1234);
+        assert_same_entry(&decoded, &entry);
+
+        // v2 entries have no last step
+        let mut v2_bytes = bytes[..4 + 2 + 1 + 8 * 3].to_vec();
+        v2_bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        v2_bytes.extend_from_slice(&bytes[4 + 2 + 1 + 8 * 4..bytes.len() - 4]);
+        let decoded = HashMapEmbeddingEntry::read_from_buffer(&v2_bytes).unwrap();
+        assert_eq!(decoded.last_step(), 0);
+        assert_same_entry(&decoded, &entry);
+        assert_eq!(decoded.opt(), &[0.5]);
+    }
 }
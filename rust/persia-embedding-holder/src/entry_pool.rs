@@ -0,0 +1,247 @@
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use persia_libs::parking_lot::Mutex;
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+
+struct PoolInner {
+    // Base pointers of blocks leaked from boxed slices. Blocks are only freed when the last
+    // handle to the pool is dropped, so the rows handed out keep pointing into valid memory.
+    blocks: Vec<NonNull<f32>>,
+    block_len: usize,
+    free_rows: Vec<usize>,
+    // Counted under the pool lock, which acquire takes anyway.
+    acquisitions: u64,
+    reuses: u64,
+}
+
+// Rows of the blocks are only accessed through the PooledEntry that owns them.
+unsafe impl Send for PoolInner {}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        let block_len = self.block_len;
+        self.blocks.drain(..).for_each(|ptr| unsafe {
+            let block = std::ptr::slice_from_raw_parts_mut(ptr.as_ptr(), block_len);
+            drop(Box::from_raw(block));
+        });
+    }
+}
+
+/// Snapshot of the usage of an [`EntryPool`], see [`EntryPool::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub blocks: usize,
+    pub rows_in_use: usize,
+    pub rows_free: usize,
+    pub acquisitions: u64,
+    /// Acquisitions served by a free row, without allocating a block.
+    pub reuses: u64,
+}
+
+impl PoolStats {
+    /// Share of acquisitions served without allocating a block. A low rate after warm up means
+    /// the blocks are too small for the workload.
+    pub fn reuse_hit_rate(&self) -> f64 {
+        if self.acquisitions == 0 {
+            0.0
+        } else {
+            self.reuses as f64 / self.acquisitions as f64
+        }
+    }
+}
+
+/// Object pool of fixed length `f32` rows carved out of large contiguous blocks. When the pool
+/// is exhausted, a new block of `block_rows` rows is allocated and split into rows. Dropping a
+/// [`PooledEntry`] returns its row to the pool.
+#[derive(Clone)]
+pub struct EntryPool {
+    row_len: usize,
+    block_rows: usize,
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+impl EntryPool {
+    pub fn new(row_len: usize, block_rows: usize) -> Self {
+        assert!(block_rows > 0, "block_rows of entry pool must be positive");
+        Self {
+            row_len,
+            block_rows,
+            inner: Arc::new(Mutex::new(PoolInner {
+                blocks: Vec::new(),
+                block_len: row_len * block_rows,
+                free_rows: Vec::new(),
+                acquisitions: 0,
+                reuses: 0,
+            })),
+        }
+    }
+
+    pub fn row_len(&self) -> usize {
+        self.row_len
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.inner.lock().blocks.len()
+    }
+
+    pub fn num_free_rows(&self) -> usize {
+        self.inner.lock().free_rows.len()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let inner = self.inner.lock();
+        let rows_free = inner.free_rows.len();
+        PoolStats {
+            blocks: inner.blocks.len(),
+            rows_in_use: inner.blocks.len() * self.block_rows - rows_free,
+            rows_free,
+            acquisitions: inner.acquisitions,
+            reuses: inner.reuses,
+        }
+    }
+
+    /// Takes a zeroed row out of the pool, allocating a new block if there is no free row.
+    pub fn acquire(&self, sign: u64) -> PooledEntry {
+        let mut inner = self.inner.lock();
+        inner.acquisitions += 1;
+        let row = match inner.free_rows.pop() {
+            Some(row) => {
+                inner.reuses += 1;
+                row
+            }
+            None => {
+                let first_row = inner.blocks.len() * self.block_rows;
+                let block = vec![0f32; inner.block_len].into_boxed_slice();
+                let ptr = Box::into_raw(block) as *mut f32;
+                inner
+                    .blocks
+                    .push(NonNull::new(ptr).expect("block pointer is null"));
+                inner
+                    .free_rows
+                    .extend((first_row + 1..first_row + self.block_rows).rev());
+                first_row
+            }
+        };
+
+        let offset = (row % self.block_rows) * self.row_len;
+        let ptr = unsafe {
+            let ptr = inner.blocks[row / self.block_rows].as_ptr().add(offset);
+            std::ptr::write_bytes(ptr, 0, self.row_len);
+            NonNull::new_unchecked(ptr)
+        };
+
+        PooledEntry {
+            ptr,
+            row,
+            row_len: self.row_len,
+            sign,
+            pool: self.inner.clone(),
+        }
+    }
+}
+
+/// A row borrowed from an [`EntryPool`]. Laid out like [`HashMapEmbeddingEntry`], the embedding
+/// followed by the optimizer state.
+pub struct PooledEntry {
+    ptr: NonNull<f32>,
+    row: usize,
+    row_len: usize,
+    sign: u64,
+    pool: Arc<Mutex<PoolInner>>,
+}
+
+// The row is exclusively owned by this handle until it is dropped.
+unsafe impl Send for PooledEntry {}
+unsafe impl Sync for PooledEntry {}
+
+impl PooledEntry {
+    pub fn sign(&self) -> u64 {
+        self.sign
+    }
+
+    pub fn inner_size(&self) -> usize {
+        self.row_len
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.row_len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.row_len) }
+    }
+
+    pub fn to_entry(&self, embedding_dim: usize) -> HashMapEmbeddingEntry {
+        let (emb, opt) = self.as_slice().split_at(embedding_dim);
+        HashMapEmbeddingEntry::from_emb_and_opt(emb.to_vec(), opt, self.sign)
+    }
+}
+
+impl Drop for PooledEntry {
+    fn drop(&mut self) {
+        self.pool.lock().free_rows.push(self.row);
+    }
+}
+
+#[cfg(test)]
+mod entry_pool_tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire_release() {
+        let pool = EntryPool::new(4, 2);
+        let mut first = pool.acquire(1);
+        let second = pool.acquire(2);
+        assert_eq!(pool.num_blocks(), 1);
+        assert_eq!(pool.num_free_rows(), 0);
+
+        first.as_mut_slice().copy_from_slice(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(second.as_slice(), &[0.0; 4]);
+        let entry = first.to_entry(3);
+        assert_eq!(entry.emb(), &[1.0, 2.0, 3.0]);
+        assert_eq!(entry.opt(), &[4.0]);
+        assert_eq!(entry.sign(), 1);
+
+        let third = pool.acquire(3);
+        assert_eq!(pool.num_blocks(), 2);
+        assert_eq!(pool.num_free_rows(), 1);
+
+        drop(first);
+        assert_eq!(pool.num_free_rows(), 2);
+        let reused = pool.acquire(4);
+        assert_eq!(reused.as_slice(), &[0.0; 4]);
+        assert_eq!(pool.num_blocks(), 2);
+
+        drop(second);
+        drop(third);
+        drop(reused);
+        assert_eq!(pool.num_free_rows(), 4);
+    }
+
+    #[test]
+    fn test_stats() {
+        let pool = EntryPool::new(4, 3);
+        assert_eq!(pool.stats(), PoolStats::default());
+
+        let mut rows: Vec<_> = (0..4).map(|x| pool.acquire(x)).collect();
+        let stats = pool.stats();
+        assert_eq!(stats.blocks, 2);
+        assert_eq!(stats.rows_in_use, 4);
+        assert_eq!(stats.rows_free, 2);
+        // the first rows of both blocks needed an allocation
+        assert_eq!((stats.acquisitions, stats.reuses), (4, 2));
+
+        rows.truncate(1);
+        rows.push(pool.acquire(9));
+        let stats = pool.stats();
+        assert_eq!((stats.rows_in_use, stats.rows_free), (2, 4));
+        assert_eq!((stats.acquisitions, stats.reuses), (5, 3));
+        assert!((stats.reuse_hit_rate() - 0.6).abs() < 1e-9);
+
+        drop(rows);
+        assert_eq!(pool.stats().rows_in_use, 0);
+        assert_eq!(pool.stats().rows_free, 6);
+    }
+}
@@ -1,21 +1,129 @@
-use persia_libs::hashbrown::HashMap;
 use std::convert::TryFrom;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::admission::AdmissionFilter;
 use crate::array_linked_list::ArrayLinkedList;
+use crate::bloom::BloomFilter;
+use crate::map_backend::{DefaultMapBackend, MapBackend};
 
 pub trait EvictionMapValue<K> {
     fn hashmap_key(&self) -> K;
+
+    /// Called when the value is handed out by [`EvictionMap::get_mut`] or
+    /// [`EvictionMap::get_refresh_mut`], but not by [`EvictionMap::peek_mut`].
+    fn on_mut_access(&mut self) {}
+}
+
+// Number of the least recently inserted entries among which the lfu policy picks its victim.
+const LFU_EVICTION_SAMPLES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently inserted or refreshed entry.
+    Lru,
+    /// Evict the least frequently accessed entry, approximated by sampling the least recently
+    /// inserted entries.
+    Lfu,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        Self::Lru
+    }
+}
+
+pub type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Called with the key of every entry handed out mutably, replaced, evicted or removed, e.g. to
+/// invalidate copies of the entry cached elsewhere. [`EvictionMap::clear`] does not call it.
+pub type MutationHook<K> = Arc<dyn Fn(&K) + Send + Sync>;
+
+/// Last access time of every entry, indexed by the entry's position in the linked list. Times
+/// are kept as nanoseconds since `epoch` so reads through `&self` can update them.
+pub struct AccessTimes {
+    epoch: Instant,
+    clock: Clock,
+    nanos: Vec<AtomicU64>,
 }
 
-pub struct EvictionMap<K, V>
+impl AccessTimes {
+    fn new(clock: Clock) -> Self {
+        Self {
+            epoch: clock(),
+            clock,
+            nanos: Vec::new(),
+        }
+    }
+
+    fn nanos_since_epoch(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.epoch).as_nanos() as u64
+    }
+
+    fn touch(&self, idx: u32) {
+        if let Some(nanos) = self.nanos.get(idx as usize) {
+            nanos.store(self.nanos_since_epoch((self.clock)()), Ordering::Relaxed);
+        }
+    }
+
+    fn touch_slot(&mut self, idx: u32) {
+        while self.nanos.len() <= idx as usize {
+            self.nanos.push(AtomicU64::new(0));
+        }
+        self.touch(idx);
+    }
+
+    fn is_expired(&self, idx: u32, now: Instant, ttl: Duration) -> bool {
+        let last_access = self.nanos[idx as usize].load(Ordering::Relaxed);
+        self.nanos_since_epoch(now).saturating_sub(last_access) > ttl.as_nanos() as u64
+    }
+}
+
+/// Saturating 8 bit access counter of every entry, indexed like [`AccessTimes`].
+#[derive(Default)]
+pub struct AccessCounts {
+    counts: Vec<AtomicU8>,
+}
+
+impl AccessCounts {
+    fn get(&self, idx: u32) -> u8 {
+        self.counts
+            .get(idx as usize)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    fn increment(&self, idx: u32) {
+        if let Some(count) = self.counts.get(idx as usize) {
+            let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_add(1));
+        }
+    }
+
+    fn set(&mut self, idx: u32, value: u8) {
+        while self.counts.len() <= idx as usize {
+            self.counts.push(AtomicU8::new(0));
+        }
+        self.counts[idx as usize].store(value, Ordering::Relaxed);
+    }
+}
+
+pub struct EvictionMap<K, V, M = DefaultMapBackend<K>>
 where
     K: Hash + Eq + Clone,
     V: EvictionMapValue<K>,
+    M: MapBackend<K>,
 {
-    pub hashmap: HashMap<K, u32>,
+    pub hashmap: M,
     pub linkedlist: ArrayLinkedList<V>,
     pub capacity: usize,
+    pub policy: EvictionPolicy,
+    pub access_times: Option<AccessTimes>,
+    pub access_counts: Option<AccessCounts>,
+    pub admission: Option<AdmissionFilter>,
+    pub bloom: Option<BloomFilter>,
+    pub on_mutation: Option<MutationHook<K>>,
 }
 
 impl<K, V> EvictionMap<K, V>
@@ -24,35 +132,208 @@ where
     V: EvictionMapValue<K>,
 {
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_reserved(capacity, capacity)
+    }
+
+    /// Map evicting beyond `capacity` entries, which allocates room for `reserved` entries
+    /// only and grows on demand after that.
+    pub fn with_reserved(capacity: usize, reserved: usize) -> Self {
+        Self::with_backend(capacity, reserved)
+    }
+
+    pub fn with_policy(capacity: usize, policy: EvictionPolicy) -> Self {
+        let mut map = Self::with_capacity(capacity);
+        map.policy = policy;
+        if policy == EvictionPolicy::Lfu {
+            map.access_counts = Some(AccessCounts::default());
+        }
+        map
+    }
+
+    /// Track the last access time of entries, which is required by [`EvictionMap::evict_expired`].
+    /// This is opt-in since it costs a timestamp store on every access.
+    pub fn with_ttl_tracking(capacity: usize) -> Self {
+        Self::with_ttl_tracking_clock(capacity, Arc::new(Instant::now))
+    }
+
+    pub fn with_ttl_tracking_clock(capacity: usize, clock: Clock) -> Self {
+        let mut map = Self::with_capacity(capacity);
+        map.access_times = Some(AccessTimes::new(clock));
+        map
+    }
+}
+
+impl<K, V, M> EvictionMap<K, V, M>
+where
+    K: Hash + Eq + Clone,
+    V: EvictionMapValue<K>,
+    M: MapBackend<K>,
+{
+    /// [`EvictionMap::with_reserved`] indexing the entries with the map backend `M`.
+    pub fn with_backend(capacity: usize, reserved: usize) -> Self {
         Self {
-            hashmap: HashMap::with_capacity(capacity + 1),
-            linkedlist: ArrayLinkedList::with_capacity(capacity as u32 + 1),
+            hashmap: M::with_capacity(reserved + 1),
+            linkedlist: ArrayLinkedList::with_capacity(reserved as u32 + 1),
             capacity,
+            policy: EvictionPolicy::Lru,
+            access_times: None,
+            access_counts: None,
+            admission: None,
+            bloom: None,
+            on_mutation: None,
+        }
+    }
+
+    /// Only admit keys observed `threshold` times, see [`EvictionMap::admit`].
+    pub fn with_admission(self, threshold: u32, sketch_width: usize, sketch_depth: usize) -> Self {
+        let mut map = self;
+        map.admission = Some(AdmissionFilter::new(threshold, sketch_width, sketch_depth));
+        map
+    }
+
+    /// Record an occurrence of a missing key and return whether it should be inserted. Always
+    /// true when the map has no admission filter.
+    pub fn admit(&mut self, key: &K) -> bool {
+        match &mut self.admission {
+            Some(admission) => admission.admit(key),
+            None => true,
+        }
+    }
+
+    /// Record inserted keys in a bloom filter sized for `expected_items` keys, so that
+    /// [`EvictionMap::may_contain`] rules out most missing keys without probing the map.
+    pub fn with_bloom_filter(self, expected_items: usize, false_positive_rate: f64) -> Self {
+        let mut map = self;
+        map.enable_bloom_filter(expected_items, false_positive_rate);
+        map
+    }
+
+    /// Replaces the bloom filter of the map by one holding the present keys.
+    pub fn enable_bloom_filter(&mut self, expected_items: usize, false_positive_rate: f64) {
+        let mut bloom = BloomFilter::new(expected_items, false_positive_rate);
+        self.hashmap.for_each_key(|key| bloom.insert(key));
+        self.bloom = Some(bloom);
+    }
+
+    /// False if `key` is definitely missing, true if it may be present. Always true when the
+    /// map has no bloom filter.
+    pub fn may_contain(&self, key: &K) -> bool {
+        match &self.bloom {
+            Some(bloom) => bloom.may_contain(key),
+            None => true,
+        }
+    }
+
+    /// Rebuilds the bloom filter from the present keys, which drops the bits of removed and
+    /// evicted keys.
+    pub fn rebuild_bloom_filter(&mut self) {
+        if let Some(bloom) = &mut self.bloom {
+            bloom.clear();
+            self.hashmap.for_each_key(|key| bloom.insert(key));
+        }
+    }
+
+    pub fn clear_admission(&mut self) {
+        if let Some(admission) = &mut self.admission {
+            admission.clear();
+        }
+    }
+
+    #[inline]
+    fn notify_mutation(&self, key: &K) {
+        if let Some(hook) = &self.on_mutation {
+            hook(key);
+        }
+    }
+
+    #[inline]
+    fn record_access(&self, idx: u32) {
+        if let Some(access_times) = &self.access_times {
+            access_times.touch(idx);
+        }
+        if let Some(access_counts) = &self.access_counts {
+            access_counts.increment(idx);
+        }
+    }
+
+    #[inline]
+    fn record_slot(&mut self, idx: u32, access_count: u8) {
+        if let Some(access_times) = &mut self.access_times {
+            access_times.touch_slot(idx);
+        }
+        if let Some(access_counts) = &mut self.access_counts {
+            access_counts.set(idx, access_count);
+        }
+    }
+
+    #[inline]
+    fn access_count(&self, idx: u32) -> u8 {
+        match &self.access_counts {
+            Some(access_counts) => access_counts.get(idx),
+            None => 0,
+        }
+    }
+
+    fn pop_victim(&mut self) -> Option<V> {
+        match self.policy {
+            EvictionPolicy::Lru => self.linkedlist.pop_front(),
+            EvictionPolicy::Lfu => {
+                let victim = self
+                    .linkedlist
+                    .indices()
+                    .take(LFU_EVICTION_SAMPLES)
+                    .min_by_key(|idx| self.access_count(*idx));
+                victim.and_then(|idx| self.linkedlist.remove(idx))
+            }
         }
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
-        match self.hashmap.get(&key) {
-            Some(idx) => self.linkedlist[*idx as usize].as_ref(),
+        match self.hashmap.get(key) {
+            Some(idx) => {
+                self.record_access(idx);
+                self.linkedlist[idx as usize].as_ref()
+            }
             None => None,
         }
     }
 
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        match self.hashmap.get(&key) {
-            Some(idx) => self.linkedlist[*idx as usize].as_mut(),
+        match self.hashmap.get(key) {
+            Some(idx) => {
+                self.notify_mutation(key);
+                self.record_access(idx);
+                let v = self.linkedlist[idx as usize].as_mut();
+                v.map(|v| {
+                    v.on_mut_access();
+                    v
+                })
+            }
+            None => None,
+        }
+    }
+
+    /// Like [`Self::get_mut`], but leaves the access statistics and the eviction order alone,
+    /// for maintenance such as tombstoning that is not an access by training.
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        match self.hashmap.get(key) {
+            Some(idx) => {
+                self.notify_mutation(key);
+                self.linkedlist[idx as usize].as_mut()
+            }
             None => None,
         }
     }
 
     pub fn get_refresh(&mut self, key: &K) -> Option<&V> {
-        match self.hashmap.get(&key) {
+        match self.hashmap.get(key) {
             Some(idx) => {
-                let idx = u32::try_from(*idx).expect("u32 array linked list overflow");
+                let idx = u32::try_from(idx).expect("u32 array linked list overflow");
+                let access_count = self.access_count(idx);
                 let v = self.linkedlist.remove(idx).unwrap();
                 let new_idx = self.linkedlist.push_back(v);
-                let idx_ref = self.hashmap.get_mut(key).unwrap();
-                *idx_ref = new_idx;
+                self.hashmap.insert(key.clone(), new_idx);
+                self.record_slot(new_idx, access_count.saturating_add(1));
                 self.linkedlist[new_idx as usize].as_ref()
             }
             None => None,
@@ -60,32 +341,44 @@ where
     }
 
     pub fn get_refresh_mut(&mut self, key: &K) -> Option<&mut V> {
-        match self.hashmap.get(&key) {
+        match self.hashmap.get(key) {
             Some(idx) => {
-                let idx = u32::try_from(*idx).expect("u32 array linked list overflow");
+                let idx = u32::try_from(idx).expect("u32 array linked list overflow");
+                self.notify_mutation(key);
+                let access_count = self.access_count(idx);
                 let v = self.linkedlist.remove(idx).unwrap();
                 let new_idx = self.linkedlist.push_back(v);
-                let idx_ref = self.hashmap.get_mut(key).unwrap();
-                *idx_ref = new_idx;
-                self.linkedlist[new_idx as usize].as_mut()
+                self.hashmap.insert(key.clone(), new_idx);
+                self.record_slot(new_idx, access_count.saturating_add(1));
+                let v = self.linkedlist[new_idx as usize].as_mut();
+                v.map(|v| {
+                    v.on_mut_access();
+                    v
+                })
             }
             None => None,
         }
     }
 
     pub fn insert(&mut self, key: K, value: V) -> (Option<V>, Option<V>) {
-        let old = match self.hashmap.get(&key) {
-            Some(idx) => self.linkedlist.remove(*idx),
-            None => None,
+        self.notify_mutation(&key);
+        let (old, access_count) = match self.hashmap.get(&key) {
+            Some(idx) => (self.linkedlist.remove(idx), self.access_count(idx)),
+            None => (None, 0),
         };
 
         let new_idx = self.linkedlist.push_back(value);
+        if let Some(bloom) = &mut self.bloom {
+            bloom.insert(&key);
+        }
         self.hashmap.insert(key, new_idx);
+        self.record_slot(new_idx, access_count.saturating_add(1));
 
         let evicted = if self.linkedlist.len() as usize > self.capacity {
-            let evicted = self.linkedlist.pop_front();
+            let evicted = self.pop_victim();
             if let Some(evicted_v) = &evicted {
                 let evicted_k = evicted_v.hashmap_key();
+                self.notify_mutation(&evicted_k);
                 self.hashmap.remove(&evicted_k);
             }
             evicted
@@ -96,15 +389,77 @@ where
         (old, evicted)
     }
 
+    /// Remove entries not accessed for longer than `ttl` before `now`, returns the number of
+    /// evicted entries. Nothing is evicted if the map was not created with ttl tracking.
+    pub fn evict_expired(&mut self, now: Instant, ttl: Duration) -> usize {
+        let expired: Vec<u32> = match &self.access_times {
+            Some(access_times) => self
+                .linkedlist
+                .indices()
+                .filter(|idx| access_times.is_expired(*idx, now, ttl))
+                .collect(),
+            None => return 0,
+        };
+
+        expired.iter().for_each(|idx| {
+            if let Some(evicted) = self.linkedlist.remove(*idx) {
+                let key = evicted.hashmap_key();
+                self.notify_mutation(&key);
+                self.hashmap.remove(&key);
+            }
+        });
+        expired.len()
+    }
+
+    /// Removes the entry of `key`, returns it if it was present.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = self.hashmap.remove(key)?;
+        self.notify_mutation(key);
+        self.linkedlist.remove(idx)
+    }
+
+    /// Remove the entries for which `f` returns false, returns the number of removed entries.
+    pub fn retain<F: FnMut(&V) -> bool>(&mut self, mut f: F) -> usize {
+        let removed: Vec<u32> = self
+            .linkedlist
+            .indexed()
+            .filter(|(_, v)| !f(v))
+            .map(|(idx, _)| idx)
+            .collect();
+
+        removed.iter().for_each(|idx| {
+            if let Some(v) = self.linkedlist.remove(*idx) {
+                let key = v.hashmap_key();
+                self.notify_mutation(&key);
+                self.hashmap.remove(&key);
+            }
+        });
+        removed.len()
+    }
+
     pub fn clear(&mut self) {
         self.hashmap.clear();
         self.linkedlist.clear();
+        if let Some(bloom) = &mut self.bloom {
+            bloom.clear();
+        }
     }
 
     pub fn capacity(&self) -> usize {
         self.capacity
     }
 
+    /// Reserves room for `additional` more entries, so that inserting them does not rehash.
+    pub fn reserve(&mut self, additional: usize) {
+        self.hashmap.reserve(additional);
+        self.linkedlist.reserve(additional as u32);
+    }
+
+    /// Number of entries the map can hold without rehashing.
+    pub fn reserved_capacity(&self) -> usize {
+        self.hashmap.capacity()
+    }
+
     pub fn len(&self) -> usize {
         self.linkedlist.len() as usize
     }
@@ -146,4 +501,106 @@ mod eviction_map_tests {
         assert_eq!(map.get_refresh(&6).is_none(), true);
         assert_eq!(map.get_refresh(&5).is_some(), true);
     }
+
+    #[test]
+    fn test_evict_expired() {
+        let elapsed_secs = Arc::new(AtomicU64::new(0));
+        let start = Instant::now();
+        let clock: Clock = {
+            let elapsed_secs = elapsed_secs.clone();
+            Arc::new(move || start + Duration::from_secs(elapsed_secs.load(Ordering::Relaxed)))
+        };
+        let mut map: EvictionMap<u64, HashMapEmbeddingEntry> =
+            EvictionMap::with_ttl_tracking_clock(10, clock);
+
+        let initialization = InitializationMethod::default();
+        for i in 0..6 {
+            let entry = HashMapEmbeddingEntry::new(&initialization, 8, 16, i, i);
+            map.insert(i, entry);
+        }
+
+        elapsed_secs.store(50, Ordering::Relaxed);
+        assert!(map.get(&0).is_some());
+        assert!(map.get_mut(&1).is_some());
+        assert!(map.get_refresh(&2).is_some());
+
+        let ttl = Duration::from_secs(60);
+        assert_eq!(map.evict_expired(start + Duration::from_secs(60), ttl), 0);
+        assert_eq!(map.evict_expired(start + Duration::from_secs(100), ttl), 3);
+        assert_eq!(map.len(), 3);
+        for i in 0..3 {
+            assert!(map.get(&i).is_some());
+        }
+        for i in 3..6 {
+            assert!(map.get(&i).is_none());
+        }
+
+        let mut untracked: EvictionMap<u64, HashMapEmbeddingEntry> = EvictionMap::with_capacity(5);
+        let entry = HashMapEmbeddingEntry::new(&initialization, 8, 16, 0, 0);
+        untracked.insert(0, entry);
+        assert_eq!(
+            untracked.evict_expired(start + Duration::from_secs(100), ttl),
+            0
+        );
+    }
+
+    #[test]
+    fn test_lfu_evict() {
+        let mut map: EvictionMap<u64, HashMapEmbeddingEntry> =
+            EvictionMap::with_policy(10, EvictionPolicy::Lfu);
+
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 8, 16, 0, 0);
+        map.insert(0, entry);
+        for _ in 0..50 {
+            assert!(map.get(&0).is_some());
+        }
+
+        for i in 1..100 {
+            let entry = HashMapEmbeddingEntry::new(&initialization, 8, 16, i, i);
+            map.insert(i, entry);
+        }
+
+        assert_eq!(map.len(), 10);
+        assert!(map.get(&0).is_some());
+        for i in 1..90 {
+            assert!(map.get(&i).is_none());
+        }
+
+        let mut lru_map: EvictionMap<u64, HashMapEmbeddingEntry> =
+            EvictionMap::with_policy(10, EvictionPolicy::Lru);
+        let entry = HashMapEmbeddingEntry::new(&initialization, 8, 16, 0, 0);
+        lru_map.insert(0, entry);
+        for _ in 0..50 {
+            assert!(lru_map.get(&0).is_some());
+        }
+        for i in 1..100 {
+            let entry = HashMapEmbeddingEntry::new(&initialization, 8, 16, i, i);
+            lru_map.insert(i, entry);
+        }
+        assert!(lru_map.get(&0).is_none());
+    }
+
+    #[test]
+    fn test_admission() {
+        let mut map: EvictionMap<u64, HashMapEmbeddingEntry> =
+            EvictionMap::with_capacity(1000).with_admission(3, 1024, 4);
+
+        for i in 0..100 {
+            assert!(!map.admit(&i));
+        }
+        assert!(!map.admit(&7));
+        assert!(map.admit(&7));
+        assert!(map.admit(&7));
+        for i in 100..200 {
+            assert!(!map.admit(&i));
+        }
+
+        map.clear_admission();
+        assert!(!map.admit(&7));
+
+        let mut unfiltered: EvictionMap<u64, HashMapEmbeddingEntry> =
+            EvictionMap::with_capacity(1000);
+        assert!(unfiltered.admit(&7));
+    }
 }
@@ -0,0 +1,280 @@
+use std::io::{self, Write};
+
+#[cfg(feature = "arrow")]
+use std::sync::Arc;
+
+#[cfg(feature = "arrow")]
+use arrow::{
+    array::{ArrayRef, FixedSizeListBuilder, Float32Builder, UInt64Array},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+
+use crate::emb_entry::EntryError;
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+// numpy pads the header so that the data starts on a 64 byte boundary
+const NPY_HEADER_ALIGNMENT: usize = 64;
+
+/// Writes a `.npy` version 1.0 header for a C ordered array.
+fn write_npy_header<W: Write>(w: &mut W, descr: &str, shape: &[usize]) -> io::Result<()> {
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // magic, version and header length take 10 bytes, the header ends with a newline
+    let unpadded_len = NPY_MAGIC.len() + 4 + header.len() + 1;
+    let padding =
+        (NPY_HEADER_ALIGNMENT - unpadded_len % NPY_HEADER_ALIGNMENT) % NPY_HEADER_ALIGNMENT;
+    header.extend(std::iter::repeat(' ').take(padding));
+    header.push('\n');
+
+    w.write_all(NPY_MAGIC)?;
+    w.write_all(&[1, 0])?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Exports the embeddings as a `float32` `.npy` array of shape `[num_rows, dim]` to `w`,
+    /// and the signs of the rows as a parallel `uint64` `.npy` array to `signs_w`. Optimizer
+    /// states and tombstoned entries are not exported. Fails if the entries do not share one
+    /// embedding dim.
+    pub fn export_npy<W: Write, S: Write>(&self, w: &mut W, signs_w: &mut S) -> io::Result<()> {
+        let guard = self.read_all();
+        let num_rows = guard.iter().count();
+        let mut entries = guard.iter();
+        let dim = entries.next().map(|x| x.emb.len()).unwrap_or(0);
+        if let Some(entry) = entries.find(|x| x.emb.len() != dim) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "sign {} has embedding dim {}, expected {}",
+                    entry.sign,
+                    entry.emb.len(),
+                    dim
+                ),
+            ));
+        }
+
+        write_npy_header(w, "<f4", &[num_rows, dim])?;
+        write_npy_header(signs_w, "<u8", &[num_rows])?;
+        for entry in guard.iter() {
+            let bytes: Vec<u8> = entry.emb.iter().flat_map(|x| x.to_le_bytes()).collect();
+            w.write_all(&bytes)?;
+            signs_w.write_all(&entry.sign.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Materializes the embeddings of `sign_order` as a row major `[sign_order.len(), dim]`
+    /// matrix that a gather op can index, so that row `i` is the embedding of `sign_order[i]`.
+    /// Rows of missing and tombstoned signs are filled with `fill`. Returns the matrix and the parallel signs,
+    /// or an error if the present rows do not share one embedding dim.
+    pub fn to_dense_matrix(
+        &self,
+        sign_order: &[u64],
+        fill: f32,
+    ) -> Result<(Vec<f32>, Vec<u64>), EntryError> {
+        let guard = self.read_all();
+        let rows: Vec<_> = sign_order.iter().map(|sign| guard.get(*sign)).collect();
+        let dim = rows
+            .iter()
+            .flatten()
+            .next()
+            .map(|x| x.emb.len())
+            .unwrap_or(0);
+        if let Some(entry) = rows.iter().flatten().find(|x| x.emb.len() != dim) {
+            return Err(EntryError::DimMismatch {
+                expected: dim,
+                actual: entry.emb.len(),
+            });
+        }
+
+        let mut matrix = Vec::with_capacity(sign_order.len() * dim);
+        rows.iter().for_each(|row| match row {
+            Some(entry) => matrix.extend_from_slice(entry.emb),
+            None => matrix.extend(std::iter::repeat(fill).take(dim)),
+        });
+        Ok((matrix, sign_order.to_vec()))
+    }
+
+    /// Converts the holder into a record batch with a `UInt64` `sign` column and a
+    /// `FixedSizeList<Float32>` `embedding` column of width `dim`. Optimizer states and
+    /// tombstoned entries are not exported. Fails if the entries do not share one embedding dim.
+    #[cfg(feature = "arrow")]
+    pub fn to_arrow(&self) -> Result<RecordBatch, ArrowError> {
+        let guard = self.read_all();
+        let num_rows = guard.iter().count();
+        let mut entries = guard.iter();
+        let dim = entries.next().map(|x| x.emb.len()).unwrap_or(0);
+        if let Some(entry) = entries.find(|x| x.emb.len() != dim) {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "sign {} has embedding dim {}, expected {}",
+                entry.sign,
+                entry.emb.len(),
+                dim
+            )));
+        }
+
+        let mut signs = Vec::with_capacity(num_rows);
+        let mut embeddings =
+            FixedSizeListBuilder::new(Float32Builder::new(num_rows * dim), dim as i32);
+        for entry in guard.iter() {
+            signs.push(entry.sign);
+            embeddings.values().append_slice(entry.emb)?;
+            embeddings.append(true)?;
+        }
+
+        RecordBatch::try_from_iter(vec![
+            ("sign", Arc::new(UInt64Array::from(signs)) as ArrayRef),
+            ("embedding", Arc::new(embeddings.finish()) as ArrayRef),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    // Minimal npy parser returning the header dict and the data.
+    fn parse_npy(bytes: &[u8]) -> (String, &[u8]) {
+        assert_eq!(&bytes[..6], NPY_MAGIC);
+        assert_eq!(&bytes[6..8], &[1, 0]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % NPY_HEADER_ALIGNMENT, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.ends_with('\n'));
+        (header.trim_end().to_string(), &bytes[10 + header_len..])
+    }
+
+    #[test]
+    fn test_export_npy() {
+        let holder = PersiaEmbeddingHolder::new(100, 2);
+        (0..3u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![sign as f32; 4], &[9.0], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![-1.0; 4], 3);
+        let _ = holder.shard(&3).write().insert(3, entry);
+        holder.tombstone(3);
+
+        let mut emb_bytes = Vec::new();
+        let mut sign_bytes = Vec::new();
+        holder.export_npy(&mut emb_bytes, &mut sign_bytes).unwrap();
+
+        let (header, data) = parse_npy(&emb_bytes);
+        assert_eq!(
+            header,
+            "{'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), }"
+        );
+        let (sign_header, sign_data) = parse_npy(&sign_bytes);
+        assert_eq!(
+            sign_header,
+            "{'descr': '<u8', 'fortran_order': False, 'shape': (3,), }"
+        );
+        assert_eq!(data.len(), 3 * 4 * 4);
+        assert_eq!(sign_data.len(), 3 * 8);
+
+        data.chunks(16)
+            .zip(sign_data.chunks(8))
+            .for_each(|(row, sign)| {
+                let sign = u64::from_le_bytes([
+                    sign[0], sign[1], sign[2], sign[3], sign[4], sign[5], sign[6], sign[7],
+                ]);
+                row.chunks(4).for_each(|x| {
+                    assert_eq!(f32::from_le_bytes([x[0], x[1], x[2], x[3]]), sign as f32);
+                });
+            });
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![0.0; 8], 10);
+        let _ = holder.shard(&10).write().insert(10, entry);
+        assert!(holder.export_npy(&mut Vec::new(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_to_dense_matrix() {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        (1..4u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![sign as f32; 2], &[9.0], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        let (matrix, signs) = holder.to_dense_matrix(&[3, 7, 1], -1.0).unwrap();
+        assert_eq!(signs, vec![3, 7, 1]);
+        assert_eq!(matrix, vec![3.0, 3.0, -1.0, -1.0, 1.0, 1.0]);
+        holder.tombstone(3);
+        let (matrix, _) = holder.to_dense_matrix(&[3, 1], -1.0).unwrap();
+        assert_eq!(matrix, vec![-1.0, -1.0, 1.0, 1.0]);
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![0.0; 8], 10);
+        let _ = holder.shard(&10).write().insert(10, entry);
+        assert!(matches!(
+            holder.to_dense_matrix(&[1, 10], 0.0),
+            Err(EntryError::DimMismatch {
+                expected: 2,
+                actual: 8
+            })
+        ));
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_to_arrow() {
+        use arrow::array::{Array, FixedSizeListArray, Float32Array};
+        use arrow::datatypes::DataType;
+
+        let holder = PersiaEmbeddingHolder::new(100, 2);
+        (0..3u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![sign as f32; 4], &[9.0], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        let batch = holder.to_arrow().unwrap();
+        let schema = batch.schema();
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(schema.field(0).name(), "sign");
+        assert_eq!(schema.field(0).data_type(), &DataType::UInt64);
+        assert_eq!(schema.field(1).name(), "embedding");
+        assert!(matches!(
+            schema.field(1).data_type(),
+            DataType::FixedSizeList(item, 4) if item.data_type() == &DataType::Float32
+        ));
+
+        let signs = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        let embeddings = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        (0..batch.num_rows()).for_each(|row| {
+            let emb = embeddings.value(row);
+            let emb = emb.as_any().downcast_ref::<Float32Array>().unwrap();
+            assert_eq!(emb.len(), 4);
+            assert_eq!(emb.values(), &[signs.value(row) as f32; 4]);
+        });
+
+        let entry = HashMapEmbeddingEntry::from_emb(vec![0.0; 8], 10);
+        let _ = holder.shard(&10).write().insert(10, entry);
+        assert!(holder.to_arrow().is_err());
+    }
+}
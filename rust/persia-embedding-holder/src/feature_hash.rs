@@ -0,0 +1,250 @@
+//! Canonical hashing of raw features to signs, shared by trainers and servers so that both
+//! derive the same sign for a feature. The algorithms are implemented on explicit little endian
+//! reads and wrapping arithmetic, so their outputs do not depend on the platform and are pinned
+//! by the tests below. Changing an output invalidates every trained table using it.
+
+use std::convert::TryInto;
+
+/// Hash function used by [`FeatureHashAlgorithm::hash_feature`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureHashAlgorithm {
+    /// 64 bit xxHash (XXH64), seeded with the namespace.
+    XxHash64,
+    /// 64 bit FarmHash, seeded with the namespace.
+    FarmHash64,
+    /// First 64 bits of MurmurHash3 x64 128, seeded with the namespace.
+    Murmur3,
+}
+
+impl Default for FeatureHashAlgorithm {
+    fn default() -> Self {
+        FeatureHashAlgorithm::FarmHash64
+    }
+}
+
+impl FeatureHashAlgorithm {
+    /// Sign of `feature` in `namespace`, e.g. the slot of the feature, reduced to
+    /// `[0, table_capacity)`.
+    pub fn hash_feature(&self, namespace: u32, feature: &[u8], table_capacity: u64) -> u64 {
+        assert!(table_capacity > 0, "table capacity must be positive");
+        let hash = match self {
+            FeatureHashAlgorithm::XxHash64 => xxhash64(feature, namespace as u64),
+            FeatureHashAlgorithm::FarmHash64 => {
+                farmhash::hash64_with_seed(feature, namespace as u64)
+            }
+            FeatureHashAlgorithm::Murmur3 => murmur3_x64_128(feature, namespace).0,
+        };
+        hash % table_capacity
+    }
+}
+
+/// [`FeatureHashAlgorithm::hash_feature`] with the default algorithm.
+pub fn hash_feature(namespace: u32, feature: &[u8], table_capacity: u64) -> u64 {
+    FeatureHashAlgorithm::default().hash_feature(namespace, feature, table_capacity)
+}
+
+#[inline]
+fn read_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data[..8].try_into().unwrap())
+}
+
+#[inline]
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+const XXH_PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const XXH_PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const XXH_PRIME64_3: u64 = 0x165667B19E3779F9;
+const XXH_PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const XXH_PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+#[inline]
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XXH_PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(XXH_PRIME64_1)
+}
+
+#[inline]
+fn xxh64_merge_round(acc: u64, value: u64) -> u64 {
+    (acc ^ xxh64_round(0, value))
+        .wrapping_mul(XXH_PRIME64_1)
+        .wrapping_add(XXH_PRIME64_4)
+}
+
+pub fn xxhash64(data: &[u8], seed: u64) -> u64 {
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut v = [
+            seed.wrapping_add(XXH_PRIME64_1).wrapping_add(XXH_PRIME64_2),
+            seed.wrapping_add(XXH_PRIME64_2),
+            seed,
+            seed.wrapping_sub(XXH_PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            v.iter_mut()
+                .zip(rest.chunks(8))
+                .for_each(|(acc, lane)| *acc = xxh64_round(*acc, read_u64(lane)));
+            rest = &rest[32..];
+        }
+        let hash = v[0]
+            .rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18));
+        v.iter().fold(hash, |hash, x| xxh64_merge_round(hash, *x))
+    } else {
+        seed.wrapping_add(XXH_PRIME64_5)
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= xxh64_round(0, read_u64(rest));
+        hash = hash
+            .rotate_left(27)
+            .wrapping_mul(XXH_PRIME64_1)
+            .wrapping_add(XXH_PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash ^= (read_u32(rest) as u64).wrapping_mul(XXH_PRIME64_1);
+        hash = hash
+            .rotate_left(23)
+            .wrapping_mul(XXH_PRIME64_2)
+            .wrapping_add(XXH_PRIME64_3);
+        rest = &rest[4..];
+    }
+    rest.iter().for_each(|x| {
+        hash ^= (*x as u64).wrapping_mul(XXH_PRIME64_5);
+        hash = hash.rotate_left(11).wrapping_mul(XXH_PRIME64_1);
+    });
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(XXH_PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(XXH_PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+const MURMUR3_C1: u64 = 0x87c37b91114253d5;
+const MURMUR3_C2: u64 = 0x4cf5ad432745937f;
+
+#[inline]
+fn murmur3_fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51afd7ed558ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ceb9fe1a85ec53);
+    k ^ (k >> 33)
+}
+
+#[inline]
+fn murmur3_mix_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(MURMUR3_C1)
+        .rotate_left(31)
+        .wrapping_mul(MURMUR3_C2)
+}
+
+#[inline]
+fn murmur3_mix_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(MURMUR3_C2)
+        .rotate_left(33)
+        .wrapping_mul(MURMUR3_C1)
+}
+
+/// MurmurHash3 x64 128, returns the two 64 bit halves `(h1, h2)`.
+pub fn murmur3_x64_128(data: &[u8], seed: u32) -> (u64, u64) {
+    let mut h1 = seed as u64;
+    let mut h2 = seed as u64;
+    let mut blocks = data.chunks_exact(16);
+    blocks.by_ref().for_each(|block| {
+        h1 ^= murmur3_mix_k1(read_u64(block));
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dce729);
+        h2 ^= murmur3_mix_k2(read_u64(&block[8..]));
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x38495ab5);
+    });
+
+    let tail = blocks.remainder();
+    let fold = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .rev()
+            .fold(0u64, |acc, x| (acc << 8) | *x as u64)
+    };
+    if tail.len() > 8 {
+        h2 ^= murmur3_mix_k2(fold(&tail[8..]));
+    }
+    if !tail.is_empty() {
+        h1 ^= murmur3_mix_k1(fold(&tail[..tail.len().min(8)]));
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = murmur3_fmix64(h1);
+    h2 = murmur3_fmix64(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    (h1, h2)
+}
+
+#[cfg(test)]
+mod feature_hash_tests {
+    use super::*;
+
+    // Verification code of SMHasher: hash the keys [0], [0, 1], ... [0..255] with seeds
+    // 256 - len, then hash the concatenated results with seed 0.
+    fn smhasher_verification<F: Fn(&[u8], u32) -> Vec<u8>>(hash: F) -> u32 {
+        let key: Vec<u8> = (0..=255).collect();
+        let hashes: Vec<u8> = (0..256)
+            .flat_map(|len| hash(&key[..len], 256 - len as u32))
+            .collect();
+        read_u32(&hash(&hashes, 0))
+    }
+
+    #[test]
+    fn test_reference_implementations() {
+        assert_eq!(xxhash64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(xxhash64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(
+            smhasher_verification(|key, seed| xxhash64(key, seed as u64).to_le_bytes().to_vec()),
+            0x024b7cf4
+        );
+        assert_eq!(
+            smhasher_verification(|key, seed| {
+                let (h1, h2) = murmur3_x64_128(key, seed);
+                [h1.to_le_bytes(), h2.to_le_bytes()].concat()
+            }),
+            0x6384ba69
+        );
+    }
+
+    #[test]
+    fn test_hash_feature_golden_values() {
+        let feature = b"user_id=42";
+        let golden = [
+            (FeatureHashAlgorithm::XxHash64, [167508, 941515]),
+            (FeatureHashAlgorithm::FarmHash64, [651456, 500676]),
+            (FeatureHashAlgorithm::Murmur3, [133709, 28147]),
+        ];
+        golden.iter().for_each(|(algorithm, expected)| {
+            assert_eq!(algorithm.hash_feature(0, feature, 1 << 20), expected[0]);
+            assert_eq!(algorithm.hash_feature(7, feature, 1 << 20), expected[1]);
+        });
+        assert_eq!(hash_feature(7, feature, 1 << 20), 500676);
+        assert_eq!(
+            FeatureHashAlgorithm::XxHash64.hash_feature(0, feature, u64::MAX),
+            0xae4d9ee075228e54
+        );
+    }
+}
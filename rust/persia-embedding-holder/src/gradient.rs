@@ -0,0 +1,141 @@
+use persia_libs::rayon::prelude::*;
+
+use crate::map_backend::HolderMapBackend;
+use crate::optim_state::{OptimizerKind, OptimizerStateError};
+use crate::PersiaEmbeddingHolder;
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Applies the gradients of a batch with `optimizer`, where `grads` holds the gradient of
+    /// `signs[i]` at `grads[i * dim..(i + 1) * dim]`. Shards are updated in parallel, signs
+    /// occurring more than once are updated once per occurrence in batch order, see
+    /// [`PersiaEmbeddingHolder::merge_gradients`] to sum them instead. Missing, tombstoned and
+    /// frozen signs are skipped. Returns the number of applied gradients.
+    ///
+    /// The entries of a shard are checked against `dim` and the optimizer region before any of
+    /// them is updated, so a shard with a mismatching entry is left untouched, while other
+    /// shards of the batch may already be updated when the error is returned.
+    pub fn apply_gradients(
+        &self,
+        signs: &[u64],
+        grads: &[f32],
+        dim: usize,
+        optimizer: &OptimizerKind,
+        lr: f32,
+    ) -> Result<usize, OptimizerStateError> {
+        if grads.len() != signs.len() * dim {
+            return Err(OptimizerStateError::GradientLengthMismatch {
+                expected: signs.len() * dim,
+                actual: grads.len(),
+            });
+        }
+        let groups = self.group_by_shard(signs);
+        let applied: Vec<Result<usize, OptimizerStateError>> = groups
+            .par_iter()
+            .enumerate()
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(shard_idx, group)| {
+                let mut shard = self.get_shard_by_index(shard_idx).write();
+                for idx in group.iter() {
+                    if let Some(entry) = shard.get(&signs[*idx]) {
+                        if entry.embedding_dim() != dim {
+                            return Err(OptimizerStateError::GradientLengthMismatch {
+                                expected: entry.embedding_dim(),
+                                actual: dim,
+                            });
+                        }
+                        optimizer.check_space(dim, entry.opt().len())?;
+                    }
+                }
+
+                let mut applied = 0;
+                for idx in group.iter() {
+                    let sign = signs[*idx];
+                    let skipped = shard
+                        .get(&sign)
+                        .map_or(true, |x| x.is_tombstoned() || x.is_frozen());
+                    if skipped {
+                        continue;
+                    }
+                    let entry = shard.get_mut(&sign).unwrap();
+                    optimizer.apply(entry, &grads[idx * dim..(idx + 1) * dim], lr)?;
+                    applied += 1;
+                }
+                Ok(applied)
+            })
+            .collect();
+        applied.into_iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod gradient_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    #[test]
+    fn test_apply_gradients() {
+        let dim = 3;
+        let optimizers = [
+            OptimizerKind::Sgd,
+            OptimizerKind::MomentumSgd,
+            OptimizerKind::Adagrad,
+            OptimizerKind::Adam,
+            OptimizerKind::RmsProp,
+        ];
+        optimizers.iter().for_each(|optimizer| {
+            let holder = PersiaEmbeddingHolder::new(100, 4);
+            let opt_space = optimizer.opt_space(dim);
+            (0..8u64).for_each(|sign| {
+                let emb = (0..dim).map(|x| (sign + x as u64) as f32 * 0.1).collect();
+                let entry =
+                    HashMapEmbeddingEntry::from_emb_and_opt(emb, &vec![0.0; opt_space], sign);
+                let _ = holder.shard(&sign).write().insert(sign, entry);
+            });
+            let mut expected: Vec<_> = (0..8u64).map(|x| holder.get_entry(x).unwrap()).collect();
+
+            // sign 5 occurs twice and sign 42 is missing
+            let signs = [5u64, 1, 42, 6, 5, 2];
+            let grads: Vec<f32> = (0..signs.len() * dim)
+                .map(|x| (x as f32 - 8.0) * 0.25)
+                .collect();
+            let applied = holder
+                .apply_gradients(&signs, &grads, dim, optimizer, 0.05)
+                .unwrap();
+            assert_eq!(applied, 5);
+
+            signs
+                .iter()
+                .zip(grads.chunks(dim))
+                .filter(|(sign, _)| **sign < 8)
+                .for_each(|(sign, grad)| {
+                    optimizer
+                        .apply(&mut expected[*sign as usize], grad, 0.05)
+                        .unwrap();
+                });
+            expected.iter().for_each(|entry| {
+                let actual = holder.get_entry(entry.sign()).unwrap();
+                assert_eq!(actual.emb(), entry.emb(), "{:?}", optimizer);
+                assert_eq!(actual.opt(), entry.opt(), "{:?}", optimizer);
+            });
+        });
+
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        let entry = HashMapEmbeddingEntry::from_emb(vec![1.0; 4], 1);
+        let _ = holder.shard(&1).write().insert(1, entry);
+        assert!(matches!(
+            holder.apply_gradients(&[1], &[0.5; 2], 3, &OptimizerKind::Sgd, 0.1),
+            Err(OptimizerStateError::GradientLengthMismatch {
+                expected: 3,
+                actual: 2
+            })
+        ));
+        assert!(matches!(
+            holder.apply_gradients(&[1], &[0.5; 2], 2, &OptimizerKind::Sgd, 0.1),
+            Err(OptimizerStateError::GradientLengthMismatch {
+                expected: 4,
+                actual: 2
+            })
+        ));
+        assert_eq!(holder.get_entry(1).unwrap().emb(), &[1.0; 4]);
+    }
+}
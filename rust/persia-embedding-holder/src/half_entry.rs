@@ -0,0 +1,189 @@
+use persia_libs::{
+    half::{bf16, f16, prelude::*},
+    serde::{self, Deserialize, Serialize},
+};
+
+use persia_speedy::{Context, Readable, Writable};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::eviction_map::EvictionMapValue;
+
+/// Embedding entry stored in half precision for tables that tolerate the precision loss. Only
+/// the embedding is kept, the optimizer state is dropped on conversion.
+#[derive(Serialize, Deserialize, Readable, Writable, Clone, Debug)]
+#[serde(crate = "self::serde")]
+pub struct F16EmbeddingEntry {
+    inner: Vec<f16>,
+    sign: u64,
+}
+
+impl F16EmbeddingEntry {
+    pub fn from_emb(emb: &[f32], sign: u64) -> Self {
+        Self {
+            inner: Vec::from_f32_slice(emb),
+            sign,
+        }
+    }
+
+    pub fn from_entry(entry: &HashMapEmbeddingEntry) -> Self {
+        Self::from_emb(entry.emb(), entry.sign())
+    }
+
+    pub fn dim(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn sign(&self) -> u64 {
+        self.sign
+    }
+
+    pub fn emb(&self) -> &[f16] {
+        self.inner.as_slice()
+    }
+
+    /// Upcast the stored embedding to f32.
+    pub fn get_f32_vec(&self) -> Vec<f32> {
+        self.inner.to_f32_vec()
+    }
+}
+
+impl EvictionMapValue<u64> for F16EmbeddingEntry {
+    fn hashmap_key(&self) -> u64 {
+        self.sign
+    }
+}
+
+/// Embedding entry stored in bfloat16, which keeps the dynamic range of f32 at a lower
+/// precision than f16. Suited for cold tables while hot tables stay in f32.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "self::serde")]
+pub struct Bf16EmbeddingEntry {
+    inner: Vec<bf16>,
+    sign: u64,
+}
+
+impl Bf16EmbeddingEntry {
+    pub fn from_emb(emb: &[f32], sign: u64) -> Self {
+        Self {
+            inner: Vec::from_f32_slice(emb),
+            sign,
+        }
+    }
+
+    pub fn from_entry(entry: &HashMapEmbeddingEntry) -> Self {
+        Self::from_emb(entry.emb(), entry.sign())
+    }
+
+    pub fn dim(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn sign(&self) -> u64 {
+        self.sign
+    }
+
+    pub fn emb(&self) -> &[bf16] {
+        self.inner.as_slice()
+    }
+
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        self.inner.to_f32_vec()
+    }
+}
+
+impl EvictionMapValue<u64> for Bf16EmbeddingEntry {
+    fn hashmap_key(&self) -> u64 {
+        self.sign
+    }
+}
+
+impl<'a, C> Readable<'a, C> for Bf16EmbeddingEntry
+where
+    C: Context,
+{
+    #[inline]
+    fn read_from<R: persia_speedy::Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        let sign: u64 = reader.read_value()?;
+        let bits: Vec<u16> = reader.read_value()?;
+        let inner = bits.into_iter().map(bf16::from_bits).collect();
+
+        Ok(Self { inner, sign })
+    }
+
+    #[inline]
+    fn minimum_bytes_needed() -> usize {
+        {
+            let mut out = 0;
+            out += <u64 as persia_speedy::Readable<'a, C>>::minimum_bytes_needed();
+            out += <Vec<u16> as persia_speedy::Readable<'a, C>>::minimum_bytes_needed();
+            out
+        }
+    }
+}
+
+impl<C> Writable<C> for Bf16EmbeddingEntry
+where
+    C: Context,
+{
+    #[inline]
+    fn write_to<W: ?Sized + persia_speedy::Writer<C>>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), C::Error> {
+        let bits: Vec<u16> = self.inner.iter().map(|x| x.to_bits()).collect();
+
+        writer.write_value(&self.sign)?;
+        writer.write_value(&bits)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod half_entry_tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use persia_embedding_config::InitializationMethod;
+
+    #[test]
+    fn test_f16_round_trip() {
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 64, 64, 17, 17);
+        let f16_entry = entry.to_f16();
+
+        assert_eq!(f16_entry.dim(), entry.dim());
+        assert_eq!(f16_entry.sign(), entry.sign());
+
+        let restored = f16_entry.get_f32_vec();
+        assert_eq!(restored.len(), entry.dim());
+        // f16 carries a 10 bit mantissa, relative error is bounded by 2^-11
+        restored.iter().zip(entry.emb()).for_each(|(x, y)| {
+            assert!((x - y).abs() <= y.abs() * 2f32.powi(-11) + 1e-7);
+        });
+
+        let exact = F16EmbeddingEntry::from_emb(&[0.5, -0.25, 1.0], 0);
+        assert_eq!(exact.get_f32_vec(), vec![0.5, -0.25, 1.0]);
+    }
+
+    #[test]
+    fn test_bf16_round_trip() {
+        let initialization = InitializationMethod::default();
+        let entry = HashMapEmbeddingEntry::new(&initialization, 64, 64, 19, 19);
+        let bf16_entry = entry.to_bf16();
+
+        assert_eq!(bf16_entry.dim(), entry.dim());
+        assert_eq!(bf16_entry.sign(), entry.sign());
+        // bf16 carries a 7 bit mantissa, relative error is bounded by 2^-8
+        bf16_entry
+            .to_f32_vec()
+            .iter()
+            .zip(entry.emb())
+            .for_each(|(x, y)| assert!((x - y).abs() <= y.abs() * 2f32.powi(-8)));
+
+        let bytes = bf16_entry.write_to_vec().unwrap();
+        assert_eq!(bytes.len(), 8 + 4 + 2 * entry.dim());
+        let decoded = Bf16EmbeddingEntry::read_from_buffer(&bytes).unwrap();
+        assert_eq!(decoded.sign(), bf16_entry.sign());
+        assert_eq!(decoded.to_f32_vec(), bf16_entry.to_f32_vec());
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use persia_libs::parking_lot::Mutex;
+
+use crate::eviction_map::{EvictionMap, EvictionMapValue};
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+/// Copy of the embedding of a hot sign.
+struct HotRow {
+    sign: u64,
+    emb: Vec<f32>,
+}
+
+impl EvictionMapValue<u64> for HotRow {
+    fn hashmap_key(&self) -> u64 {
+        self.sign
+    }
+}
+
+/// Small lru cache of the embeddings of the most recently read signs, guarded by one lock of
+/// its own so that hits do not touch the shards. Rows are invalidated by the mutation hooks of
+/// the shards. Misses fill the cache while holding the read lock of the shard, so a row can not
+/// be filled from a value that a concurrent update already invalidated.
+pub(crate) struct HotCache {
+    rows: Mutex<EvictionMap<u64, HotRow>>,
+    hits: AtomicU64,
+}
+
+impl HotCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            rows: Mutex::new(EvictionMap::with_capacity(capacity)),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, sign: u64) -> Option<Vec<f32>> {
+        let emb = self.rows.lock().get_refresh(&sign).map(|x| x.emb.clone());
+        if emb.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        emb
+    }
+
+    fn invalidate(&self, sign: &u64) {
+        let mut rows = self.rows.lock();
+        if rows.hashmap.contains_key(sign) {
+            rows.retain(|x| x.sign != *sign);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.rows.lock().clear();
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Serves [`PersiaEmbeddingHolder::get_hot`] from an lru cache of the embeddings of up to
+    /// `capacity` signs, for skewed lookups on serving. A cached row is dropped as soon as its
+    /// entry is handed out mutably, replaced, evicted or removed, and the cache is cleared by
+    /// [`PersiaEmbeddingHolder::clear`] and [`PersiaEmbeddingHolder::write_all`].
+    pub fn with_hot_cache(mut self, capacity: usize) -> Self {
+        let cache = Arc::new(HotCache::new(capacity));
+        self.inner.inner.iter().for_each(|x| {
+            let cache = cache.clone();
+            x.write().on_mutation = Some(Arc::new(move |sign: &u64| cache.invalidate(sign)));
+        });
+        self.hot_cache = Some(cache);
+        self
+    }
+
+    /// Copy of the embedding of `sign`, served from the hot cache when the sign is cached and
+    /// cached otherwise. Without a hot cache this is a lookup in the shard.
+    pub fn get_hot(&self, sign: u64) -> Option<Vec<f32>> {
+        let cache = match &self.hot_cache {
+            Some(cache) => cache,
+            None => return self.get_entry(sign).map(|x| x.emb().to_vec()),
+        };
+        if let Some(emb) = cache.get(sign) {
+            return Some(emb);
+        }
+        let shard = self.shard(&sign).read();
+        let emb = shard
+            .get(&sign)
+            .filter(|x| !x.is_tombstoned())?
+            .emb()
+            .to_vec();
+        let _ = cache.rows.lock().insert(
+            sign,
+            HotRow {
+                sign,
+                emb: emb.clone(),
+            },
+        );
+        Some(emb)
+    }
+
+    /// Number of [`PersiaEmbeddingHolder::get_hot`] lookups served from the hot cache.
+    pub fn hot_cache_hits(&self) -> u64 {
+        self.hot_cache
+            .as_ref()
+            .map(|x| x.hits.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod hot_cache_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    #[test]
+    fn test_hot_cache() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4).with_hot_cache(2);
+        (0..4u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb(vec![sign as f32; 2], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        assert_eq!(holder.get_hot(1), Some(vec![1.0, 1.0]));
+        assert_eq!(holder.hot_cache_hits(), 0);
+        assert_eq!(holder.get_hot(1), Some(vec![1.0, 1.0]));
+        assert_eq!(holder.hot_cache_hits(), 1);
+        assert_eq!(holder.get_hot(9), None);
+
+        // an update through the shard drops the cached row
+        holder.shard(&1).write().get_mut(&1).unwrap().emb_mut()[0] = -1.0;
+        assert_eq!(holder.get_hot(1), Some(vec![-1.0, 1.0]));
+        assert_eq!(holder.hot_cache_hits(), 1);
+        assert_eq!(holder.get_hot(1), Some(vec![-1.0, 1.0]));
+        assert_eq!(holder.hot_cache_hits(), 2);
+
+        // replacing the entry and tombstoning it invalidate too
+        let entry = HashMapEmbeddingEntry::from_emb(vec![5.0; 2], 1);
+        let _ = holder.shard(&1).write().insert(1, entry);
+        assert_eq!(holder.get_hot(1), Some(vec![5.0, 5.0]));
+        assert!(holder.tombstone(1));
+        assert_eq!(holder.get_hot(1), None);
+
+        // the cache keeps the most recently used signs only
+        holder.get_hot(2);
+        holder.get_hot(3);
+        holder.get_hot(0);
+        let hits = holder.hot_cache_hits();
+        holder.get_hot(2);
+        assert_eq!(holder.hot_cache_hits(), hits);
+
+        holder.write_all().iter_mut().for_each(|x| x.emb[1] = 7.0);
+        assert_eq!(holder.get_hot(0), Some(vec![0.0, 7.0]));
+    }
+}
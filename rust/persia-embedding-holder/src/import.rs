@@ -0,0 +1,100 @@
+use std::io::{self, BufRead};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+
+fn invalid_data(line: usize, msg: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, msg),
+    )
+}
+
+/// Parses pretrained embeddings in the word2vec text format, e.g. the `.vec` files of gensim
+/// and fastText, to warm start a table. The header line holds the number of rows and the dim,
+/// followed by one `token v1 .. v_dim` line per row. `sign_of` maps every token to the sign of
+/// its entry. Entries have no optimizer space. Fails if a row does not have the dim declared
+/// in the header or the number of rows does not match the header.
+pub fn import_word2vec<R: BufRead>(
+    r: R,
+    sign_of: impl Fn(&str) -> u64,
+) -> io::Result<Vec<HashMapEmbeddingEntry>> {
+    let mut lines = r.lines();
+    let header = lines
+        .next()
+        .ok_or_else(|| invalid_data(1, String::from("missing header")))??;
+    let header: Vec<usize> = header
+        .split_whitespace()
+        .map(|x| x.parse::<usize>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| invalid_data(1, format!("invalid header: {}", e)))?;
+    let (count, dim) = match header.as_slice() {
+        [count, dim] => (*count, *dim),
+        _ => {
+            return Err(invalid_data(
+                1,
+                String::from("header must hold the row count and dim"),
+            ))
+        }
+    };
+
+    let mut entries = Vec::with_capacity(count);
+    for (idx, line) in lines.enumerate() {
+        let line = line?;
+        let line_number = idx + 2;
+        let mut fields = line.split_whitespace();
+        let token = match fields.next() {
+            Some(token) => token,
+            None => continue,
+        };
+        let emb: Vec<f32> = fields
+            .map(|x| x.parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid_data(line_number, format!("invalid value: {}", e)))?;
+        if emb.len() != dim {
+            return Err(invalid_data(
+                line_number,
+                format!("token {} has dim {}, expected {}", token, emb.len(), dim),
+            ));
+        }
+        entries.push(HashMapEmbeddingEntry::from_emb(emb, sign_of(token)));
+    }
+    if entries.len() != count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("found {} rows, header declares {}", entries.len(), count),
+        ));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod import_tests {
+    use super::*;
+    use crate::feature_hash::hash_feature;
+
+    #[test]
+    fn test_import_word2vec() {
+        let blob = "3 4\nthe 0.1 0.2 0.3 0.4\nof -1 0 1 2.5\n\u{00e9}t\u{00e9} 1e-3 0 0 -0.5\n";
+        let sign_of = |token: &str| hash_feature(0, token.as_bytes(), 1 << 20);
+        let entries = import_word2vec(blob.as_bytes(), sign_of).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].sign(), sign_of("the"));
+        assert_eq!(entries[0].emb(), &[0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(entries[1].emb(), &[-1.0, 0.0, 1.0, 2.5]);
+        assert_eq!(entries[2].sign(), sign_of("\u{00e9}t\u{00e9}"));
+        assert_eq!(entries[2].emb(), &[1e-3, 0.0, 0.0, -0.5]);
+        assert!(entries.iter().all(|x| x.opt().is_empty()));
+
+        let invalid = [
+            "",
+            "3\nthe 0.1\n",
+            "1 2\nthe 0.1\n",
+            "1 2\nthe 0.1 x\n",
+            "2 2\nthe 0.1 0.2\n",
+        ];
+        invalid.iter().for_each(|blob| {
+            let err = import_word2vec(blob.as_bytes(), sign_of).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+}
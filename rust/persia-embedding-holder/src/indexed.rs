@@ -0,0 +1,332 @@
+use std::convert::TryInto;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use persia_libs::thiserror;
+use persia_speedy::{Readable, Writable};
+
+use crate::iter::EmbeddingEntryRef;
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+const INDEXED_MAGIC: &[u8; 4] = b"PEIX";
+/// Version of the files written by [`write_indexed`].
+pub const INDEXED_FORMAT_VERSION: u32 = 1;
+// magic, version, embedding dim, stride, number of rows and index checksum. A multiple of 8, so
+// that the rows are aligned when the file is mapped.
+const INDEXED_HEADER_LEN: usize = 4 + 4 + 8 * 4;
+// sign and offset of the row
+const INDEXED_INDEX_ENTRY_LEN: usize = 16;
+
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum IndexedError {
+    #[error("not an indexed holder file")]
+    InvalidMagic,
+    #[error("unsupported indexed holder version {0}")]
+    UnsupportedVersion(u32),
+    #[error("indexed holder of {num_rows} rows of stride {stride} is truncated to {len} bytes")]
+    Truncated {
+        num_rows: u64,
+        stride: u64,
+        len: usize,
+    },
+    #[error("corrupted index: {0}")]
+    CorruptedIndex(String),
+    #[error("rows are not aligned to f32 or the host is not little endian")]
+    Misaligned,
+}
+
+#[inline]
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_header<W: Write>(
+    w: &mut W,
+    dim: usize,
+    stride: usize,
+    num_rows: usize,
+    checksum: u64,
+) -> io::Result<()> {
+    w.write_all(INDEXED_MAGIC)?;
+    w.write_all(&INDEXED_FORMAT_VERSION.to_le_bytes())?;
+    w.write_all(&(dim as u64).to_le_bytes())?;
+    w.write_all(&(stride as u64).to_le_bytes())?;
+    w.write_all(&(num_rows as u64).to_le_bytes())?;
+    w.write_all(&checksum.to_le_bytes())
+}
+
+/// Writes the entries of `holder` in a fixed layout for lookups without deserialization, e.g.
+/// from a memory mapping by [`crate::mmap_holder::MmapHolder`]: a header, an index of
+/// `(sign, offset)` pairs sorted by sign and the rows of `stride` little endian f32, each
+/// holding the embedding followed by the optimizer state. The offsets count f32 values from the
+/// start of the rows. Rows are streamed in shard order and the index is filled in afterwards.
+/// Fails if the entries do not share one embedding dim and optimizer space, returns the number
+/// of written rows otherwise.
+pub fn write_indexed<W: Write + Seek, M: HolderMapBackend>(
+    holder: &PersiaEmbeddingHolder<M>,
+    w: &mut W,
+) -> io::Result<usize> {
+    let shards: Vec<_> = holder.inner.inner.iter().map(|x| x.read()).collect();
+    let entries = || {
+        shards
+            .iter()
+            .flat_map(|x| x.linkedlist.iter())
+            .filter(|x| !x.is_tombstoned())
+    };
+    let (dim, stride) = entries()
+        .next()
+        .map(|x| (x.embedding_dim(), x.inner_size()))
+        .unwrap_or((0, 0));
+    if let Some(entry) = entries().find(|x| x.embedding_dim() != dim || x.inner_size() != stride) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "sign {} has embedding dim {} and length {}, expected {} and {}",
+                entry.sign(),
+                entry.embedding_dim(),
+                entry.inner_size(),
+                dim,
+                stride
+            ),
+        ));
+    }
+    let num_rows = entries().count();
+
+    let start = w.stream_position()?;
+    let rows_start = start + (INDEXED_HEADER_LEN + num_rows * INDEXED_INDEX_ENTRY_LEN) as u64;
+    w.seek(SeekFrom::Start(rows_start))?;
+    let mut index = Vec::with_capacity(num_rows);
+    for (row, entry) in entries().enumerate() {
+        index.push((entry.sign(), (row * stride) as u64));
+        let bytes: Vec<u8> = entry
+            .as_emb_entry_slice()
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        w.write_all(&bytes)?;
+    }
+    let end = w.stream_position()?;
+
+    index.sort_unstable();
+    let index: Vec<u8> = index
+        .iter()
+        .flat_map(|(sign, offset)| [sign.to_le_bytes(), offset.to_le_bytes()].concat())
+        .collect();
+    w.seek(SeekFrom::Start(start))?;
+    write_header(w, dim, stride, num_rows, farmhash::fingerprint64(&index))?;
+    w.write_all(&index)?;
+    w.seek(SeekFrom::Start(end))?;
+    Ok(num_rows)
+}
+
+/// Lookups of signs in the bytes of a file written by [`write_indexed`], by binary search over
+/// the index. Returned rows borrow from the bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct IndexedReader<'a> {
+    index: &'a [u8],
+    rows: &'a [f32],
+    embedding_dim: usize,
+    stride: usize,
+}
+
+impl<'a> IndexedReader<'a> {
+    /// Validates the header and the index of `bytes`. The rows are borrowed as f32 without
+    /// copying, which requires `bytes` to be 4 byte aligned, as mappings are, and a little
+    /// endian host.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, IndexedError> {
+        let reader = Self::new_unvalidated(bytes)?;
+        if farmhash::fingerprint64(reader.index) != read_u64(bytes, 32) {
+            return Err(IndexedError::CorruptedIndex(
+                "index checksum mismatch".to_string(),
+            ));
+        }
+        reader.validate_index()?;
+        Ok(reader)
+    }
+
+    /// Like [`IndexedReader::new`], but only checks the header and not the index, which takes
+    /// time linear in its size. Lookups in a corrupted index may return wrong rows or panic.
+    pub fn new_unvalidated(bytes: &'a [u8]) -> Result<Self, IndexedError> {
+        if bytes.len() < INDEXED_HEADER_LEN || &bytes[..4] != INDEXED_MAGIC {
+            return Err(IndexedError::InvalidMagic);
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version > INDEXED_FORMAT_VERSION {
+            return Err(IndexedError::UnsupportedVersion(version));
+        }
+        let embedding_dim = read_u64(bytes, 8);
+        let stride = read_u64(bytes, 16);
+        let num_rows = read_u64(bytes, 24);
+        if embedding_dim > stride {
+            return Err(IndexedError::CorruptedIndex(format!(
+                "embedding dim {} exceeds stride {}",
+                embedding_dim, stride
+            )));
+        }
+
+        let truncated = IndexedError::Truncated {
+            num_rows,
+            stride,
+            len: bytes.len(),
+        };
+        let expected_len = (stride as usize)
+            .checked_mul(4)
+            .and_then(|x| x.checked_add(INDEXED_INDEX_ENTRY_LEN))
+            .and_then(|x| x.checked_mul(num_rows as usize))
+            .and_then(|x| x.checked_add(INDEXED_HEADER_LEN));
+        match expected_len {
+            Some(len) if len <= bytes.len() => {}
+            _ => return Err(truncated),
+        }
+
+        let rows_start = INDEXED_HEADER_LEN + num_rows as usize * INDEXED_INDEX_ENTRY_LEN;
+        let index = &bytes[INDEXED_HEADER_LEN..rows_start];
+        let rows_bytes = &bytes[rows_start..rows_start + (num_rows * stride) as usize * 4];
+        let (prefix, rows, _) = unsafe { rows_bytes.align_to::<f32>() };
+        if !prefix.is_empty() || cfg!(target_endian = "big") {
+            return Err(IndexedError::Misaligned);
+        }
+
+        Ok(Self {
+            index,
+            rows,
+            embedding_dim: embedding_dim as usize,
+            stride: stride as usize,
+        })
+    }
+
+    fn validate_index(&self) -> Result<(), IndexedError> {
+        let mut last_sign = None;
+        for idx in 0..self.len() {
+            let (sign, offset) = self.index_entry(idx);
+            if last_sign.map_or(false, |x| x >= sign) {
+                return Err(IndexedError::CorruptedIndex(format!(
+                    "sign {} at position {} is out of order",
+                    sign, idx
+                )));
+            }
+            if offset as usize + self.stride > self.rows.len() {
+                return Err(IndexedError::CorruptedIndex(format!(
+                    "offset {} of sign {} is out of bounds",
+                    offset, sign
+                )));
+            }
+            last_sign = Some(sign);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn index_entry(&self, idx: usize) -> (u64, u64) {
+        let offset = idx * INDEXED_INDEX_ENTRY_LEN;
+        (
+            read_u64(self.index, offset),
+            read_u64(self.index, offset + 8),
+        )
+    }
+
+    pub fn embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    /// Number of f32 values of a row, the embedding dim plus the optimizer space.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len() / INDEXED_INDEX_ENTRY_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, sign: u64) -> Option<EmbeddingEntryRef<'a>> {
+        let (mut lower, mut upper) = (0, self.len());
+        while lower < upper {
+            let mid = lower + (upper - lower) / 2;
+            let (mid_sign, offset) = self.index_entry(mid);
+            if mid_sign < sign {
+                lower = mid + 1;
+            } else if mid_sign > sign {
+                upper = mid;
+            } else {
+                let row = &self.rows[offset as usize..offset as usize + self.stride];
+                let (emb, opt) = row.split_at(self.embedding_dim);
+                return Some(EmbeddingEntryRef { sign, emb, opt });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod indexed_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+    use std::io::Cursor;
+
+    fn write_holder(stride_opt: usize) -> Vec<u8> {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        (0..10u64).map(|x| x * 7).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(
+                vec![sign as f32; 3],
+                &vec![-(sign as f32); stride_opt],
+                sign,
+            );
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        let mut w = Cursor::new(Vec::new());
+        assert_eq!(write_indexed(&holder, &mut w).unwrap(), 10);
+        w.into_inner()
+    }
+
+    #[test]
+    fn test_indexed_lookup() {
+        [0, 1, 6].iter().for_each(|opt_space| {
+            let bytes = write_holder(*opt_space);
+            let reader = IndexedReader::new(&bytes).unwrap();
+            assert_eq!(reader.len(), 10);
+            assert_eq!(reader.embedding_dim(), 3);
+            assert_eq!(reader.stride(), 3 + opt_space);
+
+            (0..10u64).map(|x| x * 7).for_each(|sign| {
+                let entry = reader.get(sign).unwrap();
+                assert_eq!(entry.sign, sign);
+                assert_eq!(entry.emb, &[sign as f32; 3]);
+                assert_eq!(entry.opt, vec![-(sign as f32); *opt_space].as_slice());
+            });
+            [1u64, 6, 64, u64::MAX].iter().for_each(|sign| {
+                assert!(reader.get(*sign).is_none());
+            });
+        });
+    }
+
+    #[test]
+    fn test_indexed_errors() {
+        let bytes = write_holder(1);
+        assert!(matches!(
+            IndexedReader::new(&bytes[..bytes.len() - 4]),
+            Err(IndexedError::Truncated { .. })
+        ));
+        assert!(matches!(
+            IndexedReader::new(b"PEMM"),
+            Err(IndexedError::InvalidMagic)
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[INDEXED_HEADER_LEN + 3] ^= 0x10;
+        assert!(matches!(
+            IndexedReader::new(&corrupted),
+            Err(IndexedError::CorruptedIndex(_))
+        ));
+
+        let mut future = bytes;
+        future[4..8].copy_from_slice(&(INDEXED_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            IndexedReader::new(&future),
+            Err(IndexedError::UnsupportedVersion(_))
+        ));
+    }
+}
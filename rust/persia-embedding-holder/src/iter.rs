@@ -0,0 +1,152 @@
+use persia_libs::parking_lot::{RwLockReadGuard, RwLockWriteGuard};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::eviction_map::EvictionMap;
+use crate::map_backend::{DefaultMapBackend, HolderMapBackend};
+use crate::sharded::get_index;
+use crate::PersiaEmbeddingHolder;
+
+/// Borrowed view of an entry yielded by [`HolderReadGuard::iter`].
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddingEntryRef<'a> {
+    pub sign: u64,
+    pub emb: &'a [f32],
+    pub opt: &'a [f32],
+}
+
+impl<'a> From<&'a HashMapEmbeddingEntry> for EmbeddingEntryRef<'a> {
+    fn from(entry: &'a HashMapEmbeddingEntry) -> Self {
+        Self {
+            sign: entry.sign(),
+            emb: entry.emb(),
+            opt: entry.opt(),
+        }
+    }
+}
+
+/// Mutable view of an entry yielded by [`HolderWriteGuard::iter_mut`].
+#[derive(Debug)]
+pub struct EmbeddingEntryMut<'a> {
+    pub sign: u64,
+    pub emb: &'a mut [f32],
+    pub opt: &'a mut [f32],
+}
+
+/// Read locks of all shards of a holder, entries can be walked without copying them for as
+/// long as the guard is alive. Writers of the holder block until it is dropped.
+pub struct HolderReadGuard<'a, M: HolderMapBackend = DefaultMapBackend<u64>> {
+    shards: Vec<RwLockReadGuard<'a, EvictionMap<u64, HashMapEmbeddingEntry, M>>>,
+}
+
+impl<'a, M: HolderMapBackend> HolderReadGuard<'a, M> {
+    /// Lookup of one sign, tombstoned entries are skipped.
+    pub fn get(&self, sign: u64) -> Option<EmbeddingEntryRef<'_>> {
+        self.shards[get_index(&sign, self.shards.len())]
+            .get(&sign)
+            .filter(|x| !x.is_tombstoned())
+            .map(EmbeddingEntryRef::from)
+    }
+
+    /// Iterates over the entries shard by shard, tombstoned entries are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = EmbeddingEntryRef<'_>> {
+        self.shards
+            .iter()
+            .flat_map(|x| x.linkedlist.iter())
+            .filter(|x| !x.is_tombstoned())
+            .map(EmbeddingEntryRef::from)
+    }
+}
+
+/// Write locks of all shards of a holder, see [`HolderReadGuard`].
+pub struct HolderWriteGuard<'a, M: HolderMapBackend = DefaultMapBackend<u64>> {
+    shards: Vec<RwLockWriteGuard<'a, EvictionMap<u64, HashMapEmbeddingEntry, M>>>,
+}
+
+impl<'a, M: HolderMapBackend> HolderWriteGuard<'a, M> {
+    /// Iterates mutably over the entries shard by shard, tombstoned and frozen entries are
+    /// skipped. The visited entries are marked dirty.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = EmbeddingEntryMut<'_>> {
+        self.shards
+            .iter_mut()
+            .flat_map(|x| x.linkedlist.iter_mut())
+            .filter(|x| !x.is_tombstoned() && !x.is_frozen())
+            .map(|x| {
+                let sign = x.sign();
+                let (emb, opt) = x.emb_and_opt_mut();
+                EmbeddingEntryMut { sign, emb, opt }
+            })
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Read locks all shards for walking the entries without cloning them, e.g. for offline
+    /// analytics.
+    pub fn read_all(&self) -> HolderReadGuard<'_, M> {
+        HolderReadGuard {
+            shards: self.inner.inner.iter().map(|x| x.read()).collect(),
+        }
+    }
+
+    /// Write locks all shards for updating the entries in place. The hot cache is cleared once
+    /// all shards are locked, since updates through the guard bypass the mutation hooks.
+    pub fn write_all(&self) -> HolderWriteGuard<'_, M> {
+        let shards = self.inner.inner.iter().map(|x| x.write()).collect();
+        if let Some(cache) = &self.hot_cache {
+            cache.clear();
+        }
+        HolderWriteGuard { shards }
+    }
+}
+
+#[cfg(test)]
+mod iter_tests {
+    use super::*;
+
+    #[test]
+    fn test_iter() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let mut reference = vec![0.0; 2];
+        (0..20u64).for_each(|sign| {
+            let emb = vec![sign as f32, 1.0];
+            reference
+                .iter_mut()
+                .zip(emb.iter())
+                .for_each(|(x, y)| *x += y);
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(emb, &[0.5], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        let sum = holder
+            .read_all()
+            .iter()
+            .fold(vec![0.0; 2], |mut sum, entry| {
+                assert_eq!(entry.opt, &[0.5]);
+                sum.iter_mut()
+                    .zip(entry.emb.iter())
+                    .for_each(|(x, y)| *x += y);
+                sum
+            });
+        assert_eq!(sum, reference);
+        let guard = holder.read_all();
+        assert_eq!(guard.iter().count(), 20);
+        assert_eq!(guard.get(3).unwrap().emb, &[3.0, 1.0]);
+        assert!(guard.get(20).is_none());
+        drop(guard);
+
+        holder.write_all().iter_mut().for_each(|entry| {
+            entry.emb[1] = entry.sign as f32;
+            entry.opt[0] = 0.0;
+        });
+        let entry = holder.get_entry(7).unwrap();
+        assert_eq!(entry.emb(), &[7.0, 7.0]);
+        assert_eq!(entry.opt(), &[0.0]);
+
+        holder.shard(&5).write().get_mut(&5).unwrap().freeze();
+        let mut guard = holder.write_all();
+        assert_eq!(guard.iter_mut().count(), 19);
+        guard.iter_mut().for_each(|entry| entry.emb[0] = -1.0);
+        drop(guard);
+        assert_eq!(holder.get_entry(5).unwrap().emb(), &[5.0, 5.0]);
+        assert_eq!(holder.get_entry(7).unwrap().emb(), &[-1.0, 7.0]);
+    }
+}
@@ -1,62 +1,197 @@
+pub mod admission;
+pub mod aligned;
+pub mod arena;
 pub mod array_linked_list;
+pub mod atomic_entry;
+pub mod bloom;
+pub mod checkpoint;
+pub mod collision;
+pub mod dedup;
+pub mod dense;
+pub mod diff;
 pub mod emb_entry;
+pub mod entry_pool;
 pub mod eviction_map;
+pub mod export;
+pub mod feature_hash;
+pub mod gradient;
+pub mod half_entry;
+pub mod hot_cache;
+pub mod import;
+pub mod indexed;
+pub mod iter;
+pub mod map_backend;
+pub mod mmap_holder;
+pub mod nearest;
+pub mod optim_state;
+pub mod proto;
+pub mod prune;
+pub mod ragged_holder;
 pub mod sharded;
+pub mod slab_holder;
+pub mod stats;
+pub mod update_log;
 
+use std::borrow::Cow;
 use std::sync::Arc;
 
-use persia_libs::{once_cell, parking_lot::RwLock, thiserror};
+use persia_libs::{
+    hashbrown::HashMap, once_cell, parking_lot::RwLock, rayon::prelude::*, thiserror,
+};
 
-use emb_entry::HashMapEmbeddingEntry;
+use collision::CollisionGuard;
+use emb_entry::{EntryError, HashMapEmbeddingEntry, InitError};
 use eviction_map::EvictionMap;
-use persia_embedding_config::{EmbeddingParameterServerConfig, PersiaGlobalConfigError};
+use hot_cache::HotCache;
+use map_backend::{DefaultMapBackend, HolderMapBackend};
+use persia_embedding_config::{
+    EmbeddingParameterServerConfig, InitializationMethod, PersiaGlobalConfigError,
+};
 use persia_speedy::{Readable, Writable};
-use sharded::Sharded;
+use sharded::{get_index, Sharded};
+use stats::HolderCounters;
 
 #[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
 pub enum PersiaEmbeddingHolderError {
     #[error("global config error: {0}")]
     PersiaGlobalConfigError(#[from] PersiaGlobalConfigError),
-    #[error("id not fonud")]
-    IdNotFound,
+    #[error("sign {0} not found")]
+    SignNotFound(u64),
+    #[error("sign {0} collides with another feature")]
+    SignCollision(u64),
+    #[error("sign {sign} has embedding dim {actual}, expected {expected}")]
+    DimMismatch {
+        sign: u64,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("input of dim {actual} does not match embedding dim {expected}")]
+    LengthMismatch { expected: usize, actual: usize },
+    #[error("unsupported initialization: {0}")]
+    UnsupportedInit(String),
+    #[error("corrupt embedding entry: {0}")]
+    CorruptEntry(String),
+    #[error("entry of {len} values exceeds max length {max_len}")]
+    EntryTooLarge { len: usize, max_len: usize },
+    #[error("embedding entry of sign {0} is frozen")]
+    EntryFrozen(u64),
+}
+
+impl From<InitError> for PersiaEmbeddingHolderError {
+    fn from(e: InitError) -> Self {
+        match e {
+            InitError::UnsupportedMethod(_) | InitError::InvalidParameter { .. } => {
+                PersiaEmbeddingHolderError::UnsupportedInit(e.to_string())
+            }
+            InitError::InvalidLength { expected, actual } => {
+                PersiaEmbeddingHolderError::LengthMismatch { expected, actual }
+            }
+            InitError::EntryTooLarge {
+                dim,
+                require_space,
+                max_len,
+            } => PersiaEmbeddingHolderError::EntryTooLarge {
+                len: dim.saturating_add(require_space),
+                max_len,
+            },
+        }
+    }
+}
+
+impl From<EntryError> for PersiaEmbeddingHolderError {
+    fn from(e: EntryError) -> Self {
+        match e {
+            EntryError::DimMismatch { expected, actual } => {
+                PersiaEmbeddingHolderError::LengthMismatch { expected, actual }
+            }
+            EntryError::InvalidDimension { dim, max_dim } => {
+                PersiaEmbeddingHolderError::EntryTooLarge {
+                    len: dim,
+                    max_len: max_dim,
+                }
+            }
+            EntryError::InvalidLength { .. }
+            | EntryError::UnsupportedVersion { .. }
+            | EntryError::CorruptEntry { .. } => {
+                PersiaEmbeddingHolderError::CorruptEntry(e.to_string())
+            }
+        }
+    }
 }
 
 static PERSIA_EMBEDDING_HOLDER: once_cell::sync::OnceCell<PersiaEmbeddingHolder> =
     once_cell::sync::OnceCell::new();
 
-#[derive(Clone)]
-pub struct PersiaEmbeddingHolder {
-    inner: Arc<Sharded<EvictionMap<u64, HashMapEmbeddingEntry>, u64>>,
+/// Sharded store of the embedding entries. The shards index their entries with the map backend
+/// `M`, see [`map_backend`].
+pub struct PersiaEmbeddingHolder<M: HolderMapBackend = DefaultMapBackend<u64>> {
+    inner: Arc<Sharded<EvictionMap<u64, HashMapEmbeddingEntry, M>, u64>>,
+    counters: Arc<HolderCounters>,
+    collision_guard: Option<Arc<CollisionGuard>>,
+    hot_cache: Option<Arc<HotCache>>,
+}
+
+// Not derived, since that would require `M: Clone` although only the `Arc`s are cloned.
+impl<M: HolderMapBackend> Clone for PersiaEmbeddingHolder<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            counters: self.counters.clone(),
+            collision_guard: self.collision_guard.clone(),
+            hot_cache: self.hot_cache.clone(),
+        }
+    }
 }
 
 impl PersiaEmbeddingHolder {
     pub fn get() -> Result<PersiaEmbeddingHolder, PersiaEmbeddingHolderError> {
         let singleton = PERSIA_EMBEDDING_HOLDER.get_or_try_init(|| {
             let config = EmbeddingParameterServerConfig::get()?;
+            emb_entry::set_max_entry_len(config.max_entry_len);
+            emb_entry::set_max_embedding_dim(config.max_embedding_dim);
 
             let bucket_size = config.num_hashmap_internal_shards;
             let cpapacity_per_bucket = config.capacity / bucket_size;
+            let admission_threshold = config.admission_threshold;
+            let admission_sketch_width = config.admission_sketch_width;
+            let admission_sketch_depth = config.admission_sketch_depth;
+            let enable_bloom_filter = config.enable_bloom_filter;
+            let bloom_filter_false_positive_rate = config.bloom_filter_false_positive_rate;
 
             let handles: Vec<std::thread::JoinHandle<_>> = (0..bucket_size)
                 .map(|_| {
                     std::thread::spawn(move || {
-                        EvictionMap::with_capacity(cpapacity_per_bucket as usize)
+                        let mut map = EvictionMap::with_capacity(cpapacity_per_bucket as usize);
+                        if enable_bloom_filter {
+                            map.enable_bloom_filter(
+                                cpapacity_per_bucket,
+                                bloom_filter_false_positive_rate,
+                            );
+                        }
+                        if admission_threshold > 0 {
+                            map.with_admission(
+                                admission_threshold,
+                                admission_sketch_width,
+                                admission_sketch_depth,
+                            )
+                        } else {
+                            map
+                        }
                     })
                 })
                 .collect();
 
             let maps: Vec<_> = handles
                 .into_iter()
-                .map(|h| RwLock::new(h.join().expect("failed to create map")))
+                .map(|h| h.join().expect("failed to create map"))
                 .collect();
 
-            let sharded = Sharded {
-                inner: maps,
-                phantom: std::marker::PhantomData::default(),
-            };
-            Ok(PersiaEmbeddingHolder {
-                inner: Arc::new(sharded),
-            })
+            let holder = PersiaEmbeddingHolder::from_maps(maps);
+            if config.enable_collision_guard {
+                Ok(holder.with_collision_guard(config.reject_sign_collisions))
+            } else {
+                Ok(holder)
+            }
         });
         match singleton {
             Ok(s) => Ok(s.clone()),
@@ -64,6 +199,279 @@ impl PersiaEmbeddingHolder {
         }
     }
 
+    /// Create a standalone holder, e.g. for tests and tools, instead of the global one configured
+    /// by [`EmbeddingParameterServerConfig`].
+    pub fn new(capacity: usize, num_internal_shards: usize) -> Self {
+        Self::with_backend(capacity, num_internal_shards, capacity)
+    }
+
+    /// Like [`PersiaEmbeddingHolder::new`], but the shards only allocate room for
+    /// `expected_signs` entries in total instead of `capacity`. Pre-sizing avoids rehash pauses
+    /// while the holder fills up at cold start, at the cost of allocating the memory for the
+    /// expected signs up front. Shards grow on demand beyond `expected_signs`.
+    pub fn with_capacity(
+        capacity: usize,
+        num_internal_shards: usize,
+        expected_signs: usize,
+    ) -> Self {
+        Self::with_backend(capacity, num_internal_shards, expected_signs)
+    }
+
+    /// Sums the gradients of signs that occur more than once in a batch, so that every sign is
+    /// updated once. Gradients of signs occurring once are borrowed instead of copied.
+    pub fn merge_gradients<'a>(signs: &[u64], grads: &[&'a [f32]]) -> HashMap<u64, Cow<'a, [f32]>> {
+        assert_eq!(
+            signs.len(),
+            grads.len(),
+            "signs and gradients length mismatch"
+        );
+        let mut merged: HashMap<u64, Cow<'a, [f32]>> = HashMap::with_capacity(signs.len());
+        signs
+            .iter()
+            .zip(grads.iter())
+            .for_each(|(sign, grad)| match merged.get_mut(sign) {
+                Some(sum) => {
+                    assert_eq!(
+                        sum.len(),
+                        grad.len(),
+                        "gradient dim mismatch of sign {}",
+                        sign
+                    );
+                    sum.to_mut()
+                        .iter_mut()
+                        .zip(grad.iter())
+                        .for_each(|(x, g)| *x += g);
+                }
+                None => {
+                    merged.insert(*sign, Cow::Borrowed(*grad));
+                }
+            });
+        merged
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Like [`PersiaEmbeddingHolder::with_capacity`], but the shards index their entries with the
+    /// map backend `M` instead of [`DefaultMapBackend`].
+    pub fn with_backend(
+        capacity: usize,
+        num_internal_shards: usize,
+        expected_signs: usize,
+    ) -> Self {
+        let capacity_per_bucket = capacity / num_internal_shards;
+        let reserved_per_bucket = Self::reservation_per_shard(expected_signs, num_internal_shards)
+            .min(capacity_per_bucket);
+        let maps = (0..num_internal_shards)
+            .map(|_| EvictionMap::with_backend(capacity_per_bucket, reserved_per_bucket))
+            .collect();
+        Self::from_maps(maps)
+    }
+
+    // Signs do not spread perfectly evenly across shards, so every shard reserves an eighth more
+    // than its even share.
+    fn reservation_per_shard(num_signs: usize, num_internal_shards: usize) -> usize {
+        let even_share = num_signs / num_internal_shards + 1;
+        even_share + even_share / 8
+    }
+
+    fn from_maps(maps: Vec<EvictionMap<u64, HashMapEmbeddingEntry, M>>) -> Self {
+        let sharded = Sharded {
+            inner: maps.into_iter().map(RwLock::new).collect(),
+            phantom: std::marker::PhantomData::default(),
+        };
+        PersiaEmbeddingHolder {
+            inner: Arc::new(sharded),
+            counters: Arc::new(HolderCounters::default()),
+            collision_guard: None,
+            hot_cache: None,
+        }
+    }
+
+    // Positions of signs grouped by the internal shard they belong to, so that every shard is
+    // locked once per batch.
+    fn group_by_shard(&self, signs: &[u64]) -> Vec<Vec<usize>> {
+        let num_internal_shards = self.num_internal_shards();
+        let mut groups = vec![Vec::new(); num_internal_shards];
+        signs.iter().enumerate().for_each(|(idx, sign)| {
+            groups[get_index(sign, num_internal_shards)].push(idx);
+        });
+        groups
+    }
+
+    /// Lookup of one sign, only the shard of `sign` is locked.
+    pub fn get_entry(&self, sign: u64) -> Option<HashMapEmbeddingEntry> {
+        let entry = self
+            .shard(&sign)
+            .read()
+            .get(&sign)
+            .filter(|x| !x.is_tombstoned())
+            .cloned();
+        let hit = entry.is_some() as u64;
+        self.counters.record_lookups(hit, 1 - hit);
+        entry
+    }
+
+    /// [`PersiaEmbeddingHolder::get_entry`] failing with
+    /// [`PersiaEmbeddingHolderError::SignNotFound`] for missing and tombstoned signs.
+    pub fn try_get_entry(
+        &self,
+        sign: u64,
+    ) -> Result<HashMapEmbeddingEntry, PersiaEmbeddingHolderError> {
+        self.get_entry(sign)
+            .ok_or(PersiaEmbeddingHolderError::SignNotFound(sign))
+    }
+
+    /// Lookup of one sign which initializes a missing entry with seed `seed_base ^ sign` like
+    /// [`PersiaEmbeddingHolder::get_or_init_many`]. See
+    /// [`PersiaEmbeddingHolder::get_or_init_with`] for the behavior under contention. A
+    /// tombstoned sign gets a zero entry which is not stored, until the holder is compacted.
+    pub fn get_or_init(
+        &self,
+        sign: u64,
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        seed_base: u64,
+    ) -> HashMapEmbeddingEntry {
+        self.get_or_init_with(sign, || {
+            HashMapEmbeddingEntry::new(
+                initialization_method,
+                dim,
+                require_space,
+                seed_base ^ sign,
+                sign,
+            )
+        })
+    }
+
+    /// Lookup of one sign which stores the entry returned by `init` if the sign is missing.
+    /// Hits only take the read lock of the shard. Concurrent misses of one sign serialize on
+    /// the write lock of its shard, the first thread calls `init` while the others wait and then
+    /// observe the stored entry, so `init` runs once per insertion of a sign. `init` runs under
+    /// the write lock and blocks all lookups of the shard, it should not do more than build the
+    /// entry. Tombstoned signs are handled like in [`PersiaEmbeddingHolder::get_or_init`].
+    pub fn get_or_init_with<F>(&self, sign: u64, init: F) -> HashMapEmbeddingEntry
+    where
+        F: FnOnce() -> HashMapEmbeddingEntry,
+    {
+        if let Some(entry) = self.shard(&sign).read().get(&sign) {
+            return self.found_or_tombstoned(entry);
+        }
+        let mut shard = self.shard(&sign).write();
+        // another thread may have initialized the entry in between
+        if let Some(entry) = shard.get(&sign) {
+            return self.found_or_tombstoned(entry);
+        }
+        self.counters.record_lookups(0, 1);
+        let entry = init();
+        let (_, evicted) = shard.insert(sign, entry.clone());
+        self.counters.record_insertions(1, evicted.is_some() as u64);
+        entry
+    }
+
+    fn found_or_tombstoned(&self, entry: &HashMapEmbeddingEntry) -> HashMapEmbeddingEntry {
+        if entry.is_tombstoned() {
+            self.counters.record_lookups(0, 1);
+            return HashMapEmbeddingEntry::new_empty(
+                entry.embedding_dim(),
+                entry.opt().len(),
+                entry.sign(),
+            );
+        }
+        self.counters.record_lookups(1, 0);
+        entry.clone()
+    }
+
+    /// Batched lookup of signs, the result at position `i` corresponds to `signs[i]`.
+    pub fn get_many(&self, signs: &[u64]) -> Vec<Option<HashMapEmbeddingEntry>> {
+        let groups = self.group_by_shard(signs);
+        let found: Vec<Vec<(usize, Option<HashMapEmbeddingEntry>)>> = groups
+            .par_iter()
+            .enumerate()
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(shard_idx, group)| {
+                let shard = self.get_shard_by_index(shard_idx).read();
+                let found: Vec<_> = group
+                    .iter()
+                    .map(|idx| {
+                        let entry = shard.get(&signs[*idx]).filter(|x| !x.is_tombstoned());
+                        (*idx, entry.cloned())
+                    })
+                    .collect();
+                let hits = found.iter().filter(|(_, x)| x.is_some()).count() as u64;
+                self.counters
+                    .record_lookups(hits, group.len() as u64 - hits);
+                found
+            })
+            .collect();
+
+        let mut result = vec![None; signs.len()];
+        found.into_iter().flatten().for_each(|(idx, entry)| {
+            result[idx] = entry;
+        });
+        result
+    }
+
+    /// Batched lookup of signs which initializes missing entries, shards are processed in
+    /// parallel. The seed of a missing entry is derived as `seed_base ^ sign`, so initialization
+    /// is reproducible regardless of the batch it shows up in. Tombstoned signs are handled like
+    /// in [`PersiaEmbeddingHolder::get_or_init`].
+    pub fn get_or_init_many(
+        &self,
+        signs: &[u64],
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        require_space: usize,
+        seed_base: u64,
+    ) -> Vec<HashMapEmbeddingEntry> {
+        let groups = self.group_by_shard(signs);
+        let found: Vec<Vec<(usize, HashMapEmbeddingEntry)>> = groups
+            .par_iter()
+            .enumerate()
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(shard_idx, group)| {
+                let mut shard = self.get_shard_by_index(shard_idx).write();
+                let (mut misses, mut insertions, mut evictions) = (0, 0, 0);
+                let found: Vec<_> = group
+                    .iter()
+                    .map(|idx| {
+                        let sign = signs[*idx];
+                        let entry = match shard.get(&sign) {
+                            Some(entry) if entry.is_tombstoned() => {
+                                misses += 1;
+                                HashMapEmbeddingEntry::new_empty(dim, require_space, sign)
+                            }
+                            Some(entry) => entry.clone(),
+                            None => {
+                                let entry = HashMapEmbeddingEntry::new(
+                                    initialization_method,
+                                    dim,
+                                    require_space,
+                                    seed_base ^ sign,
+                                    sign,
+                                );
+                                let (_, evicted) = shard.insert(sign, entry.clone());
+                                misses += 1;
+                                insertions += 1;
+                                evictions += evicted.is_some() as u64;
+                                entry
+                            }
+                        };
+                        (*idx, entry)
+                    })
+                    .collect();
+                self.counters
+                    .record_lookups(group.len() as u64 - misses, misses);
+                self.counters.record_insertions(insertions, evictions);
+                found
+            })
+            .collect();
+
+        let mut found: Vec<(usize, HashMapEmbeddingEntry)> = found.into_iter().flatten().collect();
+        found.sort_unstable_by_key(|(idx, _)| *idx);
+        found.into_iter().map(|(_, entry)| entry).collect()
+    }
+
     pub fn num_total_signs(&self) -> usize {
         self.inner
             .inner
@@ -84,18 +492,411 @@ impl PersiaEmbeddingHolder {
             .sum::<usize>()
     }
 
+    /// Reserves room for `additional` more signs across the shards, e.g. before loading a
+    /// checkpoint of known size, so that inserting them does not rehash.
+    pub fn reserve(&self, additional: usize) {
+        let per_shard = Self::reservation_per_shard(additional, self.num_internal_shards());
+        self.inner
+            .inner
+            .iter()
+            .for_each(|x| x.write().reserve(per_shard));
+    }
+
+    /// Number of signs the shards can hold without rehashing.
+    pub fn reserved_capacity(&self) -> usize {
+        self.inner
+            .inner
+            .iter()
+            .map(|x| x.read().reserved_capacity())
+            .sum::<usize>()
+    }
+
     pub fn clear(&self) {
         self.inner.inner.iter().for_each(|x| x.write().clear());
+        if let Some(guard) = &self.collision_guard {
+            guard.clear();
+        }
+        if let Some(cache) = &self.hot_cache {
+            cache.clear();
+        }
+    }
+
+    /// Soft deletes `sign`, e.g. for erasure requests, returns whether the sign was present.
+    /// Lookups miss the sign and [`PersiaEmbeddingHolder::get_or_init`] does not initialize it
+    /// again, but its memory is only reclaimed by [`PersiaEmbeddingHolder::compact`] or the
+    /// next [`PersiaEmbeddingHolder::checkpoint_dirty`]. Tombstoned entries are left out of all
+    /// dumps. Inserting an entry for the sign into its shard replaces the tombstone.
+    pub fn tombstone(&self, sign: u64) -> bool {
+        match self.shard(&sign).write().peek_mut(&sign) {
+            Some(entry) => {
+                entry.set_tombstoned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Flushes the subnormal values of all entries to zero, see
+    /// [`HashMapEmbeddingEntry::flush_denormals`]. This is a performance fix, not a correctness
+    /// one. Frozen entries are left as is. Returns the number of flushed values.
+    pub fn flush_all_denormals(&self) -> usize {
+        self.inner
+            .inner
+            .par_iter()
+            .map(|shard| {
+                let mut shard = shard.write();
+                let signs: Vec<u64> = shard
+                    .linkedlist
+                    .iter()
+                    .filter(|x| !x.is_frozen() && x.has_denormals())
+                    .map(|x| x.sign())
+                    .collect();
+                let mut num_flushed = 0;
+                for sign in signs.iter() {
+                    if let Some(entry) = shard.peek_mut(sign) {
+                        num_flushed += entry.flush_denormals();
+                    }
+                }
+                num_flushed
+            })
+            .sum()
     }
 
-    pub fn shard(&self, key: &u64) -> &RwLock<EvictionMap<u64, HashMapEmbeddingEntry>> {
+    /// Removes the tombstoned entries, e.g. before writing a checkpoint, returns the number of
+    /// removed entries.
+    pub fn compact(&self) -> usize {
+        self.inner
+            .inner
+            .iter()
+            .map(|x| x.write().retain(|entry| !entry.is_tombstoned()))
+            .sum()
+    }
+
+    /// Signs whose entries were last touched before `before_step`, in ascending order, e.g. to
+    /// drive eviction or pruning of entries no longer trained.
+    pub fn stale_signs(&self, before_step: u64) -> Vec<u64> {
+        let mut signs: Vec<u64> = self
+            .inner
+            .inner
+            .par_iter()
+            .flat_map_iter(|x| {
+                let shard = x.read();
+                shard
+                    .linkedlist
+                    .iter()
+                    .filter(|entry| !entry.is_tombstoned() && entry.last_step() < before_step)
+                    .map(|entry| entry.sign())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        signs.sort_unstable();
+        signs
+    }
+
+    pub fn shard(&self, key: &u64) -> &RwLock<EvictionMap<u64, HashMapEmbeddingEntry, M>> {
         self.inner.shard(key)
     }
 
     pub fn get_shard_by_index(
         &self,
         index: usize,
-    ) -> &RwLock<EvictionMap<u64, HashMapEmbeddingEntry>> {
+    ) -> &RwLock<EvictionMap<u64, HashMapEmbeddingEntry, M>> {
         self.inner.get_shard_by_index(index)
     }
 }
+
+#[cfg(test)]
+mod embedding_holder_tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_get_many() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..100).collect();
+
+        let entries = holder.get_or_init_many(&signs, &initialization, 8, 8, 42);
+        assert_eq!(entries.len(), signs.len());
+        assert_eq!(holder.num_total_signs(), signs.len());
+        entries.iter().zip(signs.iter()).for_each(|(entry, sign)| {
+            assert_eq!(entry.sign(), *sign);
+            let expected = HashMapEmbeddingEntry::new(&initialization, 8, 8, 42 ^ sign, *sign);
+            assert_eq!(entry.emb(), expected.emb());
+        });
+
+        let again = holder.get_or_init_many(&[3, 3, 1000], &initialization, 8, 8, 7);
+        assert_eq!(again[0].emb(), entries[3].emb());
+        assert_eq!(again[1].emb(), entries[3].emb());
+        assert_eq!(again[2].sign(), 1000);
+        assert_eq!(holder.num_total_signs(), signs.len() + 1);
+
+        let found = holder.get_many(&[5, 2000, 99]);
+        assert_eq!(found[0].as_ref().unwrap().emb(), entries[5].emb());
+        assert!(found[1].is_none());
+        assert_eq!(found[2].as_ref().unwrap().sign(), 99);
+    }
+
+    #[test]
+    fn test_get_or_init_shard() {
+        let holder = PersiaEmbeddingHolder::new(10_000, 8);
+        let initialization = InitializationMethod::default();
+        let handles: Vec<_> = (0..4u64)
+            .map(|thread_idx| {
+                let holder = holder.clone();
+                let initialization = initialization.clone();
+                std::thread::spawn(move || {
+                    (0..1000u64).for_each(|sign| {
+                        let entry = holder.get_or_init(sign, &initialization, 4, 0, 42);
+                        assert_eq!(entry.sign(), sign);
+                        // signs of other threads are mostly present already
+                        let _ = holder.get_entry(sign ^ thread_idx);
+                    })
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|x| x.join().unwrap());
+        assert_eq!(holder.num_total_signs(), 1000);
+
+        (0..1000u64).for_each(|sign| {
+            let expected_shard = get_index(&sign, holder.num_internal_shards());
+            (0..holder.num_internal_shards()).for_each(|idx| {
+                let shard = holder.get_shard_by_index(idx).read();
+                assert_eq!(shard.get(&sign).is_some(), idx == expected_shard);
+            });
+            let expected = HashMapEmbeddingEntry::new(&initialization, 4, 0, 42 ^ sign, sign);
+            assert_eq!(holder.get_entry(sign).unwrap().emb(), expected.emb());
+        });
+        assert!(holder.get_entry(1000).is_none());
+    }
+
+    #[test]
+    fn test_get_or_init_with_single_initialization() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Barrier;
+
+        let holder = PersiaEmbeddingHolder::new(10_000, 4);
+        let num_threads = 16;
+        let num_inits = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(num_threads));
+        let handles: Vec<_> = (0..num_threads)
+            .map(|thread_idx| {
+                let holder = holder.clone();
+                let num_inits = num_inits.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    // every thread would initialize the sign differently
+                    holder.get_or_init_with(7, || {
+                        num_inits.fetch_add(1, Ordering::SeqCst);
+                        HashMapEmbeddingEntry::from_emb(vec![thread_idx as f32; 4], 7)
+                    })
+                })
+            })
+            .collect();
+        let entries: Vec<_> = handles.into_iter().map(|x| x.join().unwrap()).collect();
+
+        assert_eq!(num_inits.load(Ordering::SeqCst), 1);
+        let stored = holder.get_entry(7).unwrap();
+        entries
+            .iter()
+            .for_each(|entry| assert_eq!(entry.emb(), stored.emb()));
+        let stats = holder.stats();
+        assert_eq!(stats.insertions, 1);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn test_merge_gradients() {
+        let grads: Vec<Vec<f32>> = vec![
+            vec![1.0, 2.0],
+            vec![0.5, 0.5, 0.5],
+            vec![3.0, -1.0],
+            vec![0.25, 0.25],
+        ];
+        let grads: Vec<&[f32]> = grads.iter().map(|x| x.as_slice()).collect();
+        let merged = PersiaEmbeddingHolder::merge_gradients(&[7, 8, 7, 7], &grads);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[&7].as_ref(), &[4.25, 1.25]);
+        assert_eq!(merged[&8].as_ref(), &[0.5, 0.5, 0.5]);
+        assert!(matches!(merged[&8], Cow::Borrowed(_)));
+        assert!(matches!(merged[&7], Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_tombstone() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..10).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 0, 42);
+
+        assert!(holder.tombstone(3));
+        assert!(!holder.tombstone(1000));
+        assert!(holder.get_entry(3).is_none());
+        assert!(holder.get_many(&[2, 3])[1].is_none());
+        // the memory is kept until compaction
+        assert_eq!(holder.num_total_signs(), 10);
+
+        // tombstoned signs are not initialized again
+        let entry = holder.get_or_init(3, &initialization, 4, 0, 42);
+        assert_eq!(entry.emb(), &[0.0; 4]);
+        let entries = holder.get_or_init_many(&[3, 4], &initialization, 4, 0, 42);
+        assert_eq!(entries[0].emb(), &[0.0; 4]);
+        assert!(holder.get_entry(3).is_none());
+
+        assert_eq!(holder.compact(), 1);
+        assert_eq!(holder.compact(), 0);
+        assert_eq!(holder.num_total_signs(), 9);
+        let entry = holder.get_or_init(3, &initialization, 4, 0, 42);
+        let expected = HashMapEmbeddingEntry::new(&initialization, 4, 0, 42 ^ 3, 3);
+        assert_eq!(entry.emb(), expected.emb());
+
+        // an explicit insertion replaces the tombstone
+        assert!(holder.tombstone(5));
+        let entry = HashMapEmbeddingEntry::from_emb(vec![5.0; 4], 5);
+        let _ = holder.shard(&5).write().insert(5, entry);
+        assert_eq!(holder.get_entry(5).unwrap().emb(), &[5.0; 4]);
+        assert_eq!(holder.compact(), 0);
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let expected_signs = 10_000;
+        let holder = PersiaEmbeddingHolder::with_capacity(1_000_000, 4, expected_signs);
+        let reserved = holder.reserved_capacity();
+        assert!(reserved >= expected_signs);
+        assert!(reserved < 1_000_000);
+
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..expected_signs as u64).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 0, 42);
+        assert_eq!(holder.num_total_signs(), expected_signs);
+        // no shard had to grow
+        assert_eq!(holder.reserved_capacity(), reserved);
+
+        holder.reserve(expected_signs);
+        assert!(holder.reserved_capacity() >= 2 * expected_signs);
+    }
+
+    // Fills a holder past its capacity, tombstones some signs and records every lookup.
+    fn run_sequence<M: HolderMapBackend>(
+        holder: PersiaEmbeddingHolder<M>,
+    ) -> Vec<Option<Vec<f32>>> {
+        let initialization = InitializationMethod::default();
+        let signs: Vec<u64> = (0..200).map(|i| i * 7 % 151).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 0, 42);
+        (0..10).for_each(|sign| {
+            holder.tombstone(sign * 3);
+        });
+        let lookups: Vec<u64> = (0..151).collect();
+        let mut results: Vec<Option<Vec<f32>>> = holder
+            .get_many(&lookups)
+            .into_iter()
+            .map(|entry| entry.map(|x| x.emb().to_vec()))
+            .collect();
+        results.push(Some(vec![holder.num_total_signs() as f32]));
+        results
+    }
+
+    #[test]
+    fn test_map_backends_agree() {
+        let expected = run_sequence(PersiaEmbeddingHolder::new(64, 4));
+        assert!(expected.iter().any(|x| x.is_none()));
+        let std_holder: PersiaEmbeddingHolder<map_backend::StdMapBackend<u64>> =
+            PersiaEmbeddingHolder::with_backend(64, 4, 64);
+        assert_eq!(run_sequence(std_holder), expected);
+        #[cfg(feature = "dashmap")]
+        {
+            let dash_holder: PersiaEmbeddingHolder<map_backend::DashMapBackend<u64>> =
+                PersiaEmbeddingHolder::with_backend(64, 4, 64);
+            assert_eq!(run_sequence(dash_holder), expected);
+        }
+    }
+
+    #[test]
+    fn test_stale_signs() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        (0..10u64).for_each(|sign| {
+            let mut entry = HashMapEmbeddingEntry::from_emb(vec![0.0; 4], sign);
+            entry.touch(sign * 100);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        assert_eq!(holder.stale_signs(0), Vec::<u64>::new());
+        assert_eq!(holder.stale_signs(250), vec![0, 1, 2]);
+        holder.shard(&1).write().get_mut(&1).unwrap().touch(1000);
+        assert_eq!(holder.stale_signs(250), vec![0, 2]);
+        assert_eq!(holder.stale_signs(u64::MAX).len(), 10);
+    }
+
+    #[test]
+    fn test_flush_all_denormals() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let subnormal = f32::MIN_POSITIVE / 8.0;
+        (0..10u64).for_each(|sign| {
+            let emb = vec![if sign % 3 == 0 { subnormal } else { 0.5 }, 1.0];
+            let entry = HashMapEmbeddingEntry::from_emb(emb, sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        holder.shard(&9).write().get_mut(&9).unwrap().freeze();
+
+        assert_eq!(holder.flush_all_denormals(), 3);
+        assert_eq!(holder.get_entry(3).unwrap().emb(), &[0.0, 1.0]);
+        assert_eq!(holder.get_entry(4).unwrap().emb(), &[0.5, 1.0]);
+        assert!(holder.get_entry(9).unwrap().has_denormals());
+        assert_eq!(holder.flush_all_denormals(), 0);
+    }
+
+    #[test]
+    fn test_holder_error_kinds() {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        let mut entry = HashMapEmbeddingEntry::from_emb(vec![1.0; 4], 3);
+        let _ = holder.shard(&3).write().insert(3, entry.clone());
+
+        assert!(matches!(
+            holder.try_get_entry(5),
+            Err(PersiaEmbeddingHolderError::SignNotFound(5))
+        ));
+        assert!(holder.try_get_entry(3).is_ok());
+
+        let other = HashMapEmbeddingEntry::from_emb(vec![2.0; 2], 4);
+        assert!(matches!(
+            entry.try_copy_from_other(&other),
+            Err(PersiaEmbeddingHolderError::DimMismatch {
+                sign: 3,
+                expected: 4,
+                actual: 2
+            })
+        ));
+        assert!(!entry.copy_from_other(&other));
+        entry.freeze();
+        let other = HashMapEmbeddingEntry::from_emb(vec![2.0; 4], 4);
+        assert!(matches!(
+            entry.try_copy_from_other(&other),
+            Err(PersiaEmbeddingHolderError::EntryFrozen(3))
+        ));
+
+        let unsupported = InitializationMethod::InverseEmbeddingSizeSqrt;
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new(&unsupported, 4, 0, 0, 1)
+                .map_err(PersiaEmbeddingHolderError::from),
+            Err(PersiaEmbeddingHolderError::UnsupportedInit(_))
+        ));
+        let dim = emb_entry::max_entry_len() + 1;
+        assert!(matches!(
+            HashMapEmbeddingEntry::try_new_empty(dim, 0, 1)
+                .map_err(PersiaEmbeddingHolderError::from),
+            Err(PersiaEmbeddingHolderError::EntryTooLarge { .. })
+        ));
+
+        let mut bytes = other.write_to_vec().unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(matches!(
+            HashMapEmbeddingEntry::from_bytes(&bytes),
+            Err(PersiaEmbeddingHolderError::CorruptEntry(_))
+        ));
+        assert!(HashMapEmbeddingEntry::from_bytes(&bytes[..3]).is_err());
+    }
+}
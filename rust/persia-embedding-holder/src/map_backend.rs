@@ -0,0 +1,241 @@
+//! Index maps from the keys of an [`crate::eviction_map::EvictionMap`] to the slots of their
+//! entries in its linked list. The map is a type parameter of the eviction map defaulting to
+//! [`DefaultMapBackend`], so the index can be swapped without touching the eviction logic.
+
+use std::collections::HashMap as StdHashMap;
+use std::hash::{BuildHasher, Hash};
+
+use persia_libs::hashbrown::HashMap;
+
+/// Backend used by [`crate::eviction_map::EvictionMap`] unless another one is selected.
+pub type DefaultMapBackend<K> = HashMap<K, u32>;
+
+pub type StdMapBackend<K> = StdHashMap<K, u32>;
+
+#[cfg(feature = "dashmap")]
+pub type DashMapBackend<K> = dashmap::DashMap<K, u32>;
+
+/// Map from keys to linked list slots. Slots are returned by value, since concurrent maps can
+/// not hand out references into their buckets.
+pub trait MapBackend<K> {
+    fn with_capacity(capacity: usize) -> Self;
+
+    fn get(&self, key: &K) -> Option<u32>;
+
+    /// Stores `slot` for `key`, returns the previous slot of `key` if any.
+    fn insert(&mut self, key: K, slot: u32) -> Option<u32>;
+
+    fn remove(&mut self, key: &K) -> Option<u32>;
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self);
+
+    /// Reserves room for `additional` more keys, so that inserting them does not rehash.
+    fn reserve(&mut self, additional: usize);
+
+    /// Number of keys the map can hold without rehashing.
+    fn capacity(&self) -> usize;
+
+    fn for_each_key<F: FnMut(&K)>(&self, f: F);
+}
+
+/// Backends the shards of a [`crate::PersiaEmbeddingHolder`] can be indexed with. The shards are
+/// shared between threads, so the backend has to be too.
+pub trait HolderMapBackend: MapBackend<u64> + Send + Sync + 'static {}
+
+impl<M: MapBackend<u64> + Send + Sync + 'static> HolderMapBackend for M {}
+
+impl<K, S> MapBackend<K> for HashMap<K, u32, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn with_capacity(capacity: usize) -> Self {
+        HashMap::with_capacity_and_hasher(capacity, S::default())
+    }
+
+    fn get(&self, key: &K) -> Option<u32> {
+        HashMap::get(self, key).copied()
+    }
+
+    fn insert(&mut self, key: K, slot: u32) -> Option<u32> {
+        HashMap::insert(self, key, slot)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u32> {
+        HashMap::remove(self, key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        HashMap::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional)
+    }
+
+    fn capacity(&self) -> usize {
+        HashMap::capacity(self)
+    }
+
+    fn for_each_key<F: FnMut(&K)>(&self, f: F) {
+        self.keys().for_each(f)
+    }
+}
+
+impl<K, S> MapBackend<K> for StdHashMap<K, u32, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn with_capacity(capacity: usize) -> Self {
+        StdHashMap::with_capacity_and_hasher(capacity, S::default())
+    }
+
+    fn get(&self, key: &K) -> Option<u32> {
+        StdHashMap::get(self, key).copied()
+    }
+
+    fn insert(&mut self, key: K, slot: u32) -> Option<u32> {
+        StdHashMap::insert(self, key, slot)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u32> {
+        StdHashMap::remove(self, key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        StdHashMap::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        StdHashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        StdHashMap::clear(self)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        StdHashMap::reserve(self, additional)
+    }
+
+    fn capacity(&self) -> usize {
+        StdHashMap::capacity(self)
+    }
+
+    fn for_each_key<F: FnMut(&K)>(&self, f: F) {
+        self.keys().for_each(f)
+    }
+}
+
+/// Sharded concurrent map. The eviction map still needs `&mut self` to mutate, this backend is
+/// meant for comparing lookup costs and for sharing the index with readers outside the map.
+#[cfg(feature = "dashmap")]
+impl<K, S> MapBackend<K> for dashmap::DashMap<K, u32, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Clone + Default,
+{
+    fn with_capacity(capacity: usize) -> Self {
+        dashmap::DashMap::with_capacity_and_hasher(capacity, S::default())
+    }
+
+    fn get(&self, key: &K) -> Option<u32> {
+        dashmap::DashMap::get(self, key).map(|slot| *slot)
+    }
+
+    fn insert(&mut self, key: K, slot: u32) -> Option<u32> {
+        dashmap::DashMap::insert(self, key, slot)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u32> {
+        dashmap::DashMap::remove(self, key).map(|(_, slot)| slot)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        dashmap::DashMap::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        dashmap::DashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        dashmap::DashMap::clear(self)
+    }
+
+    // DashMap can not reserve after construction, its shards grow on demand.
+    fn reserve(&mut self, _additional: usize) {}
+
+    fn capacity(&self) -> usize {
+        dashmap::DashMap::capacity(self)
+    }
+
+    fn for_each_key<F: FnMut(&K)>(&self, mut f: F) {
+        self.iter().for_each(|x| f(x.key()))
+    }
+}
+
+#[cfg(test)]
+mod map_backend_tests {
+    use super::*;
+    use crate::eviction_map::{EvictionMap, EvictionMapValue};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Value {
+        key: u64,
+        payload: u64,
+    }
+
+    impl EvictionMapValue<u64> for Value {
+        fn hashmap_key(&self) -> u64 {
+            self.key
+        }
+    }
+
+    // Inserts past the capacity, refreshes and removes some keys, and records every result.
+    fn run_sequence<M: MapBackend<u64>>() -> Vec<Option<u64>> {
+        let mut map: EvictionMap<u64, Value, M> = EvictionMap::with_backend(16, 4);
+        let mut results = Vec::new();
+        (0..64u64).for_each(|i| {
+            let key = i * 7 % 23;
+            let (old, evicted) = map.insert(key, Value { key, payload: i });
+            results.push(old.map(|x| x.payload));
+            results.push(evicted.map(|x| x.key));
+            if i % 3 == 0 {
+                results.push(map.get_refresh(&(i % 23)).map(|x| x.payload));
+            }
+        });
+        map.retain(|x| x.payload % 2 == 0);
+        results.extend((0..23u64).map(|key| map.get(&key).map(|x| x.payload)));
+        results.push(Some(map.len() as u64));
+        results.push(Some(map.hashmap.len() as u64));
+        results
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let expected = run_sequence::<DefaultMapBackend<u64>>();
+        assert_eq!(run_sequence::<StdMapBackend<u64>>(), expected);
+        #[cfg(feature = "dashmap")]
+        assert_eq!(run_sequence::<DashMapBackend<u64>>(), expected);
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+use persia_libs::thiserror;
+use persia_speedy::{Readable, Writable};
+
+use crate::indexed::{IndexedError, IndexedReader};
+use crate::iter::EmbeddingEntryRef;
+
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum MmapHolderError {
+    #[error("query dim {actual} does not match embedding dim {expected} of the mapped file")]
+    DimMismatch { expected: usize, actual: usize },
+}
+
+/// Read only holder serving the entries of a file written by [`crate::indexed::write_indexed`]
+/// straight from a memory mapping, e.g. for inference. Rows are only paged in when they are
+/// looked up and lookups do not copy.
+pub struct MmapHolder {
+    mmap: Mmap,
+    embedding_dim: usize,
+}
+
+impl MmapHolder {
+    /// Maps the file at `path` and validates its index. The file must not be modified while it
+    /// is mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let embedding_dim = IndexedReader::new(&mmap)
+            .map_err(|e: IndexedError| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .embedding_dim();
+        Ok(Self {
+            mmap,
+            embedding_dim,
+        })
+    }
+
+    /// Reader over the mapping, whose index was validated when the file was opened.
+    pub fn reader(&self) -> IndexedReader<'_> {
+        IndexedReader::new_unvalidated(&self.mmap)
+            .expect("mapped holder file changed after validation")
+    }
+
+    pub fn embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.reader().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Embedding of `sign` borrowed from the mapping.
+    pub fn get(&self, sign: u64) -> Option<&[f32]> {
+        self.get_entry(sign).map(|x| x.emb)
+    }
+
+    /// Embedding and optimizer state of `sign` borrowed from the mapping.
+    pub fn get_entry(&self, sign: u64) -> Option<EmbeddingEntryRef<'_>> {
+        self.reader().get(sign)
+    }
+
+    /// Like [`MmapHolder::get`], but fails if `dim` is not the embedding dim of the file, e.g.
+    /// when a model queries a file exported for another model.
+    pub fn get_checked(&self, sign: u64, dim: usize) -> Result<Option<&[f32]>, MmapHolderError> {
+        if dim != self.embedding_dim {
+            return Err(MmapHolderError::DimMismatch {
+                expected: self.embedding_dim,
+                actual: dim,
+            });
+        }
+        Ok(self.get(sign))
+    }
+}
+
+#[cfg(test)]
+mod mmap_holder_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+    use crate::indexed::write_indexed;
+    use crate::PersiaEmbeddingHolder;
+
+    #[test]
+    fn test_mmap_holder() {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        [5u64, 1, 9, 3].iter().for_each(|sign| {
+            let emb = vec![*sign as f32, -(*sign as f32), 0.5];
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(emb, &[1.0], *sign);
+            let _ = holder.shard(sign).write().insert(*sign, entry);
+        });
+
+        let path = std::env::temp_dir().join(format!(
+            "persia_mmap_holder_test_{}.bin",
+            std::process::id()
+        ));
+        let mut file = File::create(&path).unwrap();
+        assert_eq!(write_indexed(&holder, &mut file).unwrap(), 4);
+        drop(file);
+
+        let mapped = MmapHolder::open(&path).unwrap();
+        assert_eq!(mapped.len(), 4);
+        assert_eq!(mapped.embedding_dim(), 3);
+        [5u64, 1, 9, 3].iter().for_each(|sign| {
+            let row = mapped.get(*sign).unwrap();
+            assert_eq!(row, &[*sign as f32, -(*sign as f32), 0.5]);
+            // rows point into the mapping
+            let mapping = mapped.mmap.as_ptr_range();
+            assert!(mapping.contains(&(row.as_ptr() as *const u8)));
+        });
+        assert_eq!(mapped.get_entry(3).unwrap().opt, &[1.0]);
+        assert!(mapped.get(4).is_none());
+        assert_eq!(mapped.get_checked(9, 3).unwrap().unwrap()[0], 9.0);
+        assert!(matches!(
+            mapped.get_checked(9, 4),
+            Err(MmapHolderError::DimMismatch {
+                expected: 3,
+                actual: 4
+            })
+        ));
+
+        std::fs::write(&path, b"not a holder").unwrap();
+        assert!(MmapHolder::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use persia_libs::rayon::prelude::*;
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+/// Score of an embedding against a query in [`PersiaEmbeddingHolder::nearest`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// Cosine similarity, higher is nearer.
+    Cosine,
+    /// Dot product, higher is nearer.
+    Dot,
+    /// Euclidean distance, lower is nearer.
+    L2,
+}
+
+impl Metric {
+    // Queries of another dim score NaN, which the search skips.
+    fn score(&self, entry: &HashMapEmbeddingEntry, query: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => entry.cosine_similarity(query).unwrap_or(f32::NAN),
+            Metric::Dot => entry.dot(query).unwrap_or(f32::NAN),
+            Metric::L2 => entry
+                .emb()
+                .iter()
+                .zip(query.iter())
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+
+    fn higher_is_nearer(&self) -> bool {
+        !matches!(self, Metric::L2)
+    }
+}
+
+// Candidate ordered by nearness, ties are broken towards the smaller sign so results do not
+// depend on the order of the scan.
+#[derive(Clone, Copy, Debug)]
+struct Candidate {
+    nearness: f32,
+    score: f32,
+    sign: u64,
+}
+
+impl Candidate {
+    fn new(metric: Metric, entry: &HashMapEmbeddingEntry, query: &[f32]) -> Self {
+        let score = metric.score(entry, query);
+        Self {
+            nearness: if metric.higher_is_nearer() {
+                score
+            } else {
+                -score
+            },
+            score,
+            sign: entry.sign(),
+        }
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.nearness
+            .partial_cmp(&other.nearness)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.sign.cmp(&self.sign))
+    }
+}
+
+/// The `k` nearest candidates seen so far, the farthest of them on top of a min heap.
+struct TopK {
+    k: usize,
+    heap: BinaryHeap<Reverse<Candidate>>,
+}
+
+impl TopK {
+    fn new(k: usize) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    fn push(&mut self, candidate: Candidate) {
+        if candidate.nearness.is_nan() {
+            return;
+        }
+        self.heap.push(Reverse(candidate));
+        if self.heap.len() > self.k {
+            self.heap.pop();
+        }
+    }
+
+    fn merge(mut self, other: TopK) -> TopK {
+        other.heap.into_iter().for_each(|x| self.push(x.0));
+        self
+    }
+
+    // nearest first
+    fn into_sorted(self) -> Vec<(u64, f32)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|x| (x.0.sign, x.0.score))
+            .collect()
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Exact top `k` search for the signs whose embeddings are nearest to `query` under
+    /// `metric`, scanning all entries with the shards in parallel. Returns `(sign, score)`
+    /// pairs, nearest first. Entries whose embedding dim differs from the query and tombstoned
+    /// entries are skipped, as are NaN scores.
+    pub fn nearest(&self, query: &[f32], k: usize, metric: Metric) -> Vec<(u64, f32)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        self.inner
+            .inner
+            .par_iter()
+            .map(|shard| {
+                let shard = shard.read();
+                let mut top_k = TopK::new(k);
+                shard
+                    .linkedlist
+                    .iter()
+                    .filter(|x| x.embedding_dim() == query.len() && !x.is_tombstoned())
+                    .for_each(|entry| top_k.push(Candidate::new(metric, entry, query)));
+                top_k
+            })
+            .reduce(|| TopK::new(k), TopK::merge)
+            .into_sorted()
+    }
+
+    /// [`PersiaEmbeddingHolder::nearest`] for many queries, which scans the entries once and
+    /// scores every entry against all queries of its dim while it is in cache. The result at
+    /// position `i` holds the neighbors of `queries[i]`.
+    pub fn nearest_batch(
+        &self,
+        queries: &[&[f32]],
+        k: usize,
+        metric: Metric,
+    ) -> Vec<Vec<(u64, f32)>> {
+        if k == 0 {
+            return vec![Vec::new(); queries.len()];
+        }
+        let new_top_ks = || -> Vec<TopK> { queries.iter().map(|_| TopK::new(k)).collect() };
+        self.inner
+            .inner
+            .par_iter()
+            .map(|shard| {
+                let shard = shard.read();
+                let mut top_ks = new_top_ks();
+                shard
+                    .linkedlist
+                    .iter()
+                    .filter(|x| !x.is_tombstoned())
+                    .for_each(|entry| {
+                        queries
+                            .iter()
+                            .zip(top_ks.iter_mut())
+                            .filter(|(query, _)| query.len() == entry.embedding_dim())
+                            .for_each(|(query, top_k)| {
+                                top_k.push(Candidate::new(metric, entry, query))
+                            });
+                    });
+                top_ks
+            })
+            .reduce(new_top_ks, |lhs, rhs| {
+                lhs.into_iter()
+                    .zip(rhs.into_iter())
+                    .map(|(lhs, rhs)| lhs.merge(rhs))
+                    .collect()
+            })
+            .into_iter()
+            .map(TopK::into_sorted)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod nearest_tests {
+    use super::*;
+
+    fn holder_with_table() -> PersiaEmbeddingHolder {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        let table: [(u64, [f32; 2]); 5] = [
+            (1, [1.0, 0.0]),
+            (2, [0.0, 1.0]),
+            (3, [2.0, 2.0]),
+            (4, [-1.0, 0.0]),
+            (5, [0.6, 0.8]),
+        ];
+        table.iter().for_each(|(sign, emb)| {
+            let entry = HashMapEmbeddingEntry::from_emb(emb.to_vec(), *sign);
+            let _ = holder.shard(sign).write().insert(*sign, entry);
+        });
+        let other_dim = HashMapEmbeddingEntry::from_emb(vec![1.0; 3], 6);
+        let _ = holder.shard(&6).write().insert(6, other_dim);
+        holder
+    }
+
+    #[test]
+    fn test_nearest() {
+        let holder = holder_with_table();
+        let query = [1.0, 0.0];
+
+        // dot products 1, 0, 2, -1, 0.6
+        assert_eq!(
+            holder.nearest(&query, 3, Metric::Dot),
+            vec![(3, 2.0), (1, 1.0), (5, 0.6)]
+        );
+        // cosine similarities 1, 0, 0.707, -1, 0.6
+        let cosine = holder.nearest(&query, 2, Metric::Cosine);
+        assert_eq!(cosine.iter().map(|x| x.0).collect::<Vec<_>>(), vec![1, 3]);
+        assert!((cosine[1].1 - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        // distances 0, 1.414, 2.236, 2, 0.894
+        let l2 = holder.nearest(&query, 3, Metric::L2);
+        assert_eq!(l2.iter().map(|x| x.0).collect::<Vec<_>>(), vec![1, 5, 2]);
+        assert!((l2[1].1 - 0.8f32.sqrt()).abs() < 1e-6);
+
+        assert_eq!(holder.nearest(&query, 10, Metric::Dot).len(), 5);
+        assert!(holder.nearest(&query, 0, Metric::Dot).is_empty());
+    }
+
+    #[test]
+    fn test_nearest_batch() {
+        let holder = holder_with_table();
+        (10..40u64).for_each(|sign| {
+            let emb = vec![(sign as f32 * 0.37).sin(), (sign as f32 * 0.91).cos()];
+            let entry = HashMapEmbeddingEntry::from_emb(emb, sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        let queries: [&[f32]; 4] = [&[1.0, 0.0], &[-0.5, 0.25], &[0.0, 0.0, 1.0], &[3.0, -2.0]];
+
+        [Metric::Cosine, Metric::Dot, Metric::L2]
+            .iter()
+            .for_each(|metric| {
+                let batched = holder.nearest_batch(&queries, 4, *metric);
+                assert_eq!(batched.len(), queries.len());
+                queries
+                    .iter()
+                    .zip(batched.iter())
+                    .for_each(|(query, result)| {
+                        assert_eq!(result, &holder.nearest(query, 4, *metric));
+                    });
+            });
+        // only sign 6 has dim 3
+        assert_eq!(
+            holder.nearest_batch(&queries, 4, Metric::Dot)[2],
+            vec![(6, 1.0)]
+        );
+        assert!(holder
+            .nearest_batch(&queries, 0, Metric::Dot)
+            .iter()
+            .all(|x| x.is_empty()));
+    }
+}
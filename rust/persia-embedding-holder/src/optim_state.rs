@@ -0,0 +1,662 @@
+use persia_libs::thiserror;
+
+use persia_embedding_config::InitializationMethod;
+use persia_speedy::{Readable, Writable};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum OptimizerStateError {
+    #[error("optimizer region length {actual} does not match required length {required}")]
+    InvalidRegionSize { required: usize, actual: usize },
+    #[error("optimizer region length {actual} is smaller than required length {required}")]
+    RegionTooSmall { required: usize, actual: usize },
+    #[error("gradient length {actual} does not match embedding dim {expected}")]
+    GradientLengthMismatch { expected: usize, actual: usize },
+}
+
+fn check_region(opt: &[f32], required: usize) -> Result<(), OptimizerStateError> {
+    if opt.len() < required {
+        return Err(OptimizerStateError::RegionTooSmall {
+            required,
+            actual: opt.len(),
+        });
+    }
+    Ok(())
+}
+
+fn check_grad(emb: &[f32], grad: &[f32]) -> Result<(), OptimizerStateError> {
+    if emb.len() != grad.len() {
+        return Err(OptimizerStateError::GradientLengthMismatch {
+            expected: emb.len(),
+            actual: grad.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Common interface of the typed views over the optimizer region of an entry, so callers can
+/// build the state of whichever optimizer a table uses through a single code path.
+pub trait OptimizerState<'a>: Sized {
+    /// Length of the optimizer region the state needs for an embedding of `dim`.
+    fn required_space(dim: usize) -> usize;
+
+    fn new(emb: &'a mut [f32], opt: &'a mut [f32]) -> Result<Self, OptimizerStateError>;
+
+    fn emb(&mut self) -> &mut [f32];
+}
+
+/// Typed view over the Adam state in the optimizer region of an entry, laid out as the first
+/// moment `m`, the second moment `v` and the step counter.
+pub struct AdamState<'a> {
+    emb: &'a mut [f32],
+    opt: &'a mut [f32],
+}
+
+impl<'a> OptimizerState<'a> for AdamState<'a> {
+    fn required_space(dim: usize) -> usize {
+        2 * dim + 1
+    }
+
+    fn new(emb: &'a mut [f32], opt: &'a mut [f32]) -> Result<Self, OptimizerStateError> {
+        let required = Self::required_space(emb.len());
+        if opt.len() != required {
+            return Err(OptimizerStateError::InvalidRegionSize {
+                required,
+                actual: opt.len(),
+            });
+        }
+        Ok(Self { emb, opt })
+    }
+
+    fn emb(&mut self) -> &mut [f32] {
+        &mut self.emb[..]
+    }
+}
+
+impl<'a> AdamState<'a> {
+    pub fn m(&mut self) -> &mut [f32] {
+        let dim = self.emb.len();
+        &mut self.opt[..dim]
+    }
+
+    pub fn v(&mut self) -> &mut [f32] {
+        let dim = self.emb.len();
+        &mut self.opt[dim..2 * dim]
+    }
+
+    pub fn step(&mut self) -> &mut f32 {
+        let dim = self.emb.len();
+        &mut self.opt[2 * dim]
+    }
+
+    /// One bias corrected Adam step, the step counter is incremented before the update.
+    pub fn apply_adam(
+        &mut self,
+        grad: &[f32],
+        lr: f32,
+        beta1: f32,
+        beta2: f32,
+        eps: f32,
+    ) -> Result<(), OptimizerStateError> {
+        check_grad(&self.emb, grad)?;
+        let dim = self.emb.len();
+        let (moments, step) = self.opt.split_at_mut(2 * dim);
+        step[0] += 1.0;
+        let bias_correction1 = 1.0 - beta1.powf(step[0]);
+        let bias_correction2 = 1.0 - beta2.powf(step[0]);
+        let (m, v) = moments.split_at_mut(dim);
+        self.emb
+            .iter_mut()
+            .zip(m.iter_mut())
+            .zip(v.iter_mut())
+            .zip(grad.iter())
+            .for_each(|(((w, m), v), g)| {
+                *m = beta1 * *m + (1.0 - beta1) * g;
+                *v = beta2 * *v + (1.0 - beta2) * g * g;
+                let v_hat = *v / bias_correction2;
+                *w -= lr * (*m / bias_correction1) / (v_hat.sqrt() + eps);
+            });
+        Ok(())
+    }
+}
+
+/// Typed view over the Adagrad squared gradient accumulator, the first `dim` elements of the
+/// optimizer region.
+pub struct AdagradState<'a> {
+    emb: &'a mut [f32],
+    accumulator: &'a mut [f32],
+}
+
+impl<'a> OptimizerState<'a> for AdagradState<'a> {
+    fn required_space(dim: usize) -> usize {
+        dim
+    }
+
+    fn new(emb: &'a mut [f32], opt: &'a mut [f32]) -> Result<Self, OptimizerStateError> {
+        let dim = emb.len();
+        check_region(opt, Self::required_space(dim))?;
+        Ok(Self {
+            emb,
+            accumulator: &mut opt[..dim],
+        })
+    }
+
+    fn emb(&mut self) -> &mut [f32] {
+        &mut self.emb[..]
+    }
+}
+
+impl<'a> AdagradState<'a> {
+    pub fn accumulator(&mut self) -> &mut [f32] {
+        &mut self.accumulator[..]
+    }
+
+    pub fn apply_adagrad(
+        &mut self,
+        grad: &[f32],
+        lr: f32,
+        eps: f32,
+    ) -> Result<(), OptimizerStateError> {
+        check_grad(&self.emb, grad)?;
+        self.emb
+            .iter_mut()
+            .zip(self.accumulator.iter_mut())
+            .zip(grad.iter())
+            .for_each(|((w, acc), g)| {
+                *acc += g * g;
+                *w -= lr * g / (acc.sqrt() + eps);
+            });
+        Ok(())
+    }
+}
+
+/// Typed view over the SGD momentum velocity, the first `dim` elements of the optimizer region.
+pub struct MomentumState<'a> {
+    emb: &'a mut [f32],
+    velocity: &'a mut [f32],
+}
+
+impl<'a> OptimizerState<'a> for MomentumState<'a> {
+    fn required_space(dim: usize) -> usize {
+        dim
+    }
+
+    fn new(emb: &'a mut [f32], opt: &'a mut [f32]) -> Result<Self, OptimizerStateError> {
+        let dim = emb.len();
+        check_region(opt, Self::required_space(dim))?;
+        Ok(Self {
+            emb,
+            velocity: &mut opt[..dim],
+        })
+    }
+
+    fn emb(&mut self) -> &mut [f32] {
+        &mut self.emb[..]
+    }
+}
+
+impl<'a> MomentumState<'a> {
+    pub fn velocity(&mut self) -> &mut [f32] {
+        &mut self.velocity[..]
+    }
+
+    pub fn apply_momentum(
+        &mut self,
+        grad: &[f32],
+        lr: f32,
+        momentum: f32,
+        dampening: f32,
+    ) -> Result<(), OptimizerStateError> {
+        check_grad(&self.emb, grad)?;
+        self.emb
+            .iter_mut()
+            .zip(self.velocity.iter_mut())
+            .zip(grad.iter())
+            .for_each(|((w, v), g)| {
+                *v = momentum * *v + (1.0 - dampening) * g;
+                *w -= lr * *v;
+            });
+        Ok(())
+    }
+}
+
+/// Typed view over the RMSProp running average of squared gradients, the first `dim` elements
+/// of the optimizer region.
+pub struct RmsPropState<'a> {
+    emb: &'a mut [f32],
+    square_avg: &'a mut [f32],
+}
+
+impl<'a> OptimizerState<'a> for RmsPropState<'a> {
+    fn required_space(dim: usize) -> usize {
+        dim
+    }
+
+    fn new(emb: &'a mut [f32], opt: &'a mut [f32]) -> Result<Self, OptimizerStateError> {
+        let dim = emb.len();
+        check_region(opt, Self::required_space(dim))?;
+        Ok(Self {
+            emb,
+            square_avg: &mut opt[..dim],
+        })
+    }
+
+    fn emb(&mut self) -> &mut [f32] {
+        &mut self.emb[..]
+    }
+}
+
+impl<'a> RmsPropState<'a> {
+    pub fn square_avg(&mut self) -> &mut [f32] {
+        &mut self.square_avg[..]
+    }
+
+    pub fn apply_rmsprop(
+        &mut self,
+        grad: &[f32],
+        lr: f32,
+        alpha: f32,
+        eps: f32,
+    ) -> Result<(), OptimizerStateError> {
+        check_grad(&self.emb, grad)?;
+        self.emb
+            .iter_mut()
+            .zip(self.square_avg.iter_mut())
+            .zip(grad.iter())
+            .for_each(|((w, avg), g)| {
+                *avg = alpha * *avg + (1.0 - alpha) * g * g;
+                *w -= lr * g / (avg.sqrt() + eps);
+            });
+        Ok(())
+    }
+}
+
+/// Scales all gradients of a batch by the same factor so that their total L2 norm is at most
+/// `max_norm`, which keeps the direction of the batch update. Returns the total norm before
+/// clipping, gradients are left as is when it does not exceed `max_norm`.
+pub fn clip_grad_global_norm(grads: &mut [&mut [f32]], max_norm: f32) -> f32 {
+    let total_norm = grads
+        .iter()
+        .flat_map(|grad| grad.iter())
+        .map(|g| g * g)
+        .sum::<f32>()
+        .sqrt();
+    if total_norm > max_norm {
+        let factor = max_norm / total_norm;
+        grads
+            .iter_mut()
+            .for_each(|grad| grad.iter_mut().for_each(|g| *g *= factor));
+    }
+    total_norm
+}
+
+// Hyperparameters of the updates applied by `OptimizerKind::apply`, the defaults of the
+// corresponding PyTorch optimizers except for the momentum, which PyTorch defaults to zero.
+pub const DEFAULT_MOMENTUM: f32 = 0.9;
+pub const DEFAULT_ADAGRAD_EPS: f32 = 1e-10;
+pub const DEFAULT_RMSPROP_ALPHA: f32 = 0.99;
+pub const DEFAULT_RMSPROP_EPS: f32 = 1e-8;
+pub const DEFAULT_ADAM_BETAS: (f32, f32) = (0.9, 0.999);
+pub const DEFAULT_ADAM_EPS: f32 = 1e-8;
+
+#[derive(Clone, Copy, Readable, Writable, Debug, PartialEq)]
+pub enum OptimizerKind {
+    Sgd,
+    MomentumSgd,
+    Adagrad,
+    Adam,
+    RmsProp,
+}
+
+impl OptimizerKind {
+    /// Length of the per entry optimizer region for an embedding of `dim`.
+    pub fn opt_space(&self, dim: usize) -> usize {
+        match self {
+            OptimizerKind::Sgd => 0,
+            OptimizerKind::MomentumSgd => MomentumState::required_space(dim),
+            OptimizerKind::Adagrad => AdagradState::required_space(dim),
+            OptimizerKind::Adam => AdamState::required_space(dim),
+            OptimizerKind::RmsProp => RmsPropState::required_space(dim),
+        }
+    }
+
+    /// Length of the optimizer region with room for a gradient accumulator of `dim` values
+    /// after the optimizer state, see [`HashMapEmbeddingEntry::accumulate_gradient`].
+    pub fn opt_space_with_accumulator(&self, dim: usize) -> usize {
+        self.opt_space(dim) + dim
+    }
+
+    /// Checks that an optimizer region of `opt_len` holds the state for an embedding of `dim`,
+    /// without building the state.
+    pub fn check_space(&self, dim: usize, opt_len: usize) -> Result<(), OptimizerStateError> {
+        let required = self.opt_space(dim);
+        match self {
+            OptimizerKind::Adam if opt_len != required => {
+                Err(OptimizerStateError::InvalidRegionSize {
+                    required,
+                    actual: opt_len,
+                })
+            }
+            _ if opt_len < required => Err(OptimizerStateError::RegionTooSmall {
+                required,
+                actual: opt_len,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies one update of `grad` with learning rate `lr` to `entry`, using the `DEFAULT_*`
+    /// hyperparameters of the optimizer.
+    pub fn apply(
+        &self,
+        entry: &mut HashMapEmbeddingEntry,
+        grad: &[f32],
+        lr: f32,
+    ) -> Result<(), OptimizerStateError> {
+        match self {
+            OptimizerKind::Sgd => {
+                check_grad(entry.emb(), grad)?;
+                entry
+                    .emb_mut()
+                    .iter_mut()
+                    .zip(grad.iter())
+                    .for_each(|(w, g)| *w -= lr * g);
+                Ok(())
+            }
+            OptimizerKind::MomentumSgd => {
+                entry
+                    .momentum_state()?
+                    .apply_momentum(grad, lr, DEFAULT_MOMENTUM, 0.0)
+            }
+            OptimizerKind::Adagrad => {
+                entry
+                    .adagrad_state()?
+                    .apply_adagrad(grad, lr, DEFAULT_ADAGRAD_EPS)
+            }
+            OptimizerKind::Adam => {
+                let (beta1, beta2) = DEFAULT_ADAM_BETAS;
+                let mut state: AdamState = entry.optimizer_state()?;
+                state.apply_adam(grad, lr, beta1, beta2, DEFAULT_ADAM_EPS)
+            }
+            OptimizerKind::RmsProp => entry.rmsprop_state()?.apply_rmsprop(
+                grad,
+                lr,
+                DEFAULT_RMSPROP_ALPHA,
+                DEFAULT_RMSPROP_EPS,
+            ),
+        }
+    }
+}
+
+impl HashMapEmbeddingEntry {
+    pub fn new_for_optimizer(
+        initialization_method: &InitializationMethod,
+        dim: usize,
+        optimizer: OptimizerKind,
+        seed: u64,
+        sign: u64,
+    ) -> Self {
+        Self::new(
+            initialization_method,
+            dim,
+            optimizer.opt_space(dim),
+            seed,
+            sign,
+        )
+    }
+
+    pub fn optimizer_state<'a, S: OptimizerState<'a>>(
+        &'a mut self,
+    ) -> Result<S, OptimizerStateError> {
+        let (emb, opt) = self.emb_and_opt_mut();
+        S::new(emb, opt)
+    }
+
+    pub fn adam_state(&mut self) -> Option<AdamState> {
+        self.optimizer_state().ok()
+    }
+
+    pub fn adagrad_state(&mut self) -> Result<AdagradState, OptimizerStateError> {
+        self.optimizer_state()
+    }
+
+    pub fn momentum_state(&mut self) -> Result<MomentumState, OptimizerStateError> {
+        self.optimizer_state()
+    }
+
+    pub fn rmsprop_state(&mut self) -> Result<RmsPropState, OptimizerStateError> {
+        self.optimizer_state()
+    }
+
+    // The gradient accumulator is the last `dim` values of the optimizer region, so it does not
+    // overlap the states laid out from the start of the region.
+    fn accumulator_mut(&mut self) -> Result<&mut [f32], OptimizerStateError> {
+        let dim = self.dim();
+        let opt = self.opt_mut();
+        check_region(opt, dim)?;
+        let start = opt.len() - dim;
+        Ok(&mut opt[start..])
+    }
+
+    /// Adds the gradient of a micro batch to the accumulator of the entry, which needs the
+    /// optimizer region sized by [`OptimizerKind::opt_space_with_accumulator`]. The Adam view
+    /// requires the region to hold exactly its state, so it cannot be combined with an
+    /// accumulator.
+    pub fn accumulate_gradient(&mut self, grad: &[f32]) -> Result<(), OptimizerStateError> {
+        check_grad(self.emb(), grad)?;
+        self.accumulator_mut()?
+            .iter_mut()
+            .zip(grad.iter())
+            .for_each(|(x, g)| *x += g);
+        Ok(())
+    }
+
+    /// Returns the accumulated gradient and resets the accumulator to zero.
+    pub fn take_accumulated(&mut self) -> Result<Vec<f32>, OptimizerStateError> {
+        let accumulator = self.accumulator_mut()?;
+        let accumulated = accumulator.to_vec();
+        accumulator.iter_mut().for_each(|x| *x = 0.0);
+        Ok(accumulated)
+    }
+}
+
+#[cfg(test)]
+mod optim_state_tests {
+    use super::*;
+
+    #[test]
+    fn test_adam_state() {
+        let dim = 4;
+        let mut entry = HashMapEmbeddingEntry::new_empty(dim, AdamState::required_space(dim), 1);
+        {
+            let mut state = entry.adam_state().unwrap();
+            assert_eq!(state.m().len(), dim);
+            assert_eq!(state.v().len(), dim);
+            state.m().iter_mut().for_each(|x| *x = 1.0);
+            state.v().iter_mut().for_each(|x| *x = 2.0);
+            *state.step() = 3.0;
+        }
+
+        assert_eq!(entry.emb(), &[0.0; 4]);
+        assert_eq!(entry.opt(), &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0, 3.0]);
+
+        let mut small = HashMapEmbeddingEntry::new_empty(dim, 2 * dim, 1);
+        assert!(small.adam_state().is_none());
+    }
+
+    #[test]
+    fn test_adam_step() {
+        let mut entry = HashMapEmbeddingEntry::new_empty(2, AdamState::required_space(2), 1);
+        entry.emb_mut().copy_from_slice(&[1.0, -1.0]);
+        let mut state = entry.adam_state().unwrap();
+        state
+            .apply_adam(&[0.5, -2.0], 0.1, 0.9, 0.999, 0.0)
+            .unwrap();
+        assert_eq!(*state.step(), 1.0);
+        // the bias corrected first step moves every weight by lr against the gradient sign
+        entry
+            .emb()
+            .iter()
+            .zip([0.9, -0.9].iter())
+            .for_each(|(x, y)| assert!((x - y).abs() < 1e-5));
+
+        let mut entry = HashMapEmbeddingEntry::new_empty(2, AdamState::required_space(2), 1);
+        OptimizerKind::Adam
+            .apply(&mut entry, &[1.0, 1.0], 0.1)
+            .unwrap();
+        entry
+            .emb()
+            .iter()
+            .for_each(|x| assert!((x + 0.1).abs() < 1e-5));
+        assert!(OptimizerKind::Adam.check_space(2, 5).is_ok());
+        assert!(OptimizerKind::Adam.check_space(2, 6).is_err());
+        assert!(OptimizerKind::Adagrad.check_space(2, 6).is_ok());
+        assert!(OptimizerKind::Adagrad.check_space(2, 1).is_err());
+    }
+
+    #[test]
+    fn test_adagrad_step() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0, 2.0], &[0.25, 0.0], 1);
+        let mut state = entry.adagrad_state().unwrap();
+        state.apply_adagrad(&[0.5, -1.0], 0.1, 0.0).unwrap();
+        assert_eq!(state.accumulator(), &[0.5, 1.0]);
+
+        let expected = [1.0 - 0.1 * 0.5 / 0.5_f32.sqrt(), 2.0 + 0.1 * 1.0 / 1.0];
+        entry
+            .emb()
+            .iter()
+            .zip(expected.iter())
+            .for_each(|(x, y)| assert!((x - y).abs() < 1e-6));
+
+        let mut state = entry.adagrad_state().unwrap();
+        assert!(matches!(
+            state.apply_adagrad(&[0.5], 0.1, 0.0),
+            Err(OptimizerStateError::GradientLengthMismatch { .. })
+        ));
+        let mut no_opt = HashMapEmbeddingEntry::new_empty(2, 1, 1);
+        assert!(matches!(
+            no_opt.adagrad_state(),
+            Err(OptimizerStateError::RegionTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn test_zero_momentum_is_sgd() {
+        let emb = vec![0.3, -0.2, 1.5];
+        let grads = [[0.1, 0.2, -0.3], [0.5, -0.5, 0.25]];
+        let lr = 0.1;
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(emb.clone(), &[0.0; 3], 1);
+        let mut reference = emb;
+
+        grads.iter().for_each(|grad| {
+            let mut state = entry.momentum_state().unwrap();
+            state.apply_momentum(grad, lr, 0.0, 0.0).unwrap();
+            assert_eq!(state.velocity(), grad);
+            reference
+                .iter_mut()
+                .zip(grad.iter())
+                .for_each(|(w, g)| *w -= lr * g);
+        });
+        assert_eq!(entry.emb(), reference.as_slice());
+
+        let mut no_opt = HashMapEmbeddingEntry::new_empty(3, 0, 1);
+        assert!(matches!(
+            no_opt.momentum_state(),
+            Err(OptimizerStateError::RegionTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rmsprop_step() {
+        let mut entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0, -1.0], &[1.0, 0.0], 1);
+        let mut state: RmsPropState = entry.optimizer_state().unwrap();
+        state.apply_rmsprop(&[2.0, 1.0], 0.01, 0.5, 0.0).unwrap();
+        assert_eq!(state.square_avg(), &[2.5, 0.5]);
+
+        let expected = [
+            1.0 - 0.01 * 2.0 / 2.5_f32.sqrt(),
+            -1.0 - 0.01 / 0.5_f32.sqrt(),
+        ];
+        entry
+            .emb()
+            .iter()
+            .zip(expected.iter())
+            .for_each(|(x, y)| assert!((x - y).abs() < 1e-6));
+
+        let mut no_opt = HashMapEmbeddingEntry::new_empty(2, 1, 1);
+        assert!(no_opt.rmsprop_state().is_err());
+    }
+
+    #[test]
+    fn test_clip_grad_global_norm() {
+        let mut first = vec![3.0, 0.0];
+        let mut second = vec![0.0, 4.0, 0.0];
+        let mut third = vec![12.0];
+        {
+            let mut grads: Vec<&mut [f32]> = vec![&mut first, &mut second, &mut third];
+            assert_eq!(clip_grad_global_norm(&mut grads, 6.5), 13.0);
+        }
+        assert_eq!(first, vec![1.5, 0.0]);
+        assert_eq!(second, vec![0.0, 2.0, 0.0]);
+        assert_eq!(third, vec![6.0]);
+
+        // the clipped batch is within the threshold and left as is
+        let mut grads: Vec<&mut [f32]> = vec![&mut first, &mut second, &mut third];
+        assert!((clip_grad_global_norm(&mut grads, 6.5) - 6.5).abs() < 1e-6);
+        assert_eq!(grads[2], &[6.0]);
+        assert_eq!(clip_grad_global_norm(&mut [], 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_opt_space() {
+        let dim = 16;
+        assert_eq!(OptimizerKind::Sgd.opt_space(dim), 0);
+        assert_eq!(OptimizerKind::MomentumSgd.opt_space(dim), dim);
+        assert_eq!(OptimizerKind::Adagrad.opt_space(dim), dim);
+        assert_eq!(OptimizerKind::Adam.opt_space(dim), 2 * dim + 1);
+        assert_eq!(OptimizerKind::RmsProp.opt_space(dim), dim);
+
+        let initialization = InitializationMethod::default();
+        let mut entry = HashMapEmbeddingEntry::new_for_optimizer(
+            &initialization,
+            dim,
+            OptimizerKind::Adam,
+            1,
+            1,
+        );
+        assert_eq!(entry.dim(), dim);
+        assert_eq!(entry.opt().len(), 2 * dim + 1);
+        assert!(entry.adam_state().is_some());
+    }
+
+    #[test]
+    fn test_gradient_accumulation() {
+        let dim = 3;
+        let opt_space = OptimizerKind::Adagrad.opt_space_with_accumulator(dim);
+        assert_eq!(opt_space, 2 * dim);
+        let mut entry = HashMapEmbeddingEntry::new_empty(dim, opt_space, 1);
+
+        let micro_batches = [[1.0, 2.0, 3.0], [0.5, 0.5, 0.5], [-1.0, 0.0, 1.0]];
+        micro_batches
+            .iter()
+            .for_each(|grad| entry.accumulate_gradient(grad).unwrap());
+        // the adagrad state in front of the accumulator is untouched
+        assert_eq!(entry.adagrad_state().unwrap().accumulator(), &[0.0; 3]);
+        assert_eq!(entry.take_accumulated().unwrap(), vec![0.5, 2.5, 4.5]);
+        assert_eq!(entry.take_accumulated().unwrap(), vec![0.0; 3]);
+
+        assert!(matches!(
+            entry.accumulate_gradient(&[1.0]),
+            Err(OptimizerStateError::GradientLengthMismatch {
+                expected: 3,
+                actual: 1
+            })
+        ));
+        let mut small = HashMapEmbeddingEntry::new_empty(dim, 2, 1);
+        assert!(matches!(
+            small.accumulate_gradient(&[1.0; 3]),
+            Err(OptimizerStateError::RegionTooSmall { .. })
+        ));
+    }
+}
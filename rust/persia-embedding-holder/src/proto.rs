@@ -0,0 +1,273 @@
+//! Protobuf encoding of embedding entries for services that can not speak Speedy. The message
+//! is `persia.embedding.EmbeddingEntry` in `proto/embedding_entry.proto`, encoded by hand to
+//! avoid a codegen step for four fields. Speedy stays the format of checkpoints and RPCs.
+
+use persia_libs::thiserror;
+
+use persia_speedy::{Readable, Writable};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+const FIELD_SIGN: u64 = 1;
+const FIELD_EMBEDDING_DIM: u64 = 2;
+const FIELD_EMBEDDING: u64 = 3;
+const FIELD_OPTIMIZER_STATE: u64 = 4;
+
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum ProtoError {
+    #[error("message truncated")]
+    Truncated,
+    #[error("varint longer than 10 bytes")]
+    VarintOverflow,
+    #[error("field {field} has wire type {wire_type}")]
+    InvalidWireType { field: u64, wire_type: u8 },
+    #[error("embedding has {actual} values, expected embedding_dim {expected}")]
+    DimMismatch { expected: usize, actual: usize },
+}
+
+/// `persia.embedding.EmbeddingEntry` message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EmbeddingEntryProto {
+    pub sign: u64,
+    pub embedding_dim: u64,
+    pub embedding: Vec<f32>,
+    pub optimizer_state: Vec<f32>,
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_tag(buf: &mut Vec<u8>, field: u64, wire_type: u8) {
+    write_varint(buf, (field << 3) | wire_type as u64);
+}
+
+fn write_packed_floats(buf: &mut Vec<u8>, field: u64, values: &[f32]) {
+    // proto3 omits empty repeated fields
+    if values.is_empty() {
+        return;
+    }
+    write_tag(buf, field, WIRE_LEN);
+    write_varint(buf, (values.len() * 4) as u64);
+    values
+        .iter()
+        .for_each(|x| buf.extend_from_slice(&x.to_le_bytes()));
+}
+
+struct ProtoReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ProtoReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ProtoError> {
+        if self.buf.len() < len {
+            return Err(ProtoError::Truncated);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, ProtoError> {
+        let mut value = 0u64;
+        for shift in (0..70).step_by(7) {
+            let byte = self.read_bytes(1)?[0];
+            if shift == 63 && byte > 1 {
+                return Err(ProtoError::VarintOverflow);
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(ProtoError::VarintOverflow)
+    }
+
+    fn read_f32(&mut self) -> Result<f32, ProtoError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Repeated floats are packed by default but parsers must accept unpacked values too.
+    fn read_floats(
+        &mut self,
+        field: u64,
+        wire_type: u8,
+        values: &mut Vec<f32>,
+    ) -> Result<(), ProtoError> {
+        match wire_type {
+            WIRE_LEN => {
+                let len = self.read_varint()? as usize;
+                let bytes = self.read_bytes(len)?;
+                if len % 4 != 0 {
+                    return Err(ProtoError::Truncated);
+                }
+                values.extend(
+                    bytes
+                        .chunks(4)
+                        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]])),
+                );
+            }
+            WIRE_FIXED32 => values.push(self.read_f32()?),
+            _ => return Err(ProtoError::InvalidWireType { field, wire_type }),
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, field: u64, wire_type: u8) -> Result<(), ProtoError> {
+        match wire_type {
+            WIRE_VARINT => {
+                self.read_varint()?;
+            }
+            WIRE_FIXED64 => {
+                self.read_bytes(8)?;
+            }
+            WIRE_LEN => {
+                let len = self.read_varint()? as usize;
+                self.read_bytes(len)?;
+            }
+            WIRE_FIXED32 => {
+                self.read_bytes(4)?;
+            }
+            _ => return Err(ProtoError::InvalidWireType { field, wire_type }),
+        }
+        Ok(())
+    }
+}
+
+impl EmbeddingEntryProto {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf =
+            Vec::with_capacity(24 + 4 * (self.embedding.len() + self.optimizer_state.len()));
+        if self.sign != 0 {
+            write_tag(&mut buf, FIELD_SIGN, WIRE_VARINT);
+            write_varint(&mut buf, self.sign);
+        }
+        if self.embedding_dim != 0 {
+            write_tag(&mut buf, FIELD_EMBEDDING_DIM, WIRE_VARINT);
+            write_varint(&mut buf, self.embedding_dim);
+        }
+        write_packed_floats(&mut buf, FIELD_EMBEDDING, &self.embedding);
+        write_packed_floats(&mut buf, FIELD_OPTIMIZER_STATE, &self.optimizer_state);
+        buf
+    }
+
+    /// Decodes a message, skipping unknown fields.
+    pub fn decode(buf: &[u8]) -> Result<Self, ProtoError> {
+        let mut reader = ProtoReader { buf };
+        let mut message = Self::default();
+        while !reader.is_empty() {
+            let tag = reader.read_varint()?;
+            let (field, wire_type) = (tag >> 3, (tag & 0x7) as u8);
+            match (field, wire_type) {
+                (FIELD_SIGN, WIRE_VARINT) => message.sign = reader.read_varint()?,
+                (FIELD_EMBEDDING_DIM, WIRE_VARINT) => {
+                    message.embedding_dim = reader.read_varint()?
+                }
+                (FIELD_SIGN, _) | (FIELD_EMBEDDING_DIM, _) => {
+                    return Err(ProtoError::InvalidWireType { field, wire_type })
+                }
+                (FIELD_EMBEDDING, _) => {
+                    reader.read_floats(field, wire_type, &mut message.embedding)?
+                }
+                (FIELD_OPTIMIZER_STATE, _) => {
+                    reader.read_floats(field, wire_type, &mut message.optimizer_state)?
+                }
+                _ => reader.skip(field, wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl HashMapEmbeddingEntry {
+    pub fn to_proto(&self) -> EmbeddingEntryProto {
+        EmbeddingEntryProto {
+            sign: self.sign(),
+            embedding_dim: self.embedding_dim() as u64,
+            embedding: self.emb().to_vec(),
+            optimizer_state: self.opt().to_vec(),
+        }
+    }
+
+    pub fn from_proto(message: EmbeddingEntryProto) -> Result<Self, ProtoError> {
+        if message.embedding.len() as u64 != message.embedding_dim {
+            return Err(ProtoError::DimMismatch {
+                expected: message.embedding_dim as usize,
+                actual: message.embedding.len(),
+            });
+        }
+        Ok(Self::from_emb_and_opt(
+            message.embedding,
+            &message.optimizer_state,
+            message.sign,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod proto_tests {
+    use super::*;
+
+    #[test]
+    fn test_proto_round_trip() {
+        let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0, -2.5, 3.0], &[0.5], 300);
+        let bytes = entry.to_proto().encode_to_vec();
+        let decoded =
+            HashMapEmbeddingEntry::from_proto(EmbeddingEntryProto::decode(&bytes).unwrap())
+                .unwrap();
+        assert_eq!(decoded.sign(), 300);
+        assert_eq!(decoded.emb(), entry.emb());
+        assert_eq!(decoded.opt(), entry.opt());
+
+        let message = EmbeddingEntryProto {
+            embedding_dim: 4,
+            ..entry.to_proto()
+        };
+        assert!(matches!(
+            HashMapEmbeddingEntry::from_proto(message),
+            Err(ProtoError::DimMismatch {
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_proto_wire_format() {
+        // bytes of `EmbeddingEntry{sign: 300, embedding_dim: 2, embedding: [1.0, -2.0]}` as
+        // serialized by protoc generated code
+        let expected = [
+            0x08, 0xac, 0x02, 0x10, 0x02, 0x1a, 0x08, 0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00,
+            0xc0,
+        ];
+        let entry = HashMapEmbeddingEntry::from_emb(vec![1.0, -2.0], 300);
+        assert_eq!(entry.to_proto().encode_to_vec(), expected);
+
+        // unpacked floats and unknown fields are accepted
+        let unpacked = [
+            0x08, 0xac, 0x02, 0x10, 0x02, 0x1d, 0x00, 0x00, 0x80, 0x3f, 0x2a, 0x01, 0x07, 0x1d,
+            0x00, 0x00, 0x00, 0xc0,
+        ];
+        let message = EmbeddingEntryProto::decode(&unpacked).unwrap();
+        assert_eq!(message, entry.to_proto());
+
+        assert!(matches!(
+            EmbeddingEntryProto::decode(&expected[..9]),
+            Err(ProtoError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+use persia_libs::{hashbrown::HashSet, rayon::prelude::*};
+
+use crate::map_backend::HolderMapBackend;
+use crate::PersiaEmbeddingHolder;
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Removes the entries whose embedding L2 norm is below `threshold`, e.g. to shrink a
+    /// table before export, returns the number of removed entries.
+    pub fn prune_by_norm(&self, threshold: f32) -> usize {
+        self.inner
+            .inner
+            .par_iter()
+            .map(|x| x.write().retain(|entry| entry.l2_norm() >= threshold))
+            .sum()
+    }
+
+    /// Keeps only the `k` entries with the highest embedding L2 norms, ties are broken in favor
+    /// of smaller signs. Tombstoned entries neither count towards `k` nor are removed. Returns
+    /// the number of removed entries.
+    pub fn prune_to_top_k(&self, k: usize) -> usize {
+        let mut norms: Vec<(f32, u64)> = self
+            .inner
+            .inner
+            .par_iter()
+            .flat_map_iter(|x| {
+                let shard = x.read();
+                shard
+                    .linkedlist
+                    .iter()
+                    .filter(|entry| !entry.is_tombstoned())
+                    .map(|entry| (entry.l2_norm(), entry.sign()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if norms.len() <= k {
+            return 0;
+        }
+        norms.sort_unstable_by(|x, y| {
+            y.0.partial_cmp(&x.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(x.1.cmp(&y.1))
+        });
+        let kept: HashSet<u64> = norms.iter().take(k).map(|(_, sign)| *sign).collect();
+
+        self.inner
+            .inner
+            .par_iter()
+            .map(|x| {
+                x.write()
+                    .retain(|entry| entry.is_tombstoned() || kept.contains(&entry.sign()))
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod prune_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    fn holder_with_norms(norms: &[f32]) -> PersiaEmbeddingHolder {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        norms.iter().enumerate().for_each(|(sign, norm)| {
+            let sign = sign as u64;
+            let entry = HashMapEmbeddingEntry::from_emb(vec![*norm, 0.0], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        holder
+    }
+
+    fn signs(holder: &PersiaEmbeddingHolder) -> Vec<u64> {
+        let mut signs: Vec<u64> = holder.read_all().iter().map(|x| x.sign).collect();
+        signs.sort_unstable();
+        signs
+    }
+
+    #[test]
+    fn test_prune_by_norm() {
+        let holder = holder_with_norms(&[0.1, 2.0, 0.5, -3.0, 1.0, 0.0]);
+        assert_eq!(holder.prune_by_norm(1.0), 3);
+        assert_eq!(signs(&holder), vec![1, 3, 4]);
+        assert_eq!(holder.prune_by_norm(1.0), 0);
+    }
+
+    #[test]
+    fn test_prune_to_top_k() {
+        let holder = holder_with_norms(&[0.1, 2.0, 0.5, -3.0, 1.0, 2.0]);
+        assert_eq!(holder.prune_to_top_k(10), 0);
+        assert_eq!(holder.prune_to_top_k(3), 3);
+        assert_eq!(signs(&holder), vec![1, 3, 5]);
+        assert_eq!(holder.prune_to_top_k(2), 1);
+        assert_eq!(signs(&holder), vec![1, 3]);
+        assert_eq!(holder.prune_to_top_k(0), 2);
+        assert_eq!(holder.num_total_signs(), 0);
+
+        // sign 3 has the highest norm but is tombstoned
+        let holder = holder_with_norms(&[0.1, 2.0, 0.5, -3.0]);
+        holder.tombstone(3);
+        assert_eq!(holder.prune_to_top_k(2), 1);
+        assert_eq!(signs(&holder), vec![1, 2]);
+        assert_eq!(holder.num_total_signs(), 3);
+    }
+}
@@ -0,0 +1,154 @@
+use persia_libs::{hashbrown::HashMap, thiserror};
+
+use persia_speedy::{Readable, Writable};
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::slab_holder::SlabEmbeddingHolder;
+
+#[derive(Clone, Readable, Writable, thiserror::Error, Debug)]
+pub enum RaggedHolderError {
+    #[error("embedding table {0} not found")]
+    TableNotFound(u32),
+    #[error("embedding table {0} already exists")]
+    TableExists(u32),
+    #[error("entry of table {table_id} has dim {actual} and length {actual_len}, expected dim {expected} and length {expected_len}")]
+    DimMismatch {
+        table_id: u32,
+        expected: usize,
+        expected_len: usize,
+        actual: usize,
+        actual_len: usize,
+    },
+}
+
+/// Holder for embedding tables of different dims, keyed by `(table_id, sign)`. Each table
+/// declares its dim and optimizer space once and keeps its rows in its own fixed length slabs,
+/// so tables do not need padding to a common dim.
+pub struct RaggedEmbeddingHolder {
+    tables: HashMap<u32, SlabEmbeddingHolder>,
+    num_internal_shards: usize,
+}
+
+impl RaggedEmbeddingHolder {
+    pub fn new(num_internal_shards: usize) -> Self {
+        Self {
+            tables: HashMap::new(),
+            num_internal_shards,
+        }
+    }
+
+    pub fn add_table(
+        &mut self,
+        table_id: u32,
+        embedding_dim: usize,
+        require_space: usize,
+        capacity: usize,
+    ) -> Result<(), RaggedHolderError> {
+        if self.tables.contains_key(&table_id) {
+            return Err(RaggedHolderError::TableExists(table_id));
+        }
+        let table = SlabEmbeddingHolder::new(
+            capacity,
+            self.num_internal_shards,
+            embedding_dim,
+            require_space,
+        );
+        self.tables.insert(table_id, table);
+        Ok(())
+    }
+
+    pub fn table(&self, table_id: u32) -> Result<&SlabEmbeddingHolder, RaggedHolderError> {
+        self.tables
+            .get(&table_id)
+            .ok_or(RaggedHolderError::TableNotFound(table_id))
+    }
+
+    pub fn embedding_dim(&self, table_id: u32) -> Result<usize, RaggedHolderError> {
+        Ok(self.table(table_id)?.embedding_dim())
+    }
+
+    pub fn num_total_signs(&self) -> usize {
+        self.tables.values().map(|x| x.num_total_signs()).sum()
+    }
+
+    pub fn get(
+        &self,
+        table_id: u32,
+        sign: u64,
+    ) -> Result<Option<HashMapEmbeddingEntry>, RaggedHolderError> {
+        Ok(self.table(table_id)?.get_entry(&sign))
+    }
+
+    /// Stores `entry` in table `table_id`, returns the sign evicted from the table if any.
+    pub fn insert(
+        &self,
+        table_id: u32,
+        entry: &HashMapEmbeddingEntry,
+    ) -> Result<Option<u64>, RaggedHolderError> {
+        let table = self.table(table_id)?;
+        let mut shard = table.shard(&entry.sign()).write();
+        if entry.dim() != table.embedding_dim() || entry.inner_size() != shard.row_len() {
+            return Err(RaggedHolderError::DimMismatch {
+                table_id,
+                expected: table.embedding_dim(),
+                expected_len: shard.row_len(),
+                actual: entry.dim(),
+                actual_len: entry.inner_size(),
+            });
+        }
+        Ok(shard.insert_entry(entry))
+    }
+
+    pub fn clear(&self) {
+        self.tables.values().for_each(|x| x.clear());
+    }
+}
+
+#[cfg(test)]
+mod ragged_holder_tests {
+    use super::*;
+
+    #[test]
+    fn test_mixed_dims() {
+        let mut holder = RaggedEmbeddingHolder::new(4);
+        holder.add_table(0, 4, 0, 100).unwrap();
+        holder.add_table(1, 8, 8, 100).unwrap();
+        holder.add_table(2, 16, 1, 100).unwrap();
+        assert!(matches!(
+            holder.add_table(2, 32, 0, 100),
+            Err(RaggedHolderError::TableExists(2))
+        ));
+
+        let specs = [(0u32, 4usize, 0usize), (1, 8, 8), (2, 16, 1)];
+        specs.iter().for_each(|(table_id, dim, require_space)| {
+            (0..5u64).for_each(|sign| {
+                let entry = HashMapEmbeddingEntry::from_emb_and_opt(
+                    vec![*table_id as f32; *dim],
+                    &vec![0.5; *require_space],
+                    sign,
+                );
+                holder.insert(*table_id, &entry).unwrap();
+            });
+        });
+        assert_eq!(holder.num_total_signs(), 15);
+
+        // the same sign lives independently in every table
+        specs.iter().for_each(|(table_id, dim, require_space)| {
+            let entry = holder.get(*table_id, 3).unwrap().unwrap();
+            assert_eq!(entry.embedding_dim(), *dim);
+            assert_eq!(holder.embedding_dim(*table_id).unwrap(), *dim);
+            assert_eq!(entry.emb(), vec![*table_id as f32; *dim].as_slice());
+            assert_eq!(entry.opt().len(), *require_space);
+        });
+
+        let wrong_dim = HashMapEmbeddingEntry::from_emb(vec![0.0; 8], 1);
+        assert!(matches!(
+            holder.insert(0, &wrong_dim),
+            Err(RaggedHolderError::DimMismatch { .. })
+        ));
+        assert!(matches!(
+            holder.get(3, 1),
+            Err(RaggedHolderError::TableNotFound(3))
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use persia_libs::parking_lot::RwLock;
+
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::eviction_map::{EvictionMap, EvictionMapValue};
+use crate::sharded::Sharded;
+
+// Number of rows allocated at once when a slab runs out of free slots.
+const SLAB_PAGE_ROWS: usize = 4096;
+
+/// Location of a sign's row in the slab of its shard.
+#[derive(Clone, Debug)]
+pub struct SlabSlot {
+    sign: u64,
+    key: usize,
+}
+
+impl EvictionMapValue<u64> for SlabSlot {
+    fn hashmap_key(&self) -> u64 {
+        self.sign
+    }
+}
+
+/// Rows of a fixed length stored back to back in pages. Freed slots are reused by later
+/// inserts, pages are never moved or shrunk.
+struct RowSlab {
+    row_len: usize,
+    pages: Vec<Vec<f32>>,
+    free_keys: Vec<usize>,
+}
+
+impl RowSlab {
+    fn new(row_len: usize) -> Self {
+        Self {
+            row_len,
+            pages: Vec::new(),
+            free_keys: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> usize {
+        match self.free_keys.pop() {
+            Some(key) => key,
+            None => {
+                let first_key = self.pages.len() * SLAB_PAGE_ROWS;
+                self.pages.push(vec![0f32; self.row_len * SLAB_PAGE_ROWS]);
+                self.free_keys
+                    .extend((first_key + 1..first_key + SLAB_PAGE_ROWS).rev());
+                first_key
+            }
+        }
+    }
+
+    fn free(&mut self, key: usize) {
+        self.free_keys.push(key);
+    }
+
+    fn row(&self, key: usize) -> &[f32] {
+        let offset = (key % SLAB_PAGE_ROWS) * self.row_len;
+        &self.pages[key / SLAB_PAGE_ROWS][offset..offset + self.row_len]
+    }
+
+    fn row_mut(&mut self, key: usize) -> &mut [f32] {
+        let offset = (key % SLAB_PAGE_ROWS) * self.row_len;
+        &mut self.pages[key / SLAB_PAGE_ROWS][offset..offset + self.row_len]
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.free_keys.clear();
+    }
+}
+
+/// One shard of a [`SlabEmbeddingHolder`], mapping signs to slab slots with the same eviction
+/// policy as [`EvictionMap`]. Rows hold the embedding followed by the optimizer state.
+pub struct SlabShard {
+    slots: EvictionMap<u64, SlabSlot>,
+    slab: RowSlab,
+}
+
+impl SlabShard {
+    pub fn with_capacity(capacity: usize, row_len: usize) -> Self {
+        Self {
+            slots: EvictionMap::with_capacity(capacity),
+            slab: RowSlab::new(row_len),
+        }
+    }
+
+    pub fn row_len(&self) -> usize {
+        self.slab.row_len
+    }
+
+    pub fn get(&self, sign: &u64) -> Option<&[f32]> {
+        let key = self.slots.get(sign)?.key;
+        Some(self.slab.row(key))
+    }
+
+    pub fn get_mut(&mut self, sign: &u64) -> Option<&mut [f32]> {
+        let key = self.slots.get_mut(sign)?.key;
+        Some(self.slab.row_mut(key))
+    }
+
+    pub fn get_refresh_mut(&mut self, sign: &u64) -> Option<&mut [f32]> {
+        let key = self.slots.get_refresh_mut(sign)?.key;
+        Some(self.slab.row_mut(key))
+    }
+
+    /// Stores `row` for `sign`, returns the sign evicted to make room for it if any. The slot
+    /// of the evicted sign is freed for reuse.
+    pub fn insert(&mut self, sign: u64, row: &[f32]) -> Option<u64> {
+        assert_eq!(
+            row.len(),
+            self.slab.row_len,
+            "row length does not match slab row length"
+        );
+        let key = match self.slots.get(&sign) {
+            Some(slot) => slot.key,
+            None => self.slab.alloc(),
+        };
+        self.slab.row_mut(key).copy_from_slice(row);
+
+        let (_, evicted) = self.slots.insert(sign, SlabSlot { sign, key });
+        evicted.map(|slot| {
+            self.slab.free(slot.key);
+            slot.sign
+        })
+    }
+
+    pub fn insert_entry(&mut self, entry: &HashMapEmbeddingEntry) -> Option<u64> {
+        self.insert(entry.sign(), entry.as_emb_entry_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.slab.clear();
+    }
+}
+
+/// Embedding holder that keeps the rows of fixed length `row_len` in per shard slabs instead
+/// of one heap allocation per entry, implementing option 2 of the `HashMapEmbeddingEntry` TODO
+/// notes. Signs are sharded across the same way as in [`crate::PersiaEmbeddingHolder`].
+#[derive(Clone)]
+pub struct SlabEmbeddingHolder {
+    inner: Arc<Sharded<SlabShard, u64>>,
+    embedding_dim: usize,
+}
+
+impl SlabEmbeddingHolder {
+    pub fn new(
+        capacity: usize,
+        num_internal_shards: usize,
+        embedding_dim: usize,
+        require_space: usize,
+    ) -> Self {
+        let capacity_per_bucket = capacity / num_internal_shards;
+        let sharded = Sharded {
+            inner: (0..num_internal_shards)
+                .map(|_| {
+                    RwLock::new(SlabShard::with_capacity(
+                        capacity_per_bucket,
+                        embedding_dim + require_space,
+                    ))
+                })
+                .collect(),
+            phantom: std::marker::PhantomData::default(),
+        };
+        Self {
+            inner: Arc::new(sharded),
+            embedding_dim,
+        }
+    }
+
+    pub fn embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    pub fn num_total_signs(&self) -> usize {
+        self.inner.inner.iter().map(|x| x.read().len()).sum()
+    }
+
+    pub fn num_internal_shards(&self) -> usize {
+        self.inner.inner.len()
+    }
+
+    pub fn clear(&self) {
+        self.inner.inner.iter().for_each(|x| x.write().clear());
+    }
+
+    pub fn shard(&self, key: &u64) -> &RwLock<SlabShard> {
+        self.inner.shard(key)
+    }
+
+    pub fn get_shard_by_index(&self, idx: usize) -> &RwLock<SlabShard> {
+        self.inner.get_shard_by_index(idx)
+    }
+
+    /// Copies the row of `sign` out into an entry.
+    pub fn get_entry(&self, sign: &u64) -> Option<HashMapEmbeddingEntry> {
+        let shard = self.shard(sign).read();
+        shard.get(sign).map(|row| {
+            let (emb, opt) = row.split_at(self.embedding_dim);
+            HashMapEmbeddingEntry::from_emb_and_opt(emb.to_vec(), opt, *sign)
+        })
+    }
+}
+
+#[cfg(test)]
+mod slab_holder_tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_after_eviction() {
+        let mut shard = SlabShard::with_capacity(2, 3);
+        assert_eq!(shard.insert(1, &[1.0, 1.0, 1.0]), None);
+        assert_eq!(shard.insert(2, &[2.0, 2.0, 2.0]), None);
+        let key_of_first = shard.slots.get(&1).unwrap().key;
+
+        assert_eq!(shard.insert(3, &[3.0, 3.0, 3.0]), Some(1));
+        assert!(shard.get(&1).is_none());
+        assert_eq!(shard.get(&3).unwrap(), &[3.0, 3.0, 3.0]);
+        assert_eq!(shard.get(&2).unwrap(), &[2.0, 2.0, 2.0]);
+
+        // the slot freed by evicting sign 1 is reused by the next new sign
+        assert_eq!(shard.insert(4, &[4.0, 4.0, 4.0]), Some(2));
+        assert_eq!(shard.slots.get(&4).unwrap().key, key_of_first);
+        assert_eq!(shard.get(&4).unwrap(), &[4.0, 4.0, 4.0]);
+        assert_eq!(shard.get(&3).unwrap(), &[3.0, 3.0, 3.0]);
+        assert_eq!(shard.slab.pages.len(), 1);
+
+        // overwriting a sign keeps its slot
+        let key_of_third = shard.slots.get(&3).unwrap().key;
+        assert_eq!(shard.insert(3, &[5.0, 5.0, 5.0]), None);
+        assert_eq!(shard.slots.get(&3).unwrap().key, key_of_third);
+        assert_eq!(shard.get(&3).unwrap(), &[5.0, 5.0, 5.0]);
+        assert_eq!(shard.len(), 2);
+    }
+
+    #[test]
+    fn test_slab_holder() {
+        let holder = SlabEmbeddingHolder::new(100, 4, 2, 1);
+        (0..10u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![sign as f32; 2], &[0.5], sign);
+            holder.shard(&sign).write().insert_entry(&entry);
+        });
+        assert_eq!(holder.num_total_signs(), 10);
+
+        holder.shard(&7).write().get_mut(&7).unwrap()[0] = -1.0;
+        let entry = holder.get_entry(&7).unwrap();
+        assert_eq!(entry.emb(), &[-1.0, 7.0]);
+        assert_eq!(entry.opt(), &[0.5]);
+        assert!(holder.get_entry(&11).is_none());
+
+        holder.clear();
+        assert_eq!(holder.num_total_signs(), 0);
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt::Write;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::array_linked_list::LinkedListNode;
+use crate::emb_entry::HashMapEmbeddingEntry;
+use crate::map_backend::HolderMapBackend;
+use crate::{PersiaEmbeddingHolder, PersiaEmbeddingHolderError};
+
+/// Lookup and eviction counters of a holder. Counters are relaxed atomics updated once per
+/// shard and batch, they are meant for monitoring and not for synchronization.
+#[derive(Debug, Default)]
+pub(crate) struct HolderCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl HolderCounters {
+    pub(crate) fn record_lookups(&self, hits: u64, misses: u64) {
+        if hits > 0 {
+            self.hits.fetch_add(hits, Ordering::Relaxed);
+        }
+        if misses > 0 {
+            self.misses.fetch_add(misses, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_insertions(&self, insertions: u64, evictions: u64) {
+        if insertions > 0 {
+            self.insertions.fetch_add(insertions, Ordering::Relaxed);
+        }
+        if evictions > 0 {
+            self.evictions.fetch_add(evictions, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> HolderStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        HolderStats {
+            lookups: hits + misses,
+            hits,
+            misses,
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.insertions.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of the counters of a holder since its creation or the last
+/// [`PersiaEmbeddingHolder::reset_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HolderStats {
+    pub lookups: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+}
+
+impl HolderStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.lookups as f64
+        }
+    }
+
+    /// Encodes the counters in the prometheus text exposition format, with metric names starting
+    /// with `prefix`.
+    pub fn to_prometheus_text(&self, prefix: &str) -> String {
+        let counters = [
+            ("lookups", "signs looked up", self.lookups),
+            ("hits", "signs found", self.hits),
+            ("misses", "signs not found", self.misses),
+            ("insertions", "entries inserted", self.insertions),
+            ("evictions", "entries evicted", self.evictions),
+        ];
+        let mut text = String::new();
+        counters.iter().for_each(|(name, help, value)| {
+            let _ = writeln!(text, "# HELP {}_{}_total number of {}", prefix, name, help);
+            let _ = writeln!(text, "# TYPE {}_{}_total counter", prefix, name);
+            let _ = writeln!(text, "{}_{}_total {}", prefix, name, value);
+        });
+        text
+    }
+}
+
+/// Estimated memory usage of a holder, see [`PersiaEmbeddingHolder::memory_bytes`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Bytes of the embedding values.
+    pub embedding_bytes: usize,
+    /// Bytes of the optimizer states.
+    pub optimizer_bytes: usize,
+    /// Bytes of the shard maps, the entry structs and the spare capacity of entry buffers.
+    pub overhead_bytes: usize,
+}
+
+impl MemoryReport {
+    pub fn total_bytes(&self) -> usize {
+        self.embedding_bytes + self.optimizer_bytes + self.overhead_bytes
+    }
+}
+
+/// Distribution of one embedding dimension across the entries of a holder, see
+/// [`PersiaEmbeddingHolder::per_dim_stats`]. The standard deviation is the population one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DimStat {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+// Running min, max, mean and sum of squared deviations by Welford's algorithm.
+#[derive(Clone, Copy)]
+struct DimAccumulator {
+    min: f32,
+    max: f32,
+    mean: f64,
+    m2: f64,
+}
+
+impl DimAccumulator {
+    fn new() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    fn push(&mut self, x: f32, count: u64) {
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        let x = x as f64;
+        let delta = x - self.mean;
+        self.mean += delta / count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn finish(&self, count: u64) -> DimStat {
+        DimStat {
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            stddev: (self.m2 / count as f64).sqrt(),
+        }
+    }
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    /// Estimates the memory used by the holder for capacity planning. Map overhead is derived
+    /// from the allocated map capacities, allocator overhead is not included.
+    pub fn memory_bytes(&self) -> MemoryReport {
+        let mut report = MemoryReport::default();
+        self.inner.inner.iter().for_each(|shard| {
+            let shard = shard.read();
+            report.overhead_bytes += shard.hashmap.capacity() * (size_of::<(u64, u32)>() + 1)
+                + shard.linkedlist.capacity() as usize
+                    * size_of::<LinkedListNode<HashMapEmbeddingEntry>>();
+            shard.linkedlist.iter().for_each(|entry| {
+                let embedding_bytes = entry.emb().len() * size_of::<f32>();
+                let optimizer_bytes = entry.opt().len() * size_of::<f32>();
+                report.embedding_bytes += embedding_bytes;
+                report.optimizer_bytes += optimizer_bytes;
+                report.overhead_bytes += entry.heap_bytes() - embedding_bytes - optimizer_bytes;
+            });
+        });
+        report
+    }
+
+    /// Counters of the lookups and inserts done through the holder methods. Accesses that go
+    /// through the shards directly are not counted.
+    pub fn stats(&self) -> HolderStats {
+        self.counters.snapshot()
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+
+    /// Tallies the l2 norms of the embeddings into buckets delimited by the ascending
+    /// boundaries `buckets`. Bucket `i` counts norms in `[buckets[i - 1], buckets[i])`, the first
+    /// and the last of the `buckets.len() + 1` buckets are open ended.
+    pub fn norm_histogram(&self, buckets: &[f32]) -> Vec<u64> {
+        let mut histogram = vec![0; buckets.len() + 1];
+        self.inner.inner.iter().for_each(|shard| {
+            let shard = shard.read();
+            shard.linkedlist.iter().for_each(|entry| {
+                let norm = entry.l2_norm();
+                histogram[buckets.partition_point(|x| *x <= norm)] += 1;
+            });
+        });
+        histogram
+    }
+
+    /// Min, max, mean and standard deviation of every embedding dimension across the entries,
+    /// computed in one pass without copying the embeddings. Tombstoned entries are skipped, an
+    /// empty holder has no dimensions. Fails with [`PersiaEmbeddingHolderError::DimMismatch`]
+    /// if the entries do not share one embedding dim.
+    pub fn per_dim_stats(&self) -> Result<Vec<DimStat>, PersiaEmbeddingHolderError> {
+        let mut accumulators: Vec<DimAccumulator> = Vec::new();
+        let mut count = 0u64;
+        for shard in self.inner.inner.iter() {
+            let shard = shard.read();
+            for entry in shard.linkedlist.iter().filter(|x| !x.is_tombstoned()) {
+                if count == 0 {
+                    accumulators = vec![DimAccumulator::new(); entry.embedding_dim()];
+                } else if entry.embedding_dim() != accumulators.len() {
+                    return Err(PersiaEmbeddingHolderError::DimMismatch {
+                        sign: entry.sign(),
+                        expected: accumulators.len(),
+                        actual: entry.embedding_dim(),
+                    });
+                }
+                count += 1;
+                accumulators
+                    .iter_mut()
+                    .zip(entry.emb().iter())
+                    .for_each(|(acc, x)| acc.push(*x, count));
+            }
+        }
+        Ok(accumulators.iter().map(|x| x.finish(count)).collect())
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+    use persia_embedding_config::InitializationMethod;
+
+    #[test]
+    fn test_holder_stats() {
+        let holder = PersiaEmbeddingHolder::new(8, 1);
+        let initialization = InitializationMethod::default();
+
+        let signs: Vec<u64> = (0..10).collect();
+        holder.get_or_init_many(&signs, &initialization, 4, 0, 0);
+        assert_eq!(
+            holder.stats(),
+            HolderStats {
+                lookups: 10,
+                hits: 0,
+                misses: 10,
+                insertions: 10,
+                evictions: 2,
+            }
+        );
+
+        holder.reset_stats();
+        let found = holder.get_many(&[9, 8, 0, 1, 100]);
+        assert_eq!(found.iter().filter(|x| x.is_some()).count(), 2);
+        holder.get_or_init(9, &initialization, 4, 0, 0);
+        assert!(holder.get_entry(7).is_some());
+        let stats = holder.stats();
+        assert_eq!(stats.lookups, 7);
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.insertions, 0);
+        assert!((stats.hit_rate() - 4.0 / 7.0).abs() < 1e-9);
+
+        let text = stats.to_prometheus_text("persia_embedding_holder");
+        assert!(text.contains("# TYPE persia_embedding_holder_hits_total counter\n"));
+        assert!(text.contains("persia_embedding_holder_misses_total 3\n"));
+    }
+
+    #[test]
+    fn test_norm_histogram() {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        let embs = [
+            vec![0.0, 0.0],
+            vec![0.3, 0.4],
+            vec![1.2, 1.6],
+            vec![3.0, 4.0],
+            vec![6.0, 8.0],
+            vec![30.0, 40.0],
+        ];
+        embs.iter().enumerate().for_each(|(sign, emb)| {
+            let sign = sign as u64;
+            let entry = HashMapEmbeddingEntry::from_emb(emb.clone(), sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+
+        // norms are 0, 0.5, 2, 5, 10 and 50
+        let histogram = holder.norm_histogram(&[0.1, 1.0, 10.0]);
+        assert_eq!(histogram, vec![1, 1, 2, 2]);
+        assert_eq!(holder.norm_histogram(&[]), vec![6]);
+    }
+
+    #[test]
+    fn test_per_dim_stats() {
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        assert!(holder.per_dim_stats().unwrap().is_empty());
+
+        let embs = [[1.0, -2.0, 0.5], [3.0, -2.0, 1.5], [5.0, -2.0, -5.0]];
+        embs.iter().enumerate().for_each(|(sign, emb)| {
+            let sign = sign as u64;
+            let entry = HashMapEmbeddingEntry::from_emb(emb.to_vec(), sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        let stats = holder.per_dim_stats().unwrap();
+        assert_eq!(stats.len(), 3);
+        [3.0, -2.0, -1.0]
+            .iter()
+            .zip(stats.iter())
+            .for_each(|(mean, stat)| assert!((stat.mean - mean).abs() < 1e-9));
+        assert_eq!((stats[0].min, stats[0].max), (1.0, 5.0));
+        assert!((stats[0].stddev - (8.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert_eq!(stats[1].stddev, 0.0);
+
+        let ragged = HashMapEmbeddingEntry::from_emb(vec![1.0; 2], 9);
+        let _ = holder.shard(&9).write().insert(9, ragged);
+        assert!(matches!(
+            holder.per_dim_stats(),
+            Err(PersiaEmbeddingHolderError::DimMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_memory_bytes() {
+        let holder = PersiaEmbeddingHolder::new(1000, 4);
+        let empty = holder.memory_bytes();
+        assert_eq!(empty.embedding_bytes, 0);
+        assert_eq!(empty.optimizer_bytes, 0);
+        assert!(empty.overhead_bytes > 0);
+
+        (0..100u64).for_each(|sign| {
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(vec![1.0; 16], &[0.0; 8], sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        let report = holder.memory_bytes();
+        assert_eq!(report.embedding_bytes, 100 * 16 * 4);
+        assert_eq!(report.optimizer_bytes, 100 * 8 * 4);
+        assert!(report.overhead_bytes >= empty.overhead_bytes);
+        assert_eq!(
+            report.total_bytes(),
+            report.embedding_bytes + report.optimizer_bytes + report.overhead_bytes
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Write ahead log of embedding updates for point in time recovery. Every update is appended as
+//! the change it made to an entry, so that a holder restored from a checkpoint is brought up to
+//! date by adding the changes logged after the checkpoint, regardless of the optimizer.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::checkpoint::invalid_data;
+use crate::emb_entry::max_entry_len;
+use crate::map_backend::HolderMapBackend;
+use crate::optim_state::OptimizerKind;
+use crate::PersiaEmbeddingHolder;
+
+/// Version of the update log written by [`UpdateLog`].
+pub const UPDATE_LOG_VERSION: u16 = 1;
+const UPDATE_LOG_HEADER_LEN: u64 = 2;
+
+/// Change of one entry at one step, `delta` covers the embedding followed by the optimizer
+/// state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateRecord {
+    pub step: u64,
+    pub sign: u64,
+    pub delta: Vec<f32>,
+}
+
+/// Append only log of [`UpdateRecord`]s in a file. The file holds the version as a little endian
+/// u16, followed by one record per update of the step and the sign as u64, the number of values
+/// as u32 and the values as f32, all little endian. Records are written as they are appended,
+/// call [`UpdateLog::flush`] to make them durable.
+pub struct UpdateLog {
+    file: BufWriter<File>,
+    num_records: usize,
+}
+
+impl UpdateLog {
+    /// Creates the log at `path`, replacing an existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&UPDATE_LOG_VERSION.to_le_bytes())?;
+        Ok(Self {
+            file,
+            num_records: 0,
+        })
+    }
+
+    pub fn append(&mut self, step: u64, sign: u64, delta: &[f32]) -> io::Result<()> {
+        self.file.write_all(&step.to_le_bytes())?;
+        self.file.write_all(&sign.to_le_bytes())?;
+        self.file.write_all(&(delta.len() as u32).to_le_bytes())?;
+        let bytes: Vec<u8> = delta.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.file.write_all(&bytes)?;
+        self.num_records += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+
+    /// Number of records appended since the log was created or truncated.
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
+
+    /// Drops all records, e.g. once a checkpoint holding their updates has been written.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let file = self.file.get_mut();
+        file.set_len(UPDATE_LOG_HEADER_LEN)?;
+        file.seek(SeekFrom::End(0))?;
+        self.num_records = 0;
+        Ok(())
+    }
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads all records of a log written by [`UpdateLog`]. A record cut off, e.g. by a crash
+/// while appending, fails the read.
+pub fn read_update_log<R: Read>(r: &mut R) -> io::Result<Vec<UpdateRecord>> {
+    let mut version = [0u8; 2];
+    r.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if version != UPDATE_LOG_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported update log version {}, expected {}",
+            version, UPDATE_LOG_VERSION
+        )));
+    }
+
+    let mut records = Vec::new();
+    loop {
+        let mut step = [0u8; 8];
+        // the log ends at a record boundary
+        match r.read(&mut step[..1])? {
+            0 => return Ok(records),
+            _ => r.read_exact(&mut step[1..])?,
+        }
+        let step = u64::from_le_bytes(step);
+        let sign = read_u64(r)?;
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > max_entry_len() {
+            return Err(invalid_data(format!(
+                "record of sign {} has {} values, exceeding the max entry length {}",
+                sign,
+                len,
+                max_entry_len()
+            )));
+        }
+        let mut bytes = vec![0u8; len * 4];
+        r.read_exact(&mut bytes)?;
+        let delta = bytes
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+            .collect();
+        records.push(UpdateRecord { step, sign, delta });
+    }
+}
+
+/// Adds the changes logged in `r` to the entries of `holder`, e.g. a holder just loaded from
+/// the checkpoint the log was truncated at. Signs missing from the holder, tombstoned or frozen
+/// are skipped, entries created after the checkpoint are not in the log. Returns the number of
+/// replayed records. Replayed values can differ from the logged ones by float rounding.
+pub fn replay<R: Read, M: HolderMapBackend>(
+    holder: &PersiaEmbeddingHolder<M>,
+    r: &mut R,
+) -> io::Result<usize> {
+    let records = read_update_log(r)?;
+    let mut replayed = 0;
+    for record in records.iter() {
+        let mut shard = holder.shard(&record.sign).write();
+        let len = match shard.get(&record.sign) {
+            Some(entry) if !entry.is_tombstoned() && !entry.is_frozen() => {
+                entry.as_emb_entry_slice().len()
+            }
+            _ => continue,
+        };
+        if len != record.delta.len() {
+            return Err(invalid_data(format!(
+                "record of sign {} at step {} has {} values, the entry has {}",
+                record.sign,
+                record.step,
+                record.delta.len(),
+                len
+            )));
+        }
+        let (emb, opt) = shard.peek_mut(&record.sign).unwrap().emb_and_opt_mut();
+        emb.iter_mut()
+            .chain(opt.iter_mut())
+            .zip(record.delta.iter())
+            .for_each(|(x, d)| *x += d);
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+impl<M: HolderMapBackend> PersiaEmbeddingHolder<M> {
+    fn row_snapshot(&self, sign: u64) -> Option<Vec<f32>> {
+        self.shard(&sign)
+            .read()
+            .get(&sign)
+            .filter(|x| !x.is_tombstoned())
+            .map(|x| x.as_emb_entry_slice().to_vec())
+    }
+
+    /// [`PersiaEmbeddingHolder::apply_gradients`] which appends the change of every updated
+    /// entry to `log` as a record of `step`. Signs occurring more than once in the batch get one
+    /// record of their total change. Updates of shards applied before an error are logged too.
+    /// Concurrent updates of the same signs are attributed to this batch.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_gradients_logged(
+        &self,
+        signs: &[u64],
+        grads: &[f32],
+        dim: usize,
+        optimizer: &OptimizerKind,
+        lr: f32,
+        step: u64,
+        log: &mut UpdateLog,
+    ) -> io::Result<usize> {
+        let mut unique_signs = signs.to_vec();
+        unique_signs.sort_unstable();
+        unique_signs.dedup();
+        let before: Vec<_> = unique_signs
+            .iter()
+            .map(|sign| self.row_snapshot(*sign))
+            .collect();
+
+        let applied = self.apply_gradients(signs, grads, dim, optimizer, lr);
+
+        for (sign, before) in unique_signs.iter().zip(before.into_iter()) {
+            let (before, after) = match (before, self.row_snapshot(*sign)) {
+                (Some(before), Some(after)) if before.len() == after.len() => (before, after),
+                _ => continue,
+            };
+            let delta: Vec<f32> = after
+                .iter()
+                .zip(before.iter())
+                .map(|(x, y)| x - y)
+                .collect();
+            if delta.iter().any(|x| *x != 0.0) {
+                log.append(step, *sign, &delta)?;
+            }
+        }
+        applied.map_err(invalid_data)
+    }
+}
+
+#[cfg(test)]
+mod update_log_tests {
+    use super::*;
+    use crate::emb_entry::HashMapEmbeddingEntry;
+
+    #[test]
+    fn test_replay_after_checkpoint() {
+        let optimizer = OptimizerKind::Adagrad;
+        let dim = 3;
+        let holder = PersiaEmbeddingHolder::new(100, 4);
+        (0..6u64).for_each(|sign| {
+            let emb = (0..dim).map(|x| (sign + x as u64) as f32 * 0.1).collect();
+            let opt = vec![0.0; optimizer.opt_space(dim)];
+            let entry = HashMapEmbeddingEntry::from_emb_and_opt(emb, &opt, sign);
+            let _ = holder.shard(&sign).write().insert(sign, entry);
+        });
+        let grads_of = |signs: &[u64], step: u64| -> Vec<f32> {
+            (0..signs.len() * dim)
+                .map(|x| (x as f32 - 4.0) * 0.1 * step as f32)
+                .collect()
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("persia_update_log_test_{}.bin", std::process::id()));
+        let mut log = UpdateLog::create(&path).unwrap();
+        let signs = [0u64, 1, 2];
+        holder
+            .apply_gradients_logged(
+                &signs,
+                &grads_of(&signs, 1),
+                dim,
+                &optimizer,
+                0.1,
+                1,
+                &mut log,
+            )
+            .unwrap();
+        assert_eq!(log.num_records(), 3);
+
+        let mut checkpoint = Vec::new();
+        holder.dump_stream(&mut checkpoint).unwrap();
+        log.truncate().unwrap();
+        assert_eq!(log.num_records(), 0);
+
+        // sign 4 occurs twice and sign 9 is missing
+        let signs = [4u64, 1, 9, 4];
+        holder
+            .apply_gradients_logged(
+                &signs,
+                &grads_of(&signs, 2),
+                dim,
+                &optimizer,
+                0.1,
+                2,
+                &mut log,
+            )
+            .unwrap();
+        let signs = [5u64, 0];
+        holder
+            .apply_gradients_logged(
+                &signs,
+                &grads_of(&signs, 3),
+                dim,
+                &optimizer,
+                0.1,
+                3,
+                &mut log,
+            )
+            .unwrap();
+        log.flush().unwrap();
+
+        let records = read_update_log(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|x| x.step >= 2));
+
+        let restored = PersiaEmbeddingHolder::new(100, 4);
+        restored.load_stream(&mut checkpoint.as_slice()).unwrap();
+        let replayed = replay(&restored, &mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(replayed, 4);
+        (0..6u64).for_each(|sign| {
+            let expected = holder.get_entry(sign).unwrap();
+            let actual = restored.get_entry(sign).unwrap();
+            assert!(actual.approx_eq(&expected, 1e-6));
+            actual
+                .opt()
+                .iter()
+                .zip(expected.opt().iter())
+                .for_each(|(x, y)| assert!((x - y).abs() <= 1e-6));
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
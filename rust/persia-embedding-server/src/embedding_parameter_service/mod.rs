@@ -186,10 +186,11 @@ impl EmbeddingParameterServiceInner {
                 req.iter().for_each(|(sign, dim)| {
                         let conf = conf.as_ref().unwrap();
                         let mut shard = self.embedding.shard(sign).write();
-                        let e = shard.get_refresh(&sign);
+                        // erased signs are treated as missing, a new entry replaces the tombstone
+                        let e = shard.get_refresh(&sign).filter(|x| !x.is_tombstoned());
                         match e {
                             None => {
-                                if rand::thread_rng().gen_range(0f32..1f32) < conf.admit_probability {
+                                if shard.admit(sign) && rand::thread_rng().gen_range(0f32..1f32) < conf.admit_probability {
                                     let mut emb_entry = HashMapEmbeddingEntry::new(
                                         &conf.initialization_method,
                                         *dim,
@@ -231,7 +232,7 @@ impl EmbeddingParameterServiceInner {
             false => {
                 req.iter().for_each(|(sign, dim)| {
                     let shard = self.embedding.shard(sign).read();
-                    match shard.get(sign) {
+                    match shard.get(sign).filter(|x| !x.is_tombstoned()) {
                         Some(entry) => {
                             let entry_dim = entry.dim();
                             if entry_dim != *dim {
@@ -377,6 +378,12 @@ impl EmbeddingParameterServiceInner {
         tokio::task::block_in_place(|| {
             for (idx, sign) in signs.iter().enumerate() {
                 let mut shard = self.embedding.shard(sign).write();
+                if let Some(entry) = shard.get(sign).filter(|x| x.is_tombstoned()) {
+                    // erased after the lookup, its gradient is dropped
+                    remaining_gradients = &remaining_gradients[entry.dim()..];
+                    gradient_id_miss_count += 1;
+                    continue;
+                }
                 if let Some(entry) = shard.get_mut(sign) {
                     let entry_dim = entry.dim();
                     let (grad, r) = remaining_gradients.split_at(entry_dim);
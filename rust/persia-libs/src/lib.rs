@@ -28,3 +28,4 @@ pub use tokio;
 pub use tracing;
 pub use tracing_subscriber;
 pub use url;
+pub use zstd;
@@ -249,3 +249,27 @@ pub unsafe fn weight_bound(embedding: &mut [f32], weight_bound: f32) {
         *embedding_v = bounded_embedding_v;
     }
 }
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn dot_avx2(a: &[f32], b: &[f32]) -> f32 {
+    let length = a.len();
+    let end = (length / 8) * 8;
+    let a_ptr = a.as_ptr();
+    let b_ptr = b.as_ptr();
+    let mut sum_v = _mm256_setzero_ps();
+    for i in (0..end as isize).step_by(8) {
+        let a_v = _mm256_loadu_ps(a_ptr.offset(i));
+        let b_v = _mm256_loadu_ps(b_ptr.offset(i));
+        sum_v = _mm256_fmadd_ps(a_v, b_v, sum_v);
+    }
+
+    let mut lanes = [0.0_f32; 8];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), sum_v);
+    let tail: f32 = a
+        .iter()
+        .zip(b.iter())
+        .skip(end)
+        .map(|(a_v, b_v)| a_v * b_v)
+        .sum();
+    lanes.iter().sum::<f32>() + tail
+}
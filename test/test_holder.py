@@ -0,0 +1,13 @@
+from persia.prelude import EmbeddingHolder
+
+
+def test_embedding_holder_round_trip():
+    holder = EmbeddingHolder(100, 4)
+    holder.set_embedding(42, [1.0, 2.0, 3.0], [0.5])
+
+    assert holder.num_total_signs() == 1
+    assert holder.get_embedding(42) == [1.0, 2.0, 3.0]
+    assert holder.get_optimizer_state(42) == [0.5]
+
+    assert holder.get_embedding(43) is None
+    assert holder.get_optimizer_state(43) is None